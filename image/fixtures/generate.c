@@ -0,0 +1,131 @@
+/*
+ * Writes the png fixtures used by the decoder tests with libpng, so that bit packing, Adam7 interlacing and
+ * transparency chunks come from the reference encoder rather than from our own reading of the spec.
+ *
+ *     cc generate.c -lpng -o generate && ./generate
+ *
+ * Every image is 13x11 so that rows do not fill whole bytes at low bit depths and Adam7 passes end part way through a
+ * block. Sample values follow the formulas mirrored in src/image/decode.rs.
+ */
+
+#include <png.h>
+#include <stdio.h>
+#include <stdlib.h>
+
+#define WIDTH 13
+#define HEIGHT 11
+
+static int channels(int color_type) {
+    switch (color_type) {
+        case PNG_COLOR_TYPE_RGB: return 3;
+        case PNG_COLOR_TYPE_GRAY_ALPHA: return 2;
+        case PNG_COLOR_TYPE_RGB_ALPHA: return 4;
+        default: return 1;
+    }
+}
+
+static unsigned narrow(unsigned x, unsigned y, unsigned c, int depth) {
+    return (x + 3 * y + 7 * c) % (1u << depth);
+}
+
+static unsigned wide(unsigned x, unsigned y, unsigned c) {
+    return (x * 4099 + y * 8191 + c * 12345) % 65536;
+}
+
+static void write(const char *name, int color_type, int depth, int interlaced, png_color_16p trns_color, int trns_entries) {
+    FILE *file = fopen(name, "wb");
+    png_structp png = png_create_write_struct(PNG_LIBPNG_VER_STRING, NULL, NULL, NULL);
+    png_infop info = png_create_info_struct(png);
+    if (!file || !png || !info || setjmp(png_jmpbuf(png))) {
+        fprintf(stderr, "could not write %s\n", name);
+        exit(1);
+    }
+
+    png_init_io(png, file);
+    png_set_IHDR(png, info, WIDTH, HEIGHT, depth, color_type, interlaced ? PNG_INTERLACE_ADAM7 : PNG_INTERLACE_NONE,
+                 PNG_COMPRESSION_TYPE_DEFAULT, PNG_FILTER_TYPE_DEFAULT);
+
+    png_color palette[256];
+    png_byte alpha[256];
+    if (color_type == PNG_COLOR_TYPE_PALETTE) {
+        for (int i = 0; i < (1 << depth); i++) {
+            palette[i].red = (i * 37) % 256;
+            palette[i].green = (i * 91) % 256;
+            palette[i].blue = (i * 53) % 256;
+        }
+        png_set_PLTE(png, info, palette, 1 << depth);
+    }
+    for (int i = 0; i < trns_entries; i++) {
+        alpha[i] = (i * 97) % 256;
+    }
+    if (trns_entries > 0 || trns_color) {
+        png_set_tRNS(png, info, alpha, trns_entries, trns_color);
+    }
+
+    png_write_info(png, info);
+    // Rows are handed over one byte per sample below 8 bits for libpng to pack, and big endian at 16 bits.
+    if (depth < 8) {
+        png_set_packing(png);
+    }
+
+    int n = channels(color_type);
+    int bytes = depth == 16 ? 2 : 1;
+    png_bytep rows[HEIGHT];
+    for (unsigned y = 0; y < HEIGHT; y++) {
+        rows[y] = malloc(WIDTH * n * bytes);
+        for (unsigned x = 0; x < WIDTH; x++) {
+            for (unsigned c = 0; c < (unsigned)n; c++) {
+                png_bytep sample = rows[y] + (x * n + c) * bytes;
+                if (depth == 16) {
+                    unsigned v = wide(x, y, c);
+                    sample[0] = v >> 8;
+                    sample[1] = v & 0xff;
+                } else {
+                    sample[0] = narrow(x, y, c, depth);
+                }
+            }
+        }
+    }
+
+    // With interlacing on, libpng picks the pixels of each Adam7 pass out of the full rows.
+    png_write_image(png, rows);
+    png_write_end(png, NULL);
+
+    for (int y = 0; y < HEIGHT; y++) {
+        free(rows[y]);
+    }
+    png_destroy_write_struct(&png, &info);
+    fclose(file);
+}
+
+static int indexed_trns(int depth) {
+    return (1 << depth) / 2 > 1 ? (1 << depth) / 2 : 1;
+}
+
+int main(void) {
+    char name[64];
+    for (int depth = 1; depth <= 8; depth *= 2) {
+        snprintf(name, sizeof name, "indexed_%d.png", depth);
+        write(name, PNG_COLOR_TYPE_PALETTE, depth, 0, NULL, indexed_trns(depth));
+        snprintf(name, sizeof name, "gray_%d.png", depth);
+        write(name, PNG_COLOR_TYPE_GRAY, depth, 0, NULL, 0);
+    }
+
+    png_color_16 gray_key = { .gray = 5 };
+    png_color_16 rgb_key = { .red = 0, .green = 7, .blue = 14 };
+
+    write("gray_16.png", PNG_COLOR_TYPE_GRAY, 16, 0, NULL, 0);
+    write("gray_8_trns.png", PNG_COLOR_TYPE_GRAY, 8, 0, &gray_key, 0);
+    write("gray_alpha_8.png", PNG_COLOR_TYPE_GRAY_ALPHA, 8, 0, NULL, 0);
+    write("gray_alpha_16.png", PNG_COLOR_TYPE_GRAY_ALPHA, 16, 0, NULL, 0);
+    write("rgb_8_trns.png", PNG_COLOR_TYPE_RGB, 8, 0, &rgb_key, 0);
+    write("rgb_16.png", PNG_COLOR_TYPE_RGB, 16, 0, NULL, 0);
+    write("rgba_8.png", PNG_COLOR_TYPE_RGB_ALPHA, 8, 0, NULL, 0);
+    write("rgba_16.png", PNG_COLOR_TYPE_RGB_ALPHA, 16, 0, NULL, 0);
+
+    write("gray_1_interlaced.png", PNG_COLOR_TYPE_GRAY, 1, 1, NULL, 0);
+    write("indexed_4_interlaced.png", PNG_COLOR_TYPE_PALETTE, 4, 1, NULL, indexed_trns(4));
+    write("rgb_8_interlaced.png", PNG_COLOR_TYPE_RGB, 8, 1, NULL, 0);
+    write("rgba_16_interlaced.png", PNG_COLOR_TYPE_RGB_ALPHA, 16, 1, NULL, 0);
+    return 0;
+}
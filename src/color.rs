@@ -1,3 +1,4 @@
+use crate::error::Error;
 use std::fmt::Formatter;
 
 /// Color is represented using normalised floating points.
@@ -26,6 +27,16 @@ impl Color {
         let a = x[3] as f64  * (1.0 - factor) + y[3] as f64  * factor;
         Color([r.round() as u8, g.round() as u8, b.round() as u8, a.round() as u8])
     }
+
+    /// Formats the color as a hex string that `from_str` can parse.
+    /// The alpha component is only included when the color is not opaque.
+    pub fn to_hex(&self) -> String {
+        if self[3] == u8::MAX {
+            format!("#{:02x}{:02x}{:02x}", self[0], self[1], self[2])
+        } else {
+            format!("#{:02x}{:02x}{:02x}{:02x}", self[0], self[1], self[2], self[3])
+        }
+    }
 }
 
 impl From<[u8; 3]> for Color {
@@ -35,8 +46,10 @@ impl From<[u8; 3]> for Color {
 }
 
 impl std::str::FromStr for Color {
-    type Err = String;
+    type Err = Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::InvalidValue { expected: "color", value: s.to_string() };
+
         let mut transformed: &str = &s.trim().to_lowercase();
         if transformed.starts_with("0x") {
             transformed = transformed.strip_prefix("0x").unwrap()
@@ -45,22 +58,22 @@ impl std::str::FromStr for Color {
             transformed = transformed.strip_prefix("#").unwrap()
         }
         return if transformed.len() == 6 {
-            let as_number = u32::from_str_radix(transformed, 16).map_err(|e| e.to_string())?;
+            let as_number = u32::from_str_radix(transformed, 16).map_err(|_| invalid())?;
             let r = (((0b1111_1111 << 16) & as_number) >> 16) as u8;
             let g = (((0b1111_1111 << 8) & as_number) >> 8) as u8;
-            let b = (((0b1111_1111 << 0) & as_number) >> 0) as u8;
+            let b = (0b1111_1111 & as_number) as u8;
 
             Ok(Color([r, g, b, 255]))
         } else if transformed.len() == 8 {
-            let as_number = u32::from_str_radix(transformed, 16).map_err(|e| e.to_string())?;
+            let as_number = u32::from_str_radix(transformed, 16).map_err(|_| invalid())?;
             let r = (((0b1111_1111 << 24) & as_number) >> 24) as u8;
             let g = (((0b1111_1111 << 16) & as_number) >> 16) as u8;
             let b = (((0b1111_1111 << 8) & as_number) >> 8) as u8;
-            let a = (((0b1111_1111 << 0) & as_number) >> 0) as u8;
+            let a = (0b1111_1111 & as_number) as u8;
 
             Ok(Color([r, g, b, a]))
         } else {
-            Err(String::from("Invalid hex string length for color"))
+            Err(invalid())
         }
     }
 
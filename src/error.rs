@@ -0,0 +1,86 @@
+use crate::color::Color;
+use std::fmt::Formatter;
+use std::path::PathBuf;
+
+/// Everything that can go wrong while loading, generating or writing maps.
+/// Underlying causes are not part of the message and are reached through `source`.
+#[derive(Debug)]
+pub enum Error {
+    /// A file could not be opened, read or created.
+    Io { path: PathBuf, source: std::io::Error },
+    /// A file is not a valid png.
+    Decode { path: PathBuf, source: png::DecodingError },
+    /// A png could not be written.
+    Encode { path: PathBuf, source: png::EncodingError },
+    /// A png is valid but its contents cannot be used.
+    UnsupportedImage { path: PathBuf, reason: String },
+    /// A configuration or project is not valid json.
+    Json(json::Error),
+    /// A setting in a configuration or project is invalid.
+    /// `biome` is the key color of the biome the setting belongs to, if any.
+    Config {
+        key: String,
+        biome: Option<Color>,
+        reason: String,
+        source: Option<Box<Error>>,
+    },
+    /// A string could not be parsed into the expected kind of value.
+    InvalidValue { expected: &'static str, value: String },
+    /// A coordinate lies outside of an image or heightmap.
+    OutOfBounds { x: isize, y: isize, width: usize, height: usize },
+    /// Two images or heightmaps that must be the same size are not.
+    DimensionMismatch { expected: (usize, usize), found: (usize, usize) },
+    /// A buffer of values does not have one value per cell.
+    DataLength { expected: usize, found: usize },
+    /// No passable route could be found between two waypoints.
+    NoRoute { from: String, to: String },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    /// Shorthand for a configuration error without an underlying cause.
+    pub(crate) fn config(key: impl Into<String>, biome: Option<Color>, reason: impl Into<String>) -> Self {
+        Error::Config { key: key.into(), biome, reason: reason.into(), source: None }
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io { path, .. } => write!(f, "could not access {}", path.display()),
+            Error::Decode { path, .. } => write!(f, "could not decode {}", path.display()),
+            Error::Encode { path, .. } => write!(f, "could not encode {}", path.display()),
+            Error::UnsupportedImage { path, reason } => write!(f, "unsupported image {}: {}", path.display(), reason),
+            Error::Json(_) => write!(f, "invalid json"),
+            Error::Config { key, biome: Some(biome), reason, .. } => write!(f, "invalid {} for biome {}: {}", key, biome.to_hex(), reason),
+            Error::Config { key, biome: None, reason, .. } => write!(f, "invalid {}: {}", key, reason),
+            Error::InvalidValue { expected, value } => write!(f, "\"{}\" is not a valid {}", value, expected),
+            Error::OutOfBounds { x, y, width, height } => write!(f, "coordinate ({}, {}) is outside of {}x{}", x, y, width, height),
+            Error::DimensionMismatch { expected, found } => {
+                write!(f, "expected size {}x{} but found {}x{}", expected.0, expected.1, found.0, found.1)
+            }
+            Error::DataLength { expected, found } => write!(f, "expected {} values but found {}", expected, found),
+            Error::NoRoute { from, to } => write!(f, "no passable route from {} to {}", from, to),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            Error::Decode { source, .. } => Some(source),
+            Error::Encode { source, .. } => Some(source),
+            Error::Json(e) => Some(e),
+            Error::Config { source: Some(source), .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<json::Error> for Error {
+    fn from(e: json::Error) -> Self {
+        Error::Json(e)
+    }
+}
@@ -0,0 +1,122 @@
+use super::*;
+use crate::error::{Error, Result};
+
+/// Ways of combining the colors of one image with those of an image beneath it.
+#[derive(Copy, Clone, Debug, PartialEq, Default)]
+pub enum BlendMode {
+    /// The top color replaces the bottom color.
+    #[default]
+    Normal,
+    /// Darkens by multiplying the colors together.
+    Multiply,
+    /// Lightens by multiplying the inverse of the colors together.
+    Screen,
+    /// Multiplies dark areas and screens light areas of the bottom image.
+    Overlay,
+    /// Keeps the darkest of the two colors.
+    Darken,
+    /// Keeps the lightest of the two colors.
+    Lighten,
+    /// Adds the colors together, clamping at white.
+    Add,
+}
+
+impl BlendMode {
+    /// Blends a single normalised color channel.
+    fn channel(&self, bottom: f64, top: f64) -> f64 {
+        match self {
+            BlendMode::Normal => top,
+            BlendMode::Multiply => bottom * top,
+            BlendMode::Screen => bottom + top - bottom * top,
+            BlendMode::Overlay => {
+                if bottom <= 0.5 {
+                    2.0 * bottom * top
+                } else {
+                    1.0 - 2.0 * (1.0 - bottom) * (1.0 - top)
+                }
+            }
+            BlendMode::Darken => bottom.min(top),
+            BlendMode::Lighten => bottom.max(top),
+            BlendMode::Add => (bottom + top).min(1.0),
+        }
+    }
+
+    /// Composites `top` over `bottom`, with the top colors alpha scaled by `opacity`.
+    pub fn composite(&self, bottom: Color, top: Color, opacity: f64) -> Color {
+        let max = u8::MAX as f64;
+        let top_alpha = (top[3] as f64 / max) * opacity.clamp(0.0, 1.0);
+        let bottom_alpha = bottom[3] as f64 / max;
+
+        let alpha = top_alpha + bottom_alpha * (1.0 - top_alpha);
+        if alpha <= 0.0 {
+            return Color::from([0, 0, 0, 0]);
+        }
+
+        let mut result = [0u8; 4];
+        for i in 0..3 {
+            let b = bottom[i] as f64 / max;
+            let t = top[i] as f64 / max;
+            // Where the bottom is transparent the top color is shown unblended.
+            let t = (1.0 - bottom_alpha) * t + bottom_alpha * self.channel(b, t);
+            let c = (top_alpha * t + bottom_alpha * b * (1.0 - top_alpha)) / alpha;
+            result[i] = (c * max).round() as u8;
+        }
+        result[3] = (alpha * max).round() as u8;
+
+        return Color::from(result);
+    }
+}
+
+impl std::str::FromStr for BlendMode {
+    type Err = Error;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "normal" => Ok(BlendMode::Normal),
+            "multiply" => Ok(BlendMode::Multiply),
+            "screen" => Ok(BlendMode::Screen),
+            "overlay" => Ok(BlendMode::Overlay),
+            "darken" => Ok(BlendMode::Darken),
+            "lighten" => Ok(BlendMode::Lighten),
+            "add" => Ok(BlendMode::Add),
+            _ => Err(Error::InvalidValue { expected: "blend mode", value: s.to_string() }),
+        }
+    }
+}
+
+impl Image {
+    /// Composites another image of the same size on top of this one using the given blend mode and opacity.
+    pub fn blend(&mut self, top: &Image, mode: BlendMode, opacity: f64) -> Result<()> {
+        if self.width != top.width || self.height != top.height {
+            return Err(Error::DimensionMismatch { expected: (self.width, self.height), found: (top.width, top.height) });
+        }
+
+        for (bottom, top) in self.data.iter_mut().zip(top.data.iter()) {
+            *bottom = mode.composite(*bottom, *top, opacity);
+        }
+
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn blend_modes() {
+        let grey = Color::from([128u8, 128, 128]);
+        let white = Color::from([255u8, 255, 255]);
+        let black = Color::from([0u8, 0, 0]);
+        let clear = Color::from([0u8, 0, 0, 0]);
+
+        assert_eq!(BlendMode::Normal.composite(grey, white, 1.0), white);
+        assert_eq!(BlendMode::Normal.composite(black, white, 0.5), Color::from([128u8, 128, 128]));
+        assert_eq!(BlendMode::Multiply.composite(grey, white, 1.0), grey);
+        assert_eq!(BlendMode::Multiply.composite(grey, black, 1.0), black);
+        assert_eq!(BlendMode::Screen.composite(grey, black, 1.0), grey);
+        assert_eq!(BlendMode::Add.composite(grey, grey, 1.0), white);
+        assert_eq!(BlendMode::Multiply.composite(clear, grey, 1.0), grey);
+        assert_eq!(BlendMode::Multiply.composite(grey, clear, 1.0), grey);
+        assert_eq!(BlendMode::Normal.composite(clear, clear, 1.0), clear);
+    }
+}
@@ -1,181 +1,293 @@
 use super::*;
-use png::{BitDepth, ColorType, Decoder};
+use png::{ColorType, Decoder, Transformations};
+use crate::error::{Error, Result};
 
-pub fn load_png(file: impl AsRef<Path>) -> Result<Image, String> {
-    let decoder = {
-        let file = File::open(file).map_err(|e| e.to_string())?;
+/// The unpacked samples of a png before they are converted into colors.
+pub(crate) struct Samples {
+    pub path: PathBuf,
+    pub width: usize,
+    pub height: usize,
+    pub color_type: ColorType,
+    /// Bits per sample, one of 1, 2, 4, 8 or 16.
+    pub bit_depth: u8,
+    /// One value per channel per pixel, in row order, at the images own bit depth.
+    pub data: Vec<u16>,
+    /// RGB triples for indexed images.
+    pub palette: Option<Vec<u8>>,
+    /// Contents of the tRNS chunk. Alpha per palette entry for indexed images,
+    /// otherwise the sample values of the single transparent color.
+    pub trns: Option<Vec<u8>>,
+}
+
+/// Reads and unpacks every sample of a png file.
+/// Adam7 interlaced images are deinterlaced by the png crate, everything else is left untransformed.
+pub(crate) fn read_samples(file: impl AsRef<Path>) -> Result<Samples> {
+    let path = file.as_ref().to_path_buf();
+    let decode_error = |source| Error::Decode { path: path.clone(), source };
+
+    let mut decoder = {
+        let file = File::open(&path).map_err(|source| Error::Io { path: path.clone(), source })?;
         Decoder::new(file)
     };
+    decoder.set_transformations(Transformations::IDENTITY);
 
-    let (info, mut reader) = decoder.read_info().map_err(|e| e.to_string())?;
+    let (info, mut reader) = decoder.read_info().map_err(decode_error)?;
 
-    let bit_depth = match info.bit_depth {
-        BitDepth::One => 1,
-        BitDepth::Two => 2,
-        BitDepth::Four => 4,
-        BitDepth::Eight => 8,
-        BitDepth::Sixteen => 16,
-    };
+    let mut raw = vec![0u8; reader.output_buffer_size()];
+    reader.next_frame(&mut raw[..]).map_err(decode_error)?;
 
-    let mut data = {
-        let mut data = vec![0u8; reader.output_buffer_size()];
-        reader
-            .next_frame(&mut data[..])
-            .map_err(|e| e.to_string())?;
-        std::io::Cursor::new(data)
-    };
+    let width = info.width as usize;
+    let height = info.height as usize;
+    let bit_depth = info.bit_depth as u8;
+    let channels = info.color_type.samples();
 
-    let mut pixels = Vec::with_capacity(info.width as usize * info.height as usize);
+    let mut data = Vec::with_capacity(width * height * channels);
+    // Rows are padded to whole bytes so they must be unpacked one at a time.
+    for row in raw.chunks(info.line_size).take(height) {
+        let row_samples = width * channels;
 
-    loop {
-        match (info.color_type, bit_depth) {
-            (ColorType::Indexed, 1) => {
-                unimplemented!()
+        match bit_depth {
+            16 => data.extend(row.chunks(2).take(row_samples).map(|b| u16::from_be_bytes([b[0], b[1]]))),
+            8 => data.extend(row.iter().take(row_samples).map(|b| *b as u16)),
+            _ => {
+                let per_byte = (8 / bit_depth) as usize;
+                let mask = (1u8 << bit_depth) - 1;
+                data.extend((0..row_samples).map(|i| {
+                    let shift = 8 - bit_depth * (i % per_byte + 1) as u8;
+                    ((row[i / per_byte] >> shift) & mask) as u16
+                }));
             }
+        }
+    }
 
-            (ColorType::Indexed, 2) => {
-                unimplemented!()
-            }
+    if data.len() != width * height * channels {
+        return Err(Error::UnsupportedImage { path, reason: String::from("image data is shorter than the image dimensions") });
+    }
 
-            (ColorType::Indexed, 4) => {
-                unimplemented!()
-            }
+    let info = reader.info();
+    return Ok(Samples {
+        path,
+        width,
+        height,
+        color_type: info.color_type,
+        bit_depth,
+        data,
+        palette: info.palette.clone(),
+        trns: info.trns.clone(),
+    });
+}
 
-            (ColorType::Indexed, 8) => {
-                unimplemented!()
-            }
+impl Samples {
+    /// Number of samples making up each pixel.
+    pub fn channels(&self) -> usize {
+        self.color_type.samples()
+    }
 
-            (ColorType::Grayscale, 1) => {
-                let mut byte = [0u8];
-                data.read(&mut byte[..]).unwrap();
+    /// Scales a sample to the full 16 bit range.
+    pub fn to_u16(&self, v: u16) -> u16 {
+        match self.bit_depth {
+            16 => v,
+            d => (v as u32 * u16::MAX as u32 / ((1u32 << d) - 1)) as u16,
+        }
+    }
 
-                for i in 7..=0 {
-                    if (byte[0] & (0b1 << i)) > 0 {
-                        pixels.push(Color::from([255u8, 255, 255]));
-                    } else {
-                        pixels.push(Color::from([0u8, 0, 0]));
-                    }
+    /// Scales a sample to the 8 bit range.
+    pub fn to_u8(&self, v: u16) -> u8 {
+        match self.bit_depth {
+            16 => (v >> 8) as u8,
+            d => (v as u32 * u8::MAX as u32 / ((1u32 << d) - 1)) as u8,
+        }
+    }
 
-                    if pixels.len() == pixels.capacity() {
-                        break;
-                    }
-                }
-            }
+    /// Whether a grayscale or RGB pixel matches the transparent color given in the tRNS chunk.
+    fn is_transparent(&self, pixel: &[u16]) -> bool {
+        let trns = match &self.trns {
+            Some(trns) => trns,
+            None => return false,
+        };
 
-            (ColorType::Grayscale, 2) => {
-                let mut byte = [0u8];
-                data.read(&mut byte[..]).unwrap();
+        // The png crate keeps two big endian bytes per channel for 16 bit images and one otherwise.
+        let transparent: Vec<u16> = if self.bit_depth == 16 {
+            trns.chunks(2).filter(|b| b.len() == 2).map(|b| u16::from_be_bytes([b[0], b[1]])).collect()
+        } else {
+            trns.iter().map(|b| *b as u16).collect()
+        };
 
-                for i in 3..=0 {
-                    let val = (byte[0] & (0b11 << 2 * i)) >> 2 * i;
-                    let val = val * (u8::MAX / 4);
+        return transparent.len() == pixel.len() && transparent.iter().zip(pixel).all(|(t, v)| t == v);
+    }
 
-                    pixels.push(Color::from([val, val, val]));
+    /// Converts the samples of one pixel into a color.
+    fn color(&self, pixel: &[u16]) -> Result<Color> {
+        let unsupported = |reason| Error::UnsupportedImage { path: self.path.clone(), reason };
 
-                    if pixels.len() == pixels.capacity() {
-                        break;
-                    }
-                }
+        let alpha = if self.is_transparent(pixel) { 0 } else { u8::MAX };
+
+        return match self.color_type {
+            ColorType::Grayscale => {
+                let g = self.to_u8(pixel[0]);
+                Ok(Color::from([g, g, g, alpha]))
+            }
+
+            ColorType::GrayscaleAlpha => {
+                let g = self.to_u8(pixel[0]);
+                Ok(Color::from([g, g, g, self.to_u8(pixel[1])]))
             }
 
-            (ColorType::Grayscale, 4) => {
-                let mut byte = [0u8];
-                data.read(&mut byte[..]).unwrap();
+            ColorType::RGB => Ok(Color::from([self.to_u8(pixel[0]), self.to_u8(pixel[1]), self.to_u8(pixel[2]), alpha])),
+
+            ColorType::RGBA => Ok(Color::from([
+                self.to_u8(pixel[0]),
+                self.to_u8(pixel[1]),
+                self.to_u8(pixel[2]),
+                self.to_u8(pixel[3]),
+            ])),
 
-                for i in 1..=0 {
-                    let val = (byte[0] & (0b1111 << 4 * i)) >> 4 * i;
-                    let val = val * (u8::MAX / 16);
-                    pixels.push(Color::from([val, val, val]));
+            ColorType::Indexed => {
+                let index = pixel[0] as usize;
+                let palette = match &self.palette {
+                    Some(p) => p,
+                    None => return Err(unsupported(String::from("indexed image has no palette"))),
+                };
 
-                    if pixels.len() == pixels.capacity() {
-                        break;
-                    }
+                if 3 * index + 2 >= palette.len() {
+                    return Err(unsupported(format!("palette index {} is out of range", index)));
                 }
-            }
 
-            (ColorType::Grayscale, 8) => {
-                let mut byte = [0u8; 1];
-                data.read(&mut byte[..]).unwrap();
-                let val = byte[0];
-                pixels.push(Color::from([val, val, val]));
-            }
+                // Entries without a tRNS value are fully opaque.
+                let alpha = match &self.trns {
+                    Some(trns) => *trns.get(index).unwrap_or(&u8::MAX),
+                    None => u8::MAX,
+                };
 
-            (ColorType::Grayscale, 16) => {
-                let mut byte = [0u8; 2];
-                data.read(&mut byte[..]).unwrap();
-                let val = u16::from_le_bytes(byte);
-                let val = (val >> 8) as u8;
-                pixels.push(Color::from([val, val, val]));
+                Ok(Color::from([palette[3 * index], palette[3 * index + 1], palette[3 * index + 2], alpha]))
             }
+        };
+    }
+}
 
-            (ColorType::GrayscaleAlpha, 8) => {
-                let mut byte = [0u8; 2];
-                data.read(&mut byte[..]).unwrap();
-                let g = byte[0];
-                let a = byte[1];
-                pixels.push(Color::from([g, g, g, a]));
-            }
+pub fn load_png(file: impl AsRef<Path>) -> Result<Image> {
+    let samples = read_samples(file)?;
 
-            (ColorType::GrayscaleAlpha, 16) => {
-                let mut byte = [0u8; 4];
-                data.read(&mut byte[..]).unwrap();
-                let byte = unsafe { std::mem::transmute::<_, [u16; 2]>(byte) };
-                let g = (byte[0] >> 8) as u8;
-                let a = (byte[1] >> 8) as u8;
-                pixels.push(Color::from([g, g, g, a]));
-            }
+    let pixels = samples
+        .data
+        .chunks(samples.channels())
+        .map(|pixel| samples.color(pixel))
+        .collect::<Result<Vec<_>>>()?;
 
-            (ColorType::RGB, 8) => {
-                let mut byte = [0u8; 3];
-                data.read(&mut byte[..]).unwrap();
-                let r = byte[0];
-                let g = byte[1];
-                let b = byte[2];
-                pixels.push(Color::from([r, g, b]));
-            }
+    Ok(Image {
+        width: samples.width,
+        height: samples.height,
+        data: pixels,
+    })
+}
 
-            (ColorType::RGB, 16) => {
-                let mut byte = [0u8; 6];
-                data.read(&mut byte[..]).unwrap();
-                let byte = unsafe { std::mem::transmute::<_, [u16; 3]>(byte) };
-                let r = (byte[0] >> 8) as u8;
-                let g = (byte[1] >> 8) as u8;
-                let b = (byte[2] >> 8) as u8;
-                pixels.push(Color::from([r, g, b]));
-            }
+#[cfg(test)]
+mod test {
+    use super::*;
 
-            (ColorType::RGBA, 8) => {
-                let mut byte = [0u8; 4];
-                data.read(&mut byte[..]).unwrap();
-                let r = byte[0];
-                let g = byte[1];
-                let b = byte[2];
-                let a = byte[3];
-                pixels.push(Color::from([r, g, b, a]));
-            }
+    const WIDTH: usize = 13;
+    const HEIGHT: usize = 11;
+
+    // Mirrors of the sample formulas in image/fixtures/generate.c
+    fn narrow(x: usize, y: usize, c: usize, depth: u32) -> u16 {
+        ((x + 3 * y + 7 * c) % (1 << depth)) as u16
+    }
+
+    fn wide(x: usize, y: usize, c: usize) -> u16 {
+        ((x * 4099 + y * 8191 + c * 12345) % 65536) as u16
+    }
+
+    fn palette_color(i: usize, alpha_entries: usize) -> Color {
+        let alpha = if i < alpha_entries { ((i * 97) % 256) as u8 } else { 255 };
+        Color::from([((i * 37) % 256) as u8, ((i * 91) % 256) as u8, ((i * 53) % 256) as u8, alpha])
+    }
+
+    fn check(file: &str, expected: impl Fn(usize, usize) -> Color) {
+        let image = load_png(format!("image/fixtures/{}", file)).unwrap();
+        assert_eq!((image.width(), image.height()), (WIDTH, HEIGHT), "{}", file);
 
-            (ColorType::RGBA, 16) => {
-                let mut byte = [0u8; 8];
-                data.read(&mut byte[..]).unwrap();
-                let byte = unsafe { std::mem::transmute::<_, [u16; 4]>(byte) };
-                let r = (byte[0] >> 8) as u8;
-                let g = (byte[1] >> 8) as u8;
-                let b = (byte[2] >> 8) as u8;
-                let a = (byte[3] >> 8) as u8;
-                pixels.push(Color::from([r, g, b, a]));
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                assert_eq!(image.pixel_at(x, y).unwrap(), expected(x, y), "{} at ({}, {})", file, x, y);
             }
+        }
+    }
 
-            _ => unreachable!(),
+    #[test]
+    fn indexed() {
+        for depth in [1, 2, 4, 8] {
+            let alpha_entries = usize::max(1, (1 << depth) / 2);
+            check(&format!("indexed_{}.png", depth), |x, y| palette_color(narrow(x, y, 0, depth) as usize, alpha_entries));
         }
+    }
 
-        if pixels.len() == pixels.capacity() {
-            break;
+    #[test]
+    fn low_bit_depths() {
+        for (depth, scale) in [(1, 255), (2, 85), (4, 17), (8, 1)] {
+            check(&format!("gray_{}.png", depth), |x, y| {
+                let g = (narrow(x, y, 0, depth) * scale) as u8;
+                Color::from([g, g, g])
+            });
         }
     }
 
-    Ok(Image {
-        width: info.width as usize,
-        height: info.height as usize,
-        data: pixels,
-    })
+    #[test]
+    fn transparency_chunks() {
+        check("gray_8_trns.png", |x, y| {
+            let g = narrow(x, y, 0, 8) as u8;
+            Color::from([g, g, g, if g == 5 { 0 } else { 255 }])
+        });
+        check("rgb_8_trns.png", |x, y| {
+            let c = [narrow(x, y, 0, 8) as u8, narrow(x, y, 1, 8) as u8, narrow(x, y, 2, 8) as u8];
+            Color::from([c[0], c[1], c[2], if c == [0, 7, 14] { 0 } else { 255 }])
+        });
+    }
+
+    #[test]
+    fn sixteen_bit() {
+        let high = |x, y, c| (wide(x, y, c) >> 8) as u8;
+
+        check("gray_16.png", |x, y| Color::from([high(x, y, 0), high(x, y, 0), high(x, y, 0)]));
+        check("gray_alpha_16.png", |x, y| Color::from([high(x, y, 0), high(x, y, 0), high(x, y, 0), high(x, y, 1)]));
+        check("rgb_16.png", |x, y| Color::from([high(x, y, 0), high(x, y, 1), high(x, y, 2)]));
+        check("rgba_16.png", |x, y| Color::from([high(x, y, 0), high(x, y, 1), high(x, y, 2), high(x, y, 3)]));
+    }
+
+    #[test]
+    fn eight_bit_alpha() {
+        let n = |x, y, c| narrow(x, y, c, 8) as u8;
+
+        check("gray_alpha_8.png", |x, y| Color::from([n(x, y, 0), n(x, y, 0), n(x, y, 0), n(x, y, 1)]));
+        check("rgba_8.png", |x, y| Color::from([n(x, y, 0), n(x, y, 1), n(x, y, 2), n(x, y, 3)]));
+    }
+
+    #[test]
+    fn interlaced() {
+        let high = |x, y, c| (wide(x, y, c) >> 8) as u8;
+
+        check("gray_1_interlaced.png", |x, y| {
+            let g = (narrow(x, y, 0, 1) * 255) as u8;
+            Color::from([g, g, g])
+        });
+        check("indexed_4_interlaced.png", |x, y| palette_color(narrow(x, y, 0, 4) as usize, 8));
+        check("rgb_8_interlaced.png", |x, y| {
+            Color::from([narrow(x, y, 0, 8) as u8, narrow(x, y, 1, 8) as u8, narrow(x, y, 2, 8) as u8])
+        });
+        check("rgba_16_interlaced.png", |x, y| Color::from([high(x, y, 0), high(x, y, 1), high(x, y, 2), high(x, y, 3)]));
+    }
+
+    #[test]
+    fn indexed_and_interlaced_round_trips() {
+        let fixtures = [
+            "indexed_1", "indexed_2", "indexed_4", "indexed_8",
+            "gray_1_interlaced", "indexed_4_interlaced", "rgb_8_interlaced", "rgba_16_interlaced",
+        ];
+
+        for name in fixtures {
+            let original = load_png(format!("image/fixtures/{}.png", name)).unwrap();
+            let path = format!("image/{}_round_trip.out.png", name);
+            original.write_to_file(&path).unwrap();
+            assert_eq!(original.data(), load_png(&path).unwrap().data(), "{}", name);
+        }
+    }
 }
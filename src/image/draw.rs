@@ -0,0 +1,533 @@
+use super::*;
+use crate::error::{Error, Result};
+use crate::vector::Vector;
+
+/// Longest a miter may be, as a multiple of the line width, before the join is beveled instead.
+const MITER_LIMIT: f64 = 4.0;
+
+/// How the ends of an open line are drawn.
+#[derive(Copy, Clone, Debug, PartialEq, Default)]
+pub enum LineCap {
+    /// The line stops flat at its end points.
+    Butt,
+    /// The line ends in a half circle around its end points.
+    #[default]
+    Round,
+    /// The line stops flat half its width past its end points.
+    Square,
+}
+
+impl LineCap {
+    /// The name used for this cap in configuration files.
+    pub fn name(&self) -> &'static str {
+        match self {
+            LineCap::Butt => "butt",
+            LineCap::Round => "round",
+            LineCap::Square => "square",
+        }
+    }
+}
+
+impl std::str::FromStr for LineCap {
+    type Err = Error;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "butt" => Ok(LineCap::Butt),
+            "round" => Ok(LineCap::Round),
+            "square" => Ok(LineCap::Square),
+            _ => Err(Error::InvalidValue { expected: "line cap", value: s.to_string() }),
+        }
+    }
+}
+
+/// How the corners between two segments of a line are drawn.
+#[derive(Copy, Clone, Debug, PartialEq, Default)]
+pub enum LineJoin {
+    /// The outer edges are extended until they meet, unless that is too far.
+    Miter,
+    /// The corner is rounded off.
+    #[default]
+    Round,
+    /// The corner is cut off flat.
+    Bevel,
+}
+
+impl LineJoin {
+    /// The name used for this join in configuration files.
+    pub fn name(&self) -> &'static str {
+        match self {
+            LineJoin::Miter => "miter",
+            LineJoin::Round => "round",
+            LineJoin::Bevel => "bevel",
+        }
+    }
+}
+
+impl std::str::FromStr for LineJoin {
+    type Err = Error;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "miter" => Ok(LineJoin::Miter),
+            "round" => Ok(LineJoin::Round),
+            "bevel" => Ok(LineJoin::Bevel),
+            _ => Err(Error::InvalidValue { expected: "line join", value: s.to_string() }),
+        }
+    }
+}
+
+/// Anti-aliased coverage of shapes, collected before being painted on to an image in a single color.
+/// Overlapping shapes do not darken each other. Pixel centers lie on whole coordinates.
+pub struct Mask {
+    width: usize,
+    height: usize,
+    data: Vec<f64>,
+}
+
+impl Mask {
+    /// Creates a mask with nothing covered.
+    pub fn new(width: usize, height: usize) -> Self {
+        Mask { width, height, data: vec![0.0; width * height] }
+    }
+
+    /// Returns how much of the pixel is covered, from 0 to 1. Returns None if out of bounds.
+    pub fn coverage_at(&self, x: usize, y: usize) -> Option<f64> {
+        if x < self.width && y < self.height {
+            Some(self.data[x + y * self.width])
+        } else {
+            None
+        }
+    }
+
+    /// Covers the pixels within `distance` of the shape, where `distance` is negative inside it.
+    /// Only pixels within the bounding box `min` to `max` are tested.
+    fn cover(&mut self, min: Vector<2>, max: Vector<2>, distance: impl Fn(Vector<2>) -> f64) {
+        if self.width == 0 || self.height == 0 {
+            return;
+        }
+
+        let x0 = (min[0] - 1.0).floor().max(0.0) as usize;
+        let y0 = (min[1] - 1.0).floor().max(0.0) as usize;
+        let x1 = ((max[0] + 1.0).ceil().max(0.0) as usize).min(self.width - 1);
+        let y1 = ((max[1] + 1.0).ceil().max(0.0) as usize).min(self.height - 1);
+
+        for y in y0..=y1 {
+            for x in x0..=x1 {
+                let coverage = (0.5 - distance(Vector::from([x as f64, y as f64]))).clamp(0.0, 1.0);
+                let pixel = &mut self.data[x + y * self.width];
+                *pixel = pixel.max(coverage);
+            }
+        }
+    }
+
+    /// Covers a filled circle.
+    pub fn disc(&mut self, center: Vector<2>, radius: f64) {
+        let r = Vector::from([radius, radius]);
+        self.cover(center - r, center + r, |p| (p - center).magnitude() - radius);
+    }
+
+    /// Covers a convex polygon, given by its corners in either winding order.
+    pub fn polygon(&mut self, corners: &[Vector<2>]) {
+        if corners.len() < 3 {
+            return;
+        }
+
+        let area: f64 = (0..corners.len())
+            .map(|i| {
+                let (a, b) = (corners[i], corners[(i + 1) % corners.len()]);
+                a[0] * b[1] - b[0] * a[1]
+            })
+            .sum();
+
+        if area.abs() < 1e-9 {
+            return;
+        }
+
+        // Outward facing normal and a point of every edge.
+        let edges: Vec<(Vector<2>, Vector<2>)> = (0..corners.len())
+            .filter_map(|i| {
+                let (a, b) = (corners[i], corners[(i + 1) % corners.len()]);
+                let d = b - a;
+                let length = d.magnitude();
+                if length == 0.0 {
+                    return None;
+                }
+                let normal = Vector::from([d[1], -d[0]]) * (area.signum() / length);
+                Some((a, normal))
+            })
+            .collect();
+
+        let mut min = corners[0];
+        let mut max = corners[0];
+        for c in corners {
+            for i in 0..2 {
+                min[i] = min[i].min(c[i]);
+                max[i] = max[i].max(c[i]);
+            }
+        }
+
+        self.cover(min, max, |p| {
+            edges.iter().map(|(a, n)| Vector::dot(p - *a, *n)).fold(f64::MIN, f64::max)
+        });
+    }
+
+    /// Covers a line of constant width through each point in turn.
+    pub fn polyline(&mut self, points: &[Vector<2>], width: f64, cap: LineCap, join: LineJoin) {
+        let widths = vec![width; points.len()];
+        self.tapered_polyline(points, &widths, cap, join);
+    }
+
+    /// Covers a line through each point in turn, with its width changing linearly between the given width at each point.
+    pub fn tapered_polyline(&mut self, points: &[Vector<2>], widths: &[f64], cap: LineCap, join: LineJoin) {
+        // Repeated points have no direction, so are dropped.
+        let mut line: Vec<(Vector<2>, f64)> = Vec::new();
+        for (p, w) in points.iter().zip(widths) {
+            match line.last() {
+                Some((last, _)) if (*p - *last).magnitude() < 1e-9 => continue,
+                _ => line.push((*p, w / 2.0)),
+            }
+        }
+
+        if line.is_empty() {
+            return;
+        }
+
+        if line.len() == 1 {
+            let (p, hw) = line[0];
+            match cap {
+                LineCap::Butt => {}
+                LineCap::Round => self.disc(p, hw),
+                LineCap::Square => {
+                    let d = Vector::from([hw, hw]);
+                    self.polygon(&[p - d, Vector::from([p[0] + hw, p[1] - hw]), p + d, Vector::from([p[0] - hw, p[1] + hw])]);
+                }
+            }
+            return;
+        }
+
+        let direction = |i: usize| (line[i + 1].0 - line[i].0).normalise();
+        let normal = |d: Vector<2>| Vector::from([-d[1], d[0]]);
+        let last = line.len() - 2;
+
+        for i in 0..=last {
+            let (mut a, ha) = line[i];
+            let (mut b, hb) = line[i + 1];
+            let d = direction(i);
+
+            if cap == LineCap::Square {
+                if i == 0 {
+                    a -= d * ha;
+                }
+                if i == last {
+                    b += d * hb;
+                }
+            }
+
+            let n = normal(d);
+            self.polygon(&[a + n * ha, b + n * hb, b - n * hb, a - n * ha]);
+        }
+
+        if cap == LineCap::Round {
+            self.disc(line[0].0, line[0].1);
+            self.disc(line[last + 1].0, line[last + 1].1);
+        }
+
+        for (i, &(p, hw)) in line.iter().enumerate().take(last + 1).skip(1) {
+            let (d1, d2) = (direction(i - 1), direction(i));
+            let (n1, n2) = (normal(d1), normal(d2));
+
+            // The gap to fill is on the outside of the turn.
+            let turn = d1[0] * d2[1] - d1[1] * d2[0];
+            if turn.abs() < 1e-9 && Vector::dot(d1, d2) > 0.0 {
+                continue;
+            }
+            let side = if turn > 0.0 { -1.0 } else { 1.0 };
+            let (a, b) = (p + n1 * (side * hw), p + n2 * (side * hw));
+
+            let bisector = n1 + n2;
+            let miter_ratio = 2.0 / bisector.magnitude();
+
+            match join {
+                LineJoin::Round => self.disc(p, hw),
+                LineJoin::Miter if miter_ratio <= MITER_LIMIT => {
+                    let m = p + bisector.normalise() * (side * hw * miter_ratio);
+                    self.polygon(&[p, a, m, b]);
+                }
+                _ => self.polygon(&[p, a, b]),
+            }
+        }
+    }
+
+    /// Paints the covered pixels of the image with a color, faded by how much each is covered.
+    pub fn paint(&self, image: &mut Image, color: Color) -> Result<()> {
+        if image.width() != self.width || image.height() != self.height {
+            return Err(Error::DimensionMismatch { expected: (image.width(), image.height()), found: (self.width, self.height) });
+        }
+
+        for (pixel, coverage) in image.data.iter_mut().zip(&self.data) {
+            if *coverage > 0.0 {
+                *pixel = BlendMode::Normal.composite(*pixel, color, *coverage);
+            }
+        }
+
+        return Ok(());
+    }
+}
+
+/// Rounds off the corners of a line by Chaikin's corner cutting, keeping its end points.
+/// Each iteration replaces every corner with two points a quarter of the way along its segments.
+/// Extra dimensions, such as a width at each point, are blended along with the position.
+pub fn smooth<const S: usize>(points: &[Vector<S>], iterations: usize) -> Vec<Vector<S>> {
+    let mut line = points.to_vec();
+
+    for _ in 0..iterations {
+        if line.len() < 3 {
+            break;
+        }
+
+        let mut smoothed = vec![line[0]];
+        for pair in line.windows(2) {
+            let (a, b) = (pair[0], pair[1]);
+            smoothed.push(a * 0.75 + b * 0.25);
+            smoothed.push(a * 0.25 + b * 0.75);
+        }
+        smoothed.push(line[line.len() - 1]);
+
+        // The first and last cuts would only shorten the end segments.
+        smoothed.remove(1);
+        smoothed.remove(smoothed.len() - 2);
+        line = smoothed;
+    }
+
+    return line;
+}
+
+/// Rounds off every corner of a closed loop by Chaikin's corner cutting, including the corner where it starts.
+/// The first point of the loop is repeated at its end, in both the given and returned points.
+pub fn smooth_closed(points: &[Vector<2>], iterations: usize) -> Vec<Vector<2>> {
+    let mut loop_points = points.to_vec();
+
+    for _ in 0..iterations {
+        if loop_points.len() < 4 {
+            break;
+        }
+
+        let mut smoothed = Vec::new();
+        for pair in loop_points.windows(2) {
+            let (a, b) = (pair[0], pair[1]);
+            smoothed.push(a * 0.75 + b * 0.25);
+            smoothed.push(a * 0.25 + b * 0.75);
+        }
+        smoothed.push(smoothed[0]);
+        loop_points = smoothed;
+    }
+
+    return loop_points;
+}
+
+/// Removes points from a line that lie within `tolerance` of the simplified line, using the Ramer-Douglas-Peucker algorithm.
+/// The end points are always kept.
+pub fn simplify(points: &[Vector<2>], tolerance: f64) -> Vec<Vector<2>> {
+    if points.len() < 3 {
+        return points.to_vec();
+    }
+
+    let mut keep = vec![false; points.len()];
+    keep[0] = true;
+    keep[points.len() - 1] = true;
+
+    let mut ranges = vec![(0, points.len() - 1)];
+    while let Some((first, last)) = ranges.pop() {
+        let (a, b) = (points[first], points[last]);
+        let d = b - a;
+        let length = d.magnitude();
+
+        let distance = |p: Vector<2>| {
+            if length == 0.0 {
+                (p - a).magnitude()
+            } else {
+                (d[0] * (a[1] - p[1]) - d[1] * (a[0] - p[0])).abs() / length
+            }
+        };
+
+        let furthest = (first + 1..last).map(|i| (i, distance(points[i]))).fold((first, 0.0), |m, c| if c.1 > m.1 { c } else { m });
+        if furthest.1 > tolerance {
+            keep[furthest.0] = true;
+            ranges.push((first, furthest.0));
+            ranges.push((furthest.0, last));
+        }
+    }
+
+    return points.iter().zip(keep).filter(|(_, k)| *k).map(|(p, _)| *p).collect();
+}
+
+/// Splits a line into dashes of length `on` separated by gaps of length `off`, measured along the line.
+pub fn dash(points: &[Vector<2>], on: f64, off: f64) -> Vec<Vec<Vector<2>>> {
+    if on <= 0.0 || off <= 0.0 || points.len() < 2 {
+        return vec![points.to_vec()];
+    }
+
+    let mut dashes = Vec::new();
+    let mut current = vec![points[0]];
+    let mut drawing = true;
+    // Distance left until the current dash or gap ends.
+    let mut remaining = on;
+
+    for pair in points.windows(2) {
+        let (mut a, b) = (pair[0], pair[1]);
+        let mut length = (b - a).magnitude();
+
+        while length > remaining {
+            let split = a + (b - a) * (remaining / length);
+            if drawing {
+                current.push(split);
+                dashes.push(std::mem::take(&mut current));
+            } else {
+                current = vec![split];
+            }
+
+            length -= remaining;
+            a = split;
+            drawing = !drawing;
+            remaining = if drawing { on } else { off };
+        }
+
+        remaining -= length;
+        if drawing {
+            current.push(b);
+        }
+    }
+
+    if drawing && current.len() > 1 {
+        dashes.push(current);
+    }
+
+    return dashes;
+}
+
+/// Returns the length of a line.
+pub fn length(points: &[Vector<2>]) -> f64 {
+    return points.windows(2).map(|pair| (pair[1] - pair[0]).magnitude()).sum();
+}
+
+/// Returns the point a distance along a line, clamped to its ends.
+pub fn point_along(points: &[Vector<2>], distance: f64) -> Option<Vector<2>> {
+    let mut remaining = distance.max(0.0);
+    for pair in points.windows(2) {
+        let length = (pair[1] - pair[0]).magnitude();
+        if remaining <= length && length > 0.0 {
+            return Some(pair[0] + (pair[1] - pair[0]) * (remaining / length));
+        }
+        remaining -= length;
+    }
+    return points.last().copied();
+}
+
+/// Removes the stretches of a line between each `(start, end)` pair of distances along it, returning the pieces left.
+pub fn cut(points: &[Vector<2>], gaps: &[(f64, f64)]) -> Vec<Vec<Vector<2>>> {
+    let in_gap = |d: f64| gaps.iter().any(|(start, end)| d > *start && d < *end);
+
+    let mut pieces = Vec::new();
+    let mut current = Vec::new();
+    let mut travelled = 0.0;
+
+    for pair in points.windows(2) {
+        let (a, b) = (pair[0], pair[1]);
+        let length = (b - a).magnitude();
+        if length == 0.0 {
+            continue;
+        }
+
+        // Split the segment wherever a gap starts or ends.
+        let (start, end) = (travelled, travelled + length);
+        let mut marks = vec![start, end];
+        marks.extend(gaps.iter().flat_map(|(s, e)| [*s, *e]).filter(|d| *d > start && *d < end));
+        marks.sort_by(f64::total_cmp);
+
+        for span in marks.windows(2) {
+            let at = |d: f64| a + (b - a) * ((d - start) / length);
+            if in_gap((span[0] + span[1]) / 2.0) {
+                if current.len() > 1 {
+                    pieces.push(std::mem::take(&mut current));
+                }
+                current.clear();
+            } else {
+                if current.is_empty() {
+                    current.push(at(span[0]));
+                }
+                current.push(at(span[1]));
+            }
+        }
+
+        travelled = end;
+    }
+
+    if current.len() > 1 {
+        pieces.push(current);
+    }
+
+    return pieces;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn line_caps_and_joins() {
+        let points = [Vector::from([4.0, 10.0]), Vector::from([16.0, 10.0]), Vector::from([16.0, 2.0])];
+        let mask = |cap, join| {
+            let mut mask = Mask::new(24, 16);
+            mask.polyline(&points, 4.0, cap, join);
+            mask
+        };
+
+        let butt = mask(LineCap::Butt, LineJoin::Miter);
+        let round = mask(LineCap::Round, LineJoin::Bevel);
+        let square = mask(LineCap::Square, LineJoin::Round);
+
+        assert_eq!(butt.coverage_at(10, 10), Some(1.0));
+        assert_eq!(butt.coverage_at(2, 10), Some(0.0));
+        assert_eq!(round.coverage_at(3, 10), Some(1.0));
+        assert!(square.coverage_at(2, 9).unwrap() > round.coverage_at(2, 9).unwrap());
+
+        // Mitered corners are filled out to a point, bevels cut straight across.
+        assert_eq!(butt.coverage_at(17, 11), Some(1.0));
+        assert_eq!(round.coverage_at(17, 11), Some(0.5));
+        assert_eq!(square.coverage_at(17, 11), Some(1.0));
+    }
+
+    #[test]
+    fn line_shaping() {
+        let corner = [Vector::from([0.0, 0.0]), Vector::from([10.0, 0.0]), Vector::from([10.0, 10.0])];
+        let smoothed = smooth(&corner, 2);
+        assert_eq!(smoothed.first(), corner.first());
+        assert_eq!(smoothed.last(), corner.last());
+        assert!(!smoothed.contains(&corner[1]));
+
+        let square = [corner[0], corner[1], corner[2], Vector::from([0.0, 10.0]), corner[0]];
+        let rounded = smooth_closed(&square, 2);
+        assert_eq!(rounded.first(), rounded.last());
+        assert_eq!(rounded.len(), 4 * 4 + 1);
+        assert!(square.iter().all(|corner| !rounded.contains(corner)));
+
+        let staircase: Vec<_> = (0..10).map(|i| Vector::from([i as f64, (i / 2) as f64])).collect();
+        assert_eq!(simplify(&staircase, 1.0), vec![staircase[0], staircase[9]]);
+        assert_eq!(simplify(&corner, 1.0), corner.to_vec());
+
+        let line = [Vector::from([0.0, 0.0]), Vector::from([10.0, 0.0]), Vector::from([10.0, 10.0])];
+        assert_eq!(length(&line), 20.0);
+        assert_eq!(point_along(&line, 12.0), Some(Vector::from([10.0, 2.0])));
+        assert_eq!(point_along(&line, 30.0), Some(Vector::from([10.0, 10.0])));
+
+        let pieces = cut(&line, &[(5.0, 12.0), (18.0, 25.0)]);
+        assert_eq!(pieces, vec![
+            vec![Vector::from([0.0, 0.0]), Vector::from([5.0, 0.0])],
+            vec![Vector::from([10.0, 2.0]), Vector::from([10.0, 8.0])],
+        ]);
+
+        let dashes = dash(&line, 3.0, 2.0);
+        assert_eq!(dashes.len(), 4);
+        assert_eq!(dashes[1], vec![Vector::from([5.0, 0.0]), Vector::from([8.0, 0.0])]);
+        assert_eq!(dashes[2], vec![Vector::from([10.0, 0.0]), Vector::from([10.0, 3.0])]);
+    }
+}
@@ -1,16 +1,20 @@
 use super::*;
 use png::{BitDepth, ColorType, Encoder};
+use crate::error::{Error, Result};
+
+pub fn write_file(image: &Image, file: impl AsRef<Path>) -> Result<()> {
+    let path = file.as_ref().to_path_buf();
+    let encode_error = |source| Error::Encode { path: path.clone(), source };
 
-pub fn write_file(image: &Image, file: impl AsRef<Path>) -> Result<(), String> {
     let mut encoder = {
-        let file = File::create(file).map_err(|e| e.to_string())?;
+        let file = File::create(&path).map_err(|source| Error::Io { path: path.clone(), source })?;
         Encoder::new(file, image.width as u32, image.height as u32)
     };
 
     encoder.set_color(ColorType::RGBA);
     encoder.set_depth(BitDepth::Eight);
 
-    let mut header = encoder.write_header().map_err(|e| e.to_string())?;
+    let mut header = encoder.write_header().map_err(encode_error)?;
 
     let mut data = Vec::with_capacity(image.data.len() * 4);
     for y in 0..image.height() {
@@ -22,16 +26,28 @@ pub fn write_file(image: &Image, file: impl AsRef<Path>) -> Result<(), String> {
         }
     }
 
-    let as_bytes = unsafe {
-        std::slice::from_raw_parts(
-            data.as_ptr() as *const u8,
-            data.len() * std::mem::size_of::<u8>(),
-        )
+    header
+        .write_image_data(&data)
+        .map_err(encode_error)?;
+
+    return Ok(());
+}
+
+/// Writes 16 bit grayscale samples, row by row, as a png.
+pub(crate) fn write_grayscale(width: usize, height: usize, samples: &[u16], file: impl AsRef<Path>) -> Result<()> {
+    let path = file.as_ref().to_path_buf();
+    let encode_error = |source| Error::Encode { path: path.clone(), source };
+
+    let mut encoder = {
+        let file = File::create(&path).map_err(|source| Error::Io { path: path.clone(), source })?;
+        Encoder::new(file, width as u32, height as u32)
     };
 
-    header
-        .write_image_data(&as_bytes[..])
-        .map_err(|e| e.to_string())?;
+    encoder.set_color(ColorType::Grayscale);
+    encoder.set_depth(BitDepth::Sixteen);
+
+    let data: Vec<u8> = samples.iter().flat_map(|s| s.to_be_bytes()).collect();
+    encoder.write_header().map_err(encode_error)?.write_image_data(&data).map_err(encode_error)?;
 
     return Ok(());
 }
@@ -1,51 +1,298 @@
-use std::collections::HashMap;
+use super::*;
+use crate::error::{Error, Result};
 
-const KERNEL_MINIMUM_VALUE: f64 = 0.0000001;
+/// Number of standard deviations a gaussian kernel reaches out to, beyond which its values are negligible.
+const GAUSSIAN_EXTENT: f64 = 3.0;
 
-#[derive(Clone)]
+/// How pixels beyond the edge of an image are sampled while filtering.
+#[derive(Copy, Clone, Debug, PartialEq, Default)]
+pub enum EdgeMode {
+    /// The nearest pixel on the edge is repeated.
+    #[default]
+    Clamp,
+    /// The image repeats, so the left edge continues from the right.
+    Wrap,
+    /// The image is reflected back on itself at its edges.
+    Mirror,
+    /// Everything beyond the edge is fully transparent.
+    Transparent,
+}
+
+impl EdgeMode {
+    /// Maps a coordinate along an axis of length `n` on to the image, or None if it samples transparency.
+    fn index(&self, i: isize, n: usize) -> Option<usize> {
+        let n = n as isize;
+        if (0..n).contains(&i) {
+            return Some(i as usize);
+        }
+
+        return match self {
+            EdgeMode::Clamp => Some(i.clamp(0, n - 1) as usize),
+            EdgeMode::Wrap => Some(i.rem_euclid(n) as usize),
+            EdgeMode::Mirror => {
+                let m = i.rem_euclid(2 * n);
+                Some(if m >= n { 2 * n - 1 - m } else { m } as usize)
+            }
+            EdgeMode::Transparent => None,
+        };
+    }
+}
+
+impl std::str::FromStr for EdgeMode {
+    type Err = Error;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "clamp" => Ok(EdgeMode::Clamp),
+            "wrap" => Ok(EdgeMode::Wrap),
+            "mirror" => Ok(EdgeMode::Mirror),
+            "transparent" => Ok(EdgeMode::Transparent),
+            _ => Err(Error::InvalidValue { expected: "edge mode", value: s.to_string() }),
+        }
+    }
+}
+
+/// Weights of a convolution, stored densely over a rectangle centered on (0, 0).
+/// Kernels that are the product of a row and a column, such as gaussian and box kernels,
+/// remember those factors so they can be applied in two fast one dimensional passes.
+#[derive(Clone, Default)]
 pub struct Kernel {
-    values: HashMap<(isize, isize), f64>,
+    x_radius: usize,
+    y_radius: usize,
+    values: Vec<f64>,
+    factors: Option<(Vec<f64>, Vec<f64>)>,
 }
 
 impl Kernel {
+    /// Creates an empty kernel.
     pub fn new() -> Self {
-        Kernel { values: HashMap::new() }
+        Self::default()
     }
 
-    pub fn gaussian(sd: f64) -> Self {
-        let mut kernel = Kernel::new();
-        let radius = (2.0 * sd * sd).ceil() as isize;
+    /// Creates a kernel from the product of a row and a column of weights, each of odd length so that they have a center.
+    /// Returns Err if either has an even length.
+    pub fn separable(row: Vec<f64>, column: Vec<f64>) -> Result<Self> {
+        if let Some(even) = [&row, &column].iter().find(|weights| weights.len() % 2 == 0) {
+            return Err(Error::InvalidValue { expected: "odd number of kernel weights", value: even.len().to_string() });
+        }
 
-        for x in -radius..radius {
-            for y in -radius..radius {
-                use std::f64::consts::{PI, E};
+        let x_radius = row.len() / 2;
+        let y_radius = column.len() / 2;
+        let values = column.iter().flat_map(|c| row.iter().map(move |r| r * c)).collect();
+        return Ok(Kernel { x_radius, y_radius, values, factors: Some((row, column)) });
+    }
 
-                let xc = x as f64;
-                let yc = y as f64;
+    /// Samples of a two dimensional gaussian with standard deviation `sd`, reaching out three deviations.
+    /// A deviation of 0 gives a kernel that leaves images unchanged. Returns Err if `sd` is negative or not finite.
+    pub fn gaussian(sd: f64) -> Result<Self> {
+        use std::f64::consts::PI;
 
-                let v = ( 1.0 / (2.0 * PI * sd * sd) ) * E.powf( -(xc * xc  + yc * yc) / ( 2.0 * sd * sd) );
-                if v < KERNEL_MINIMUM_VALUE { continue; }
-                *kernel.value_at_mut((xc as isize, yc as isize)) = v;
-            }
+        if !sd.is_finite() || sd < 0.0 {
+            return Err(Error::InvalidValue { expected: "standard deviation", value: sd.to_string() });
+        }
+        if sd == 0.0 {
+            return Self::separable(vec![1.0], vec![1.0]);
         }
 
-        return kernel;
+        let radius = (GAUSSIAN_EXTENT * sd).ceil().max(0.0) as isize;
+        let row: Vec<f64> = (-radius..=radius)
+            .map(|x| (1.0 / ((2.0 * PI).sqrt() * sd)) * (-(x * x) as f64 / (2.0 * sd * sd)).exp())
+            .collect();
+
+        return Self::separable(row.clone(), row);
     }
 
+    /// A kernel averaging every pixel within `radius` pixels horizontally and vertically.
+    pub fn box_blur(radius: usize) -> Self {
+        let row = vec![1.0 / (2 * radius + 1) as f64; 2 * radius + 1];
+        return Self::separable(row.clone(), row).unwrap();
+    }
+
+    /// Returns the weight at an offset from the center, 0 outside of the kernel.
     pub fn value_at(&self, p: (isize, isize)) -> f64 {
-        return *self.values.get(&p).unwrap_or(&0.0);
+        return match self.index(p) {
+            Some(i) => self.values[i],
+            None => 0.0,
+        };
     }
 
+    /// Returns the weight at an offset from the center to be changed, growing the kernel to fit it.
+    /// The kernel is no longer treated as separable.
     pub fn value_at_mut(&mut self, p: (isize, isize)) -> &mut f64 {
-        if !self.values.contains_key(&p) {
-            self.values.insert(p, 0.0);
-        } 
+        let x_radius = self.x_radius.max(p.0.unsigned_abs());
+        let y_radius = self.y_radius.max(p.1.unsigned_abs());
+
+        if x_radius != self.x_radius || y_radius != self.y_radius || self.values.is_empty() {
+            let mut grown = Kernel {
+                x_radius,
+                y_radius,
+                values: vec![0.0; (2 * x_radius + 1) * (2 * y_radius + 1)],
+                factors: None,
+            };
 
-        return self.values.get_mut(&p).unwrap();
+            for (offset, value) in self.pairs() {
+                let i = grown.index(offset).unwrap();
+                grown.values[i] = value;
+            }
+
+            *self = grown;
+        }
+
+        self.factors = None;
+        let i = self.index(p).unwrap();
+        return &mut self.values[i];
     }
 
+    /// Returns every offset with a non zero weight along with its weight.
     pub fn pairs(&self) -> Vec<((isize, isize), f64)> {
-        return self.values.iter().map(|(p, v)| (*p, *v)).collect();
+        let width = 2 * self.x_radius + 1;
+        return self.values.iter().enumerate()
+            .filter(|(_, v)| **v != 0.0)
+            .map(|(i, v)| (((i % width) as isize - self.x_radius as isize, (i / width) as isize - self.y_radius as isize), *v))
+            .collect();
+    }
+
+    /// Sum of every weight in the kernel.
+    pub fn total(&self) -> f64 {
+        return self.values.iter().sum();
+    }
+
+    fn index(&self, p: (isize, isize)) -> Option<usize> {
+        if self.values.is_empty() || p.0.unsigned_abs() > self.x_radius || p.1.unsigned_abs() > self.y_radius {
+            return None;
+        }
+
+        let x = (p.0 + self.x_radius as isize) as usize;
+        let y = (p.1 + self.y_radius as isize) as usize;
+        return Some(x + y * (2 * self.x_radius + 1));
+    }
+}
+
+/// Colors with their channels scaled by alpha, so that transparent pixels do not bleed their color when filtered.
+struct Premultiplied {
+    width: usize,
+    height: usize,
+    data: Vec<[f64; 4]>,
+}
+
+impl Premultiplied {
+    fn from_image(image: &Image) -> Self {
+        let data = image.data().iter().map(|c| Self::premultiply(*c)).collect();
+        Premultiplied { width: image.width(), height: image.height(), data }
+    }
+
+    fn premultiply(c: Color) -> [f64; 4] {
+        let a = c[3] as f64 / u8::MAX as f64;
+        return [c[0] as f64 * a, c[1] as f64 * a, c[2] as f64 * a, c[3] as f64];
+    }
+
+    fn sample(&self, x: isize, y: isize, edge: EdgeMode) -> [f64; 4] {
+        return match (edge.index(x, self.width), edge.index(y, self.height)) {
+            (Some(x), Some(y)) => self.data[x + y * self.width],
+            _ => [0.0; 4],
+        };
+    }
+
+    /// Weighted sum of the pixels around (x, y), divided by `total` unless it is 0.
+    fn convolve_pixel(&self, x: usize, y: usize, pairs: &[((isize, isize), f64)], total: f64, edge: EdgeMode) -> [f64; 4] {
+        let mut acc = [0.0; 4];
+        for ((dx, dy), w) in pairs {
+            let c = self.sample(x as isize + dx, y as isize + dy, edge);
+            for i in 0..4 {
+                acc[i] += w * c[i];
+            }
+        }
+        return normalise(acc, total);
+    }
+
+    /// Convolves every pixel with a one dimensional kernel along the rows, or down the columns if `vertical`.
+    fn convolve_line(&self, weights: &[f64], vertical: bool, edge: EdgeMode) -> Self {
+        let radius = (weights.len() / 2) as isize;
+        let total: f64 = weights.iter().sum();
+        let mut data = vec![[0.0; 4]; self.data.len()];
+
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let mut acc = [0.0; 4];
+                for (k, w) in weights.iter().enumerate() {
+                    let offset = k as isize - radius;
+                    let c = if vertical {
+                        self.sample(x as isize, y as isize + offset, edge)
+                    } else {
+                        self.sample(x as isize + offset, y as isize, edge)
+                    };
+
+                    for i in 0..4 {
+                        acc[i] += w * c[i];
+                    }
+                }
+                data[x + y * self.width] = normalise(acc, total);
+            }
+        }
+
+        Premultiplied { width: self.width, height: self.height, data }
+    }
+
+    fn color(p: [f64; 4]) -> Color {
+        let alpha = p[3].clamp(0.0, u8::MAX as f64);
+        if alpha <= 0.0 {
+            return Color::from([0, 0, 0, 0]);
+        }
+
+        let a = alpha / u8::MAX as f64;
+        let channel = |v: f64| (v / a).round().clamp(0.0, u8::MAX as f64) as u8;
+        return Color::from([channel(p[0]), channel(p[1]), channel(p[2]), alpha.round() as u8]);
+    }
+
+    fn to_image(&self) -> Image {
+        let mut image = Image::new(self.width, self.height);
+        image.data = self.data.iter().map(|p| Self::color(*p)).collect();
+        return image;
+    }
+}
+
+fn normalise(acc: [f64; 4], total: f64) -> [f64; 4] {
+    if total == 0.0 {
+        return acc;
+    }
+    return acc.map(|v| v / total);
+}
+
+impl Image {
+    /// Convolves a single pixel with a kernel, sampling beyond the edges of the image according to `edge`.
+    /// The weighted sum is divided by the total weight of the kernel, unless that is 0 as for edge detection.
+    pub fn kernel_filter_pixel(&self, x: usize, y: usize, kernel: &Kernel, edge: EdgeMode) -> Color {
+        let mut acc = [0.0; 4];
+        for ((dx, dy), w) in kernel.pairs() {
+            let c = match (edge.index(x as isize + dx, self.width), edge.index(y as isize + dy, self.height)) {
+                (Some(x), Some(y)) => Premultiplied::premultiply(self.data[x + y * self.width]),
+                _ => [0.0; 4],
+            };
+
+            for i in 0..4 {
+                acc[i] += w * c[i];
+            }
+        }
+
+        return Premultiplied::color(normalise(acc, kernel.total()));
+    }
+
+    /// Convolves the whole image with a kernel. See `kernel_filter_pixel`.
+    /// Separable kernels are applied as two one dimensional passes, which is much faster for large kernels.
+    pub fn kernel_filter(&self, kernel: &Kernel, edge: EdgeMode) -> Image {
+        let image = Premultiplied::from_image(self);
+
+        if let Some((row, column)) = &kernel.factors {
+            return image.convolve_line(row, false, edge).convolve_line(column, true, edge).to_image();
+        }
+
+        let total = kernel.total();
+        let pairs = kernel.pairs();
+        let data = (0..self.height)
+            .flat_map(|y| (0..self.width).map(move |x| (x, y)))
+            .map(|(x, y)| image.convolve_pixel(x, y, &pairs, total, edge))
+            .collect();
+
+        return Premultiplied { width: self.width, height: self.height, data }.to_image();
     }
 }
 
@@ -55,10 +302,72 @@ mod test {
 
     #[test]
     fn gauss_test() {
-        let k = Kernel::gaussian(0.84089642);
-        
-        assert!(k.value_at((0, 0)) - 0.22508352 < 0.00000001); 
+        let k = Kernel::gaussian(0.84089642).unwrap();
+
+        assert!(k.value_at((0, 0)) - 0.22508352 < 0.00000001);
         assert!(k.value_at((-3, 0)) - 0.00038771 < 0.00000001);
         assert!(k.value_at((2, -1)) - 0.00655965 < 0.00000001);
+
+        // No deviation leaves the image as it is, while impossible deviations are rejected.
+        let identity = Kernel::gaussian(0.0).unwrap();
+        assert_eq!(identity.pairs(), vec![((0, 0), 1.0)]);
+        let image = Image::new(3, 2).fill(Color::from([10, 20, 30, 255]));
+        assert_eq!(image.kernel_filter(&identity, EdgeMode::Clamp).data(), image.data());
+        assert!(Kernel::gaussian(-1.0).is_err());
+        assert!(Kernel::gaussian(f64::NAN).is_err());
+    }
+
+    #[test]
+    fn weighted_edges() {
+        let mut image = Image::new(3, 1);
+        image.set_pixel_at(0, 0, Color::from([0, 0, 0, 255])).unwrap();
+        image.set_pixel_at(1, 0, Color::from([90, 90, 90, 255])).unwrap();
+        image.set_pixel_at(2, 0, Color::from([180, 180, 180, 255])).unwrap();
+
+        // Takes three quarters of the pixel to the left.
+        let mut kernel = Kernel::new();
+        *kernel.value_at_mut((-1, 0)) = 3.0;
+        *kernel.value_at_mut((0, 0)) = 1.0;
+
+        assert_eq!(image.kernel_filter_pixel(1, 0, &kernel, EdgeMode::Clamp), Color::from([23, 23, 23, 255]));
+        assert_eq!(image.kernel_filter_pixel(0, 0, &kernel, EdgeMode::Clamp), Color::from([0, 0, 0, 255]));
+        assert_eq!(image.kernel_filter_pixel(0, 0, &kernel, EdgeMode::Wrap), Color::from([135, 135, 135, 255]));
+        assert_eq!(image.kernel_filter_pixel(0, 0, &kernel, EdgeMode::Mirror), Color::from([0, 0, 0, 255]));
+        assert_eq!(image.kernel_filter_pixel(0, 0, &kernel, EdgeMode::Transparent), Color::from([0, 0, 0, 64]));
+    }
+
+    #[test]
+    fn separable_matches_dense() {
+        let mut image = Image::new(9, 7);
+        for x in 0..9 {
+            for y in 0..7 {
+                image.set_pixel_at(x, y, Color::from([(x * 28) as u8, (y * 36) as u8, 100, (255 - x * y * 4) as u8])).unwrap();
+            }
+        }
+
+        let separable = Kernel::gaussian(1.5).unwrap();
+        let mut dense = Kernel::new();
+        for (offset, value) in separable.pairs() {
+            *dense.value_at_mut(offset) = value;
+        }
+
+        for edge in [EdgeMode::Clamp, EdgeMode::Wrap, EdgeMode::Mirror, EdgeMode::Transparent] {
+            let a = image.kernel_filter(&separable, edge);
+            let b = image.kernel_filter(&dense, edge);
+            for (p, q) in a.data().iter().zip(b.data()) {
+                for i in 0..4 {
+                    assert!((p[i] as i32 - q[i] as i32).abs() <= 1, "{} and {} differ with {:?}", p, q, edge);
+                }
+            }
+        }
+
+        let flat = Image::new(5, 5).fill(Color::from([10, 20, 30, 255])).kernel_filter(&Kernel::box_blur(2), EdgeMode::Clamp);
+        assert!(flat.data().iter().all(|c| *c == Color::from([10, 20, 30, 255])));
+
+        // Rows and columns need a center weight.
+        let uneven = Kernel::separable(vec![0.25, 0.5, 0.25], vec![1.0, 2.0, 1.0]).unwrap();
+        assert_eq!(uneven.value_at((1, -1)), 0.25);
+        assert!(Kernel::separable(vec![0.5, 0.5], vec![1.0]).is_err());
+        assert!(Kernel::separable(vec![1.0], vec![]).is_err());
     }
 }
@@ -0,0 +1,176 @@
+use super::*;
+use crate::error::{Error, Result};
+use json::JsonValue;
+use std::io::Read;
+use std::str::FromStr;
+
+/// Colors placed along the range 0 to 1, blended smoothly between each other.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ColorRamp {
+    stops: Vec<(f64, Color)>,
+}
+
+impl ColorRamp {
+    /// Creates a ramp from colors at positions between 0 and 1. There must be at least one stop.
+    pub fn new(mut stops: Vec<(f64, Color)>) -> Result<Self> {
+        if stops.is_empty() {
+            return Err(Error::InvalidValue { expected: "color ramp", value: String::from("no colors") });
+        }
+        if let Some((position, _)) = stops.iter().find(|(p, _)| !(0.0..=1.0).contains(p)) {
+            return Err(Error::InvalidValue { expected: "color ramp position between 0 and 1", value: position.to_string() });
+        }
+
+        stops.sort_by(|a, b| a.0.total_cmp(&b.0));
+        return Ok(ColorRamp { stops });
+    }
+
+    /// Creates a ramp from colors spread evenly from 0 to 1.
+    pub fn even(colors: &[Color]) -> Result<Self> {
+        let last = colors.len().saturating_sub(1).max(1) as f64;
+        return ColorRamp::new(colors.iter().enumerate().map(|(i, c)| (i as f64 / last, *c)).collect());
+    }
+
+    /// A ramp from black to white.
+    pub fn greyscale() -> Self {
+        ColorRamp { stops: vec![(0.0, Color::from([0, 0, 0, 255])), (1.0, Color::from([255, 255, 255, 255]))] }
+    }
+
+    /// A ramp over land from lowland greens through browns to snowy white peaks.
+    pub fn classic() -> Self {
+        ColorRamp::builtin(&[(0.0, "#2e6b3a"), (0.2, "#6fa35c"), (0.4, "#d8cf8a"), (0.6, "#a8773f"), (0.8, "#7a5a45"), (1.0, "#ffffff")])
+    }
+
+    /// A ramp under water from the deep navy of the ocean floor to pale shallows.
+    pub fn bathymetric() -> Self {
+        ColorRamp::builtin(&[(0.0, "#08306b"), (0.4, "#2171b5"), (0.75, "#6baed6"), (1.0, "#deebf7")])
+    }
+
+    /// Returns the built in ramp with the given name, if there is one.
+    pub fn named(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "greyscale" => Some(ColorRamp::greyscale()),
+            "classic" => Some(ColorRamp::classic()),
+            "bathymetric" => Some(ColorRamp::bathymetric()),
+            _ => None,
+        }
+    }
+
+    fn builtin(stops: &[(f64, &str)]) -> Self {
+        ColorRamp { stops: stops.iter().map(|(p, c)| (*p, Color::from_str(c).unwrap())).collect() }
+    }
+
+    /// Loads a ramp from a json file. See `from_json`.
+    pub fn from_file(file: impl AsRef<Path>) -> Result<Self> {
+        let path = PathBuf::from(file.as_ref());
+        let io_error = |source| Error::Io { path: path.clone(), source };
+
+        let mut file = File::open(&path).map_err(io_error)?;
+        let mut string = String::new();
+        file.read_to_string(&mut string).map_err(io_error)?;
+        return Self::from_json(json::parse(&string)?);
+    }
+
+    /// Builds a ramp from a string that `from_str` can parse, an array of colors spread evenly,
+    /// or an array of `[position, color]` pairs.
+    pub fn from_json(json: JsonValue) -> Result<Self> {
+        if let Some(s) = json.as_str() {
+            return ColorRamp::from_str(s);
+        }
+
+        let invalid = |value: &JsonValue| Error::InvalidValue { expected: "color ramp stop", value: value.dump() };
+        if !json.is_array() {
+            return Err(invalid(&json));
+        }
+
+        if json.members().all(|m| m.is_string()) {
+            let colors = json.members().map(|m| Color::from_str(m.as_str().unwrap())).collect::<Result<Vec<_>>>()?;
+            return ColorRamp::even(&colors);
+        }
+
+        let mut stops = Vec::new();
+        for stop in json.members() {
+            match (stop[0].as_f64(), stop[1].as_str()) {
+                (Some(position), Some(color)) => stops.push((position, Color::from_str(color)?)),
+                _ => return Err(invalid(stop)),
+            }
+        }
+        return ColorRamp::new(stops);
+    }
+
+    /// Getter for the positions and colors of the ramp, in order.
+    pub fn stops(&self) -> &[(f64, Color)] {
+        &self.stops
+    }
+
+    /// Returns the color at a value, blended between the stops either side of it.
+    /// Values beyond the first or last stop take its color.
+    pub fn color_at(&self, value: f64) -> Color {
+        let after = self.stops.iter().position(|(p, _)| *p > value);
+        return match after {
+            Some(0) => self.stops[0].1,
+            None => self.stops[self.stops.len() - 1].1,
+            Some(i) => {
+                let (start, from) = self.stops[i - 1];
+                let (end, to) = self.stops[i];
+                Color::interpolate(from, to, (value - start) / (end - start))
+            }
+        };
+    }
+}
+
+impl Default for ColorRamp {
+    fn default() -> Self {
+        ColorRamp::greyscale()
+    }
+}
+
+/// Parses the name of a built in ramp, or a comma separated list of colors spread evenly along the ramp.
+impl std::str::FromStr for ColorRamp {
+    type Err = Error;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if let Some(ramp) = ColorRamp::named(s) {
+            return Ok(ramp);
+        }
+
+        let colors = s.split(',').map(Color::from_str).collect::<Result<Vec<_>>>()?;
+        return ColorRamp::even(&colors);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn ramps_blend_between_stops() {
+        let ramp: ColorRamp = "#000000, #ff0000, #ffffff".parse().unwrap();
+        assert_eq!(ramp.color_at(-1.0), Color::from([0, 0, 0, 255]));
+        assert_eq!(ramp.color_at(0.25), Color::from([128, 0, 0, 255]));
+        assert_eq!(ramp.color_at(0.5), Color::from([255, 0, 0, 255]));
+        assert_eq!(ramp.color_at(2.0), Color::from([255, 255, 255, 255]));
+
+        let single = ColorRamp::new(vec![(0.3, Color::from([1, 2, 3, 4]))]).unwrap();
+        assert_eq!(single.color_at(0.0), single.color_at(1.0));
+
+        let unordered = ColorRamp::new(vec![(1.0, Color::from([255, 255, 255, 255])), (0.0, Color::from([0, 0, 0, 255]))]).unwrap();
+        assert_eq!(unordered, ColorRamp::greyscale());
+
+        assert!(ColorRamp::new(vec![]).is_err());
+        assert!(ColorRamp::new(vec![(1.5, Color::from([0, 0, 0, 0]))]).is_err());
+        assert!("#000000,blue".parse::<ColorRamp>().is_err());
+    }
+
+    #[test]
+    fn ramps_from_json() {
+        assert_eq!("Classic".parse::<ColorRamp>().unwrap(), ColorRamp::classic());
+        assert_eq!(ColorRamp::from_json(json::parse(r#""bathymetric""#).unwrap()).unwrap(), ColorRamp::bathymetric());
+        assert_eq!(ColorRamp::from_json(json::parse(r##"["#000000", "#ffffff"]"##).unwrap()).unwrap(), ColorRamp::greyscale());
+
+        let ramp = ColorRamp::from_json(json::parse(r##"[[0, "#000000"], [0.25, "#ff0000"], [1, "#ffffff"]]"##).unwrap()).unwrap();
+        assert_eq!(ramp.color_at(0.25), Color::from([255, 0, 0, 255]));
+
+        assert!(ColorRamp::from_json(json::parse(r##"[[0, "#000000"], ["#ffffff"]]"##).unwrap()).is_err());
+        assert!(ColorRamp::from_json(json::parse("{}").unwrap()).is_err());
+        assert!(ColorRamp::from_file("samples/missing_ramp.json").is_err());
+    }
+}
@@ -0,0 +1,75 @@
+use crate::vector::Vector;
+
+/// Space taken by each character along a line of text, as a fraction of the text height.
+const ADVANCE: f64 = 0.7;
+
+/// Strokes of each supported character, in a box half as wide as it is tall with y pointing down.
+fn glyph(c: char) -> &'static [&'static [(f64, f64)]] {
+    match c {
+        '0' => &[&[(0.1, 0.0), (0.4, 0.0), (0.5, 0.15), (0.5, 0.85), (0.4, 1.0), (0.1, 1.0), (0.0, 0.85), (0.0, 0.15), (0.1, 0.0)]],
+        '1' => &[&[(0.1, 0.2), (0.3, 0.0), (0.3, 1.0)], &[(0.1, 1.0), (0.5, 1.0)]],
+        '2' => &[&[(0.0, 0.15), (0.1, 0.0), (0.4, 0.0), (0.5, 0.15), (0.5, 0.35), (0.0, 1.0), (0.5, 1.0)]],
+        '3' => &[&[(0.0, 0.0), (0.5, 0.0), (0.2, 0.4), (0.4, 0.4), (0.5, 0.55), (0.5, 0.85), (0.4, 1.0), (0.1, 1.0), (0.0, 0.85)]],
+        '4' => &[&[(0.4, 1.0), (0.4, 0.0), (0.0, 0.7), (0.5, 0.7)]],
+        '5' => &[&[(0.5, 0.0), (0.0, 0.0), (0.0, 0.45), (0.4, 0.45), (0.5, 0.6), (0.5, 0.85), (0.4, 1.0), (0.0, 1.0)]],
+        '6' => &[&[(0.45, 0.0), (0.15, 0.0), (0.0, 0.25), (0.0, 0.85), (0.1, 1.0), (0.4, 1.0), (0.5, 0.85), (0.5, 0.6), (0.4, 0.45), (0.0, 0.45)]],
+        '7' => &[&[(0.0, 0.0), (0.5, 0.0), (0.15, 1.0)]],
+        '8' => &[
+            &[(0.4, 0.45), (0.5, 0.33), (0.5, 0.12), (0.4, 0.0), (0.1, 0.0), (0.0, 0.12), (0.0, 0.33), (0.1, 0.45), (0.4, 0.45)],
+            &[(0.4, 0.45), (0.5, 0.57), (0.5, 0.88), (0.4, 1.0), (0.1, 1.0), (0.0, 0.88), (0.0, 0.57), (0.1, 0.45)],
+        ],
+        '9' => &[&[(0.5, 0.55), (0.1, 0.55), (0.0, 0.4), (0.0, 0.15), (0.1, 0.0), (0.4, 0.0), (0.5, 0.15), (0.5, 0.75), (0.35, 1.0), (0.05, 1.0)]],
+        '-' => &[&[(0.05, 0.5), (0.45, 0.5)]],
+        '.' => &[&[(0.25, 0.95), (0.25, 1.0)]],
+        _ => &[],
+    }
+}
+
+/// Length of a line of text `size` pixels tall.
+pub fn width(text: &str, size: f64) -> f64 {
+    let characters = text.chars().count() as f64;
+    return ((characters * ADVANCE - (ADVANCE - 0.5)) * size).max(0.0);
+}
+
+/// Strokes that write a line of text `size` pixels tall, centered on `center` and reading along `angle` radians
+/// clockwise from the x axis. Only digits, `-` and `.` are drawn, other characters are left as spaces.
+pub fn strokes(text: &str, size: f64, center: Vector<2>, angle: f64) -> Vec<Vec<Vector<2>>> {
+    let (sin, cos) = angle.sin_cos();
+    let start = -width(text, size) / 2.0;
+
+    let mut strokes = Vec::new();
+    for (i, c) in text.chars().enumerate() {
+        for stroke in glyph(c) {
+            strokes.push(
+                stroke
+                    .iter()
+                    .map(|(x, y)| {
+                        let (x, y) = (start + (i as f64 * ADVANCE + x) * size, (y - 0.5) * size);
+                        center + Vector::from([x * cos - y * sin, x * sin + y * cos])
+                    })
+                    .collect(),
+            );
+        }
+    }
+
+    return strokes;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn text_is_centered_and_rotated() {
+        assert_eq!(width("", 10.0), 0.0);
+        assert!((width("12", 10.0) - 12.0).abs() < 1e-9);
+
+        let level = strokes("-", 10.0, Vector::from([50.0, 50.0]), 0.0);
+        assert_eq!(level, vec![vec![Vector::from([48.0, 50.0]), Vector::from([52.0, 50.0])]]);
+
+        // Turned a quarter clockwise the text reads down the image.
+        let down = strokes("-", 10.0, Vector::from([50.0, 50.0]), std::f64::consts::FRAC_PI_2);
+        assert!((down[0][0] - Vector::from([50.0, 48.0])).magnitude() < 1e-9);
+        assert_eq!(strokes("1 1", 10.0, Vector::new(), 0.0).len(), 4);
+    }
+}
@@ -0,0 +1,20 @@
+#![allow(clippy::needless_return)]
+
+extern crate png;
+extern crate rand;
+extern crate rand_chacha;
+extern crate json;
+
+pub mod color;
+pub mod error;
+pub mod image;
+pub mod math;
+pub mod topography;
+pub mod vector;
+pub mod region;
+pub mod project;
+pub mod roads;
+
+mod section;
+
+pub use error::{Error, Result};
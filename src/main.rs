@@ -1,14 +1,708 @@
-extern crate png;
-extern crate rand;
-extern crate json;
+#![allow(clippy::needless_return)]
 
-pub mod color;
-pub mod image;
-pub mod math;
-pub mod topography;
-pub mod vector;
-pub mod region;
+use eldorado::Error;
+use eldorado::image::{ColorRamp, Image};
+use eldorado::project::Project;
+use eldorado::region::Configuration;
+use eldorado::topography::{self, isolines, HeightMap};
+use eldorado::vector::Vector;
+
+use std::collections::HashMap;
+use std::str::FromStr;
+
+const USAGE: &str = "\
+Usage: eldorado <command> [flags]
+
+Commands:
+  render    Paint every layer of a json project file into one map
+              --project <json> [--output <png>]
+  region    Shade, outline and decorate the regions of a biome map
+              --biomes <png> --config <json> --output <png>
+  contour   Draw contour lines from a heightmap
+              --heightmap <png> --output <png>
+              [--line-divisions <n>] [--line-color <color>] [--background-color <color>]
+              [--cleaning-factor <n>]
+  isolines  Trace smooth contour lines from a heightmap, written as an svg if the output ends in .svg
+              --heightmap <png> --output <png|svg>
+              [--line-divisions <n>] [--line-color <color>] [--line-width <n>] [--smoothing <n>]
+              [--index-interval <n>] [--index-color <color>] [--index-width <n>] [--labels <true|false>]
+              [--label-color <color>] [--label-size <n>] [--label-spacing <n>] [--label-scale <n>]
+  tanaka    Draw tanaka contour lines from a heightmap
+              --heightmap <png> --output <png>
+              [--line-divisions <n>] [--light-color <color>] [--dark-color <color>]
+              [--background-color <color>] [--light-dir <x,y>] [--cleaning-factor <n>]
+  tint      Color a heightmap by elevation
+              --heightmap <png> --output <png>
+              [--ramp <name|color,color,...>] [--ramp-file <json>] [--line-divisions <n>]
+  shade     Draw monochrome relief shading from a heightmap
+              --heightmap <png> --output <png>
+              [--mode <single|multidirectional>] [--azimuth <degrees>] [--altitude <degrees>]
+              [--z-factor <n>] [--cell-size <n>] [--vertical-scale <n>] [--light-color <color>] [--dark-color <color>]
+              [--background-color <color>]
+  shadow    Draw the shadows cast by a heightmap
+              --heightmap <png> --output <png>
+              [--azimuth <degrees>] [--altitude <degrees>] [--cell-size <n>] [--vertical-scale <n>]
+              [--shadow-color <color>]
+  sky-view  Shade a heightmap by how much of the sky each point sees
+              --heightmap <png> --output <png>
+              [--directions <n>] [--radius <n>] [--cell-size <n>] [--vertical-scale <n>]
+              [--ramp <color,color,...>]
+  terrain   Generate a heightmap from seeded fractal noise, written as a 16 bit grayscale png
+              --output <png> [--width <n>] [--height <n>]
+              [--algorithm <perlin|simplex|ridged|diamond_square>] [--seed <n>] [--scale <n>] [--octaves <n>]
+              [--lacunarity <n>] [--persistence <n>] [--falloff <none|island|continent>] [--falloff-start <n>]
+  erode     Wear a heightmap down with rain and slumping, written as a 16 bit grayscale png
+              --heightmap <png> --output <png>
+              [--droplets <n>] [--seed <n>] [--lifetime <n>] [--inertia <n>] [--capacity <n>] [--erosion <n>]
+              [--deposition <n>] [--evaporation <n>] [--radius <n>] [--thermal-iterations <n>] [--talus-angle <degrees>]
+              [--cell-size <n>] [--vertical-scale <n>]
+  rivers    Trace rivers down a heightmap to the sea or the edge of the map
+              --heightmap <png> --output <png>
+              [--method <d8|dinf>] [--sea-level <n>] [--threshold <n>] [--min-order <n>]
+              [--simplify-tolerance <n>] [--smoothing <n>] [--source-width <n>] [--mouth-width <n>] [--color <color>]
+              [--color-from <json>] [--color-biome <color>]
+  basins    Color or outline the drainage basins of a heightmap
+              --heightmap <png> --output <png>
+              [--method <d8|dinf>] [--sea-level <n>] [--min-area <n>] [--fill <true|false>]
+              [--outline-color <color>] [--outline-thickness <n>]
+  lakes     Fill the depressions of a heightmap with lakes
+              --heightmap <png> --output <png>
+              [--sea-level <n>] [--min-area <n>] [--min-depth <n>] [--color <color>] [--shore-color <color>]
+              [--shore-thickness <n>]
+  slope     Color a heightmap by the steepness of its slopes in degrees
+  aspect    Color a heightmap by the compass bearing its slopes face in degrees
+              --heightmap <png> --output <png>
+              [--cell-size <n>] [--vertical-scale <n>] [--ramp <color,color,...>] [--range <min,max>]
+
+Colors are hex strings such as #40ffff or #00404080. Ramps are the name of a built in ramp (greyscale, classic or
+bathymetric) or comma separated colors spread evenly from the lowest value to the highest.
+
+Exit codes:
+  0  success
+  1  the map or layer could not be generated
+  2  invalid command line
+  3  an input file could not be read
+  4  the output file could not be written";
+
+/// Reasons the tool can fail, each mapping to its own exit code.
+#[derive(Debug)]
+enum Failure {
+    Generate(String),
+    Usage(String),
+    Input(String),
+    Output(String),
+}
+
+impl Failure {
+    fn exit_code(&self) -> i32 {
+        match self {
+            Failure::Generate(_) => 1,
+            Failure::Usage(_) => 2,
+            Failure::Input(_) => 3,
+            Failure::Output(_) => 4,
+        }
+    }
+
+    /// Classifies a library error raised while loading inputs or generating layers.
+    fn from_error(e: Error) -> Self {
+        let message = describe(&e);
+        match e {
+            Error::Io { .. } | Error::Decode { .. } | Error::UnsupportedImage { .. } | Error::Json(_) | Error::Config { .. } => Failure::Input(message),
+            Error::Encode { .. } => Failure::Output(message),
+            _ => Failure::Generate(message),
+        }
+    }
+
+    fn message(&self) -> &str {
+        match self {
+            Failure::Generate(m) | Failure::Usage(m) | Failure::Input(m) | Failure::Output(m) => m,
+        }
+    }
+}
+
+/// Joins an error with the chain of errors that caused it.
+fn describe(e: &dyn std::error::Error) -> String {
+    let mut message = e.to_string();
+    let mut source = e.source();
+    while let Some(s) = source {
+        message += &format!(": {}", s);
+        source = s.source();
+    }
+    return message;
+}
+
+/// The `--flag value` pairs given after the command name.
+struct Flags {
+    values: HashMap<String, String>,
+}
+
+impl Flags {
+    /// Parses flag/value pairs, rejecting any flag not listed in `accepted`.
+    fn parse(args: &[String], accepted: &[&str]) -> Result<Self, Failure> {
+        let mut values = HashMap::new();
+        let mut args = args.iter();
+
+        while let Some(arg) = args.next() {
+            let name = match arg.strip_prefix("--") {
+                Some(name) if accepted.contains(&name) => name,
+                Some(_) => return Err(Failure::Usage(format!("Unknown flag {}", arg))),
+                None => return Err(Failure::Usage(format!("Unexpected argument {}", arg))),
+            };
+
+            let value = match args.next() {
+                Some(v) => v,
+                None => return Err(Failure::Usage(format!("No value given for {}", arg))),
+            };
+
+            if values.insert(name.to_string(), value.clone()).is_some() {
+                return Err(Failure::Usage(format!("{} given more than once", arg)));
+            }
+        }
+
+        return Ok(Flags { values });
+    }
+
+    /// Returns the value of a flag which must be present.
+    fn required(&self, name: &str) -> Result<&str, Failure> {
+        return match self.values.get(name) {
+            Some(v) => Ok(v),
+            None => Err(Failure::Usage(format!("Missing required flag --{}", name))),
+        };
+    }
+
+    /// Parses the value of a flag, or returns `default` if it was not given.
+    fn parse_or<T: FromStr>(&self, name: &str, default: T) -> Result<T, Failure> {
+        return match self.values.get(name) {
+            Some(v) => T::from_str(v).map_err(|_| Failure::Usage(format!("Invalid value {} for --{}", v, name))),
+            None => Ok(default),
+        };
+    }
+
+    /// Parses a `x,y` pair into a vector, or returns `default` if the flag was not given.
+    fn vector_or(&self, name: &str, default: Vector<2>) -> Result<Vector<2>, Failure> {
+        let value = match self.values.get(name) {
+            Some(v) => v,
+            None => return Ok(default),
+        };
+
+        let components: Vec<_> = value.split(',').map(|c| f64::from_str(c.trim())).collect();
+        return match components[..] {
+            [Ok(x), Ok(y)] => Ok(Vector::from([x, y])),
+            _ => Err(Failure::Usage(format!("Invalid vector {} for --{}, expected x,y", value, name))),
+        };
+    }
+}
+
+fn load_heightmap(flags: &Flags) -> Result<HeightMap, Failure> {
+    let path = flags.required("heightmap")?;
+    return HeightMap::from_file(path).map_err(|e| Failure::Input(format!("Could not load heightmap: {}", describe(&e))));
+}
+
+fn write_output(flags: &Flags, image: &Image) -> Result<(), Failure> {
+    let path = flags.required("output")?;
+    return image.write_to_file(path).map_err(|e| Failure::Output(describe(&e)));
+}
+
+fn render(args: &[String]) -> Result<(), Failure> {
+    let flags = Flags::parse(args, &["project", "output"])?;
+
+    let project_path = flags.required("project")?;
+    let project = Project::from_file(project_path)
+        .map_err(|e| Failure::Input(format!("Could not load project: {}", describe(&e))))?;
+
+    let output = match (flags.required("output"), &project.output) {
+        (Ok(path), _) => path.into(),
+        (Err(_), Some(path)) => path.clone(),
+        (Err(e), None) => return Err(e),
+    };
+
+    let map = project.render().map_err(Failure::from_error)?;
+    return map.write_to_file(&output).map_err(|e| Failure::Output(describe(&e)));
+}
+
+fn region(args: &[String]) -> Result<(), Failure> {
+    let flags = Flags::parse(args, &["biomes", "config", "output"])?;
+    flags.required("output")?;
+
+    let config_path = flags.required("config")?;
+    let configuration = Configuration::from_file(config_path)
+        .map_err(|e| Failure::Input(format!("Could not load configuration: {}", describe(&e))))?;
+
+    let biomes_path = flags.required("biomes")?;
+    let biomes = Image::from_file(biomes_path)
+        .map_err(|e| Failure::Input(format!("Could not load biome map: {}", describe(&e))))?;
+
+    let layer = configuration.generate_layer(&biomes).map_err(Failure::from_error)?;
+    return write_output(&flags, &layer);
+}
+
+fn contour(args: &[String]) -> Result<(), Failure> {
+    let flags = Flags::parse(args, &["heightmap", "output", "line-divisions", "line-color", "background-color", "cleaning-factor"])?;
+    flags.required("output")?;
+
+    let default = topography::ContourSettings::default();
+    let settings = topography::ContourSettings {
+        line_divisions: flags.parse_or("line-divisions", default.line_divisions)?,
+        line_color: flags.parse_or("line-color", default.line_color)?,
+        background_color: flags.parse_or("background-color", default.background_color)?,
+        cleaning_factor: flags.parse_or("cleaning-factor", default.cleaning_factor)?,
+    };
+
+    if settings.line_divisions == 0 {
+        return Err(Failure::Usage(String::from("--line-divisions must be greater than 0")));
+    }
+
+    let heightmap = load_heightmap(&flags)?;
+    let layer = topography::generate_contour_layer(&heightmap, settings);
+    return write_output(&flags, &layer);
+}
+
+fn isolines(args: &[String]) -> Result<(), Failure> {
+    let flags = Flags::parse(args, &[
+        "heightmap", "output", "line-divisions", "line-color", "line-width", "smoothing", "index-interval", "index-color",
+        "index-width", "labels", "label-color", "label-size", "label-spacing", "label-scale",
+    ])?;
+    let output = flags.required("output")?;
+
+    let default = topography::IsolineSettings::default();
+    let settings = topography::IsolineSettings {
+        line_divisions: flags.parse_or("line-divisions", default.line_divisions)?,
+        line_color: flags.parse_or("line-color", default.line_color)?,
+        line_width: flags.parse_or("line-width", default.line_width)?,
+        smoothing: flags.parse_or("smoothing", default.smoothing)?,
+        index_interval: flags.parse_or("index-interval", default.index_interval)?,
+        index_color: flags.parse_or("index-color", default.index_color)?,
+        index_width: flags.parse_or("index-width", default.index_width)?,
+        labels: flags.parse_or("labels", default.labels)?,
+        label_color: flags.parse_or("label-color", default.label_color)?,
+        label_size: flags.parse_or("label-size", default.label_size)?,
+        label_spacing: flags.parse_or("label-spacing", default.label_spacing)?,
+        label_scale: flags.parse_or("label-scale", default.label_scale)?,
+    };
+
+    if settings.line_divisions == 0 {
+        return Err(Failure::Usage(String::from("--line-divisions must be greater than 0")));
+    }
+    if settings.label_size.is_nan() || settings.label_size <= 0.0 {
+        return Err(Failure::Usage(String::from("--label-size must be greater than 0")));
+    }
+    if settings.label_spacing.is_nan() || settings.label_spacing < 0.0 {
+        return Err(Failure::Usage(String::from("--label-spacing must not be negative")));
+    }
+
+    let heightmap = load_heightmap(&flags)?;
+    if !output.to_lowercase().ends_with(".svg") {
+        let layer = topography::generate_isoline_layer(&heightmap, settings);
+        return write_output(&flags, &layer);
+    }
+
+    let lines: Vec<_> = isolines::trace(&heightmap, &isolines::levels(settings.line_divisions))
+        .iter()
+        .map(|line| line.smoothed(settings.smoothing))
+        .collect();
+    let svg = isolines::to_svg(&lines, heightmap.width(), heightmap.height(), settings);
+    return std::fs::write(output, svg).map_err(|e| Failure::Output(format!("Could not write {}: {}", output, e)));
+}
+
+fn tanaka(args: &[String]) -> Result<(), Failure> {
+    let flags = Flags::parse(args, &["heightmap", "output", "line-divisions", "light-color", "dark-color", "background-color", "light-dir", "cleaning-factor"])?;
+    flags.required("output")?;
+
+    let default = topography::TanakaSettings::default();
+    let settings = topography::TanakaSettings {
+        line_divisions: flags.parse_or("line-divisions", default.line_divisions)?,
+        light_color: flags.parse_or("light-color", default.light_color)?,
+        dark_color: flags.parse_or("dark-color", default.dark_color)?,
+        background_color: flags.parse_or("background-color", default.background_color)?,
+        light_dir: flags.vector_or("light-dir", default.light_dir)?,
+        cleaning_factor: flags.parse_or("cleaning-factor", default.cleaning_factor)?,
+    };
+
+    if settings.line_divisions == 0 {
+        return Err(Failure::Usage(String::from("--line-divisions must be greater than 0")));
+    }
+    if settings.light_dir.magnitude() == 0.0 {
+        return Err(Failure::Usage(String::from("--light-dir must not be 0,0")));
+    }
+
+    let heightmap = load_heightmap(&flags)?;
+    let layer = topography::generate_tanaka_layer(&heightmap, settings);
+    return write_output(&flags, &layer);
+}
+
+fn tint(args: &[String]) -> Result<(), Failure> {
+    let flags = Flags::parse(args, &["heightmap", "output", "ramp", "ramp-file", "line-divisions"])?;
+    flags.required("output")?;
+
+    let default = topography::HypsometricSettings::default();
+    let ramp = match flags.required("ramp-file") {
+        Ok(path) => ColorRamp::from_file(path).map_err(|e| Failure::Input(format!("Could not load ramp: {}", describe(&e))))?,
+        Err(_) => flags.parse_or("ramp", default.ramp)?,
+    };
+    let line_divisions = match flags.required("line-divisions") {
+        Ok(_) => Some(flags.parse_or("line-divisions", 0)?),
+        Err(_) => None,
+    };
+
+    if line_divisions == Some(0) {
+        return Err(Failure::Usage(String::from("--line-divisions must be greater than 0")));
+    }
+
+    let heightmap = load_heightmap(&flags)?;
+    let layer = topography::generate_hypsometric_layer(&heightmap, topography::HypsometricSettings { ramp, line_divisions });
+    return write_output(&flags, &layer);
+}
+
+fn shade(args: &[String]) -> Result<(), Failure> {
+    let flags = Flags::parse(args, &["heightmap", "output", "mode", "azimuth", "altitude", "z-factor", "cell-size", "vertical-scale", "light-color", "dark-color", "background-color"])?;
+    flags.required("output")?;
+
+    let default = topography::ShadedSettings::default();
+    let settings = topography::ShadedSettings {
+        mode: flags.parse_or("mode", default.mode)?,
+        azimuth: flags.parse_or("azimuth", default.azimuth)?,
+        altitude: flags.parse_or("altitude", default.altitude)?,
+        z_factor: flags.parse_or("z-factor", default.z_factor)?,
+        cell_size: flags.parse_or("cell-size", default.cell_size)?,
+        vertical_scale: flags.parse_or("vertical-scale", default.vertical_scale)?,
+        background_color: flags.parse_or("background-color", default.background_color)?,
+        light_color: flags.parse_or("light-color", default.light_color)?,
+        dark_color: flags.parse_or("dark-color", default.dark_color)?,
+    };
+
+    if !(0.0..=90.0).contains(&settings.altitude) {
+        return Err(Failure::Usage(String::from("--altitude must be between 0 and 90")));
+    }
+    if settings.cell_size <= 0.0 {
+        return Err(Failure::Usage(String::from("--cell-size must be greater than 0")));
+    }
+
+    let heightmap = load_heightmap(&flags)?;
+    let layer = topography::generate_shaded_layer(&heightmap, settings);
+    return write_output(&flags, &layer);
+}
+
+fn shadow(args: &[String]) -> Result<(), Failure> {
+    let flags = Flags::parse(args, &["heightmap", "output", "azimuth", "altitude", "cell-size", "vertical-scale", "shadow-color"])?;
+    flags.required("output")?;
+
+    let default = topography::ShadowSettings::default();
+    let settings = topography::ShadowSettings {
+        azimuth: flags.parse_or("azimuth", default.azimuth)?,
+        altitude: flags.parse_or("altitude", default.altitude)?,
+        cell_size: flags.parse_or("cell-size", default.cell_size)?,
+        vertical_scale: flags.parse_or("vertical-scale", default.vertical_scale)?,
+        shadow_color: flags.parse_or("shadow-color", default.shadow_color)?,
+    };
+
+    if !(0.0..=90.0).contains(&settings.altitude) {
+        return Err(Failure::Usage(String::from("--altitude must be between 0 and 90")));
+    }
+    if settings.cell_size <= 0.0 {
+        return Err(Failure::Usage(String::from("--cell-size must be greater than 0")));
+    }
+
+    let heightmap = load_heightmap(&flags)?;
+    let layer = topography::generate_shadow_layer(&heightmap, settings);
+    return write_output(&flags, &layer);
+}
+
+fn sky_view(args: &[String]) -> Result<(), Failure> {
+    let flags = Flags::parse(args, &["heightmap", "output", "directions", "radius", "cell-size", "vertical-scale", "ramp"])?;
+    flags.required("output")?;
+
+    let default = topography::SkyViewSettings::default();
+    let settings = topography::SkyViewSettings {
+        directions: flags.parse_or("directions", default.directions)?,
+        radius: flags.parse_or("radius", default.radius)?,
+        cell_size: flags.parse_or("cell-size", default.cell_size)?,
+        vertical_scale: flags.parse_or("vertical-scale", default.vertical_scale)?,
+        ramp: flags.parse_or("ramp", default.ramp)?,
+    };
+
+    if settings.cell_size <= 0.0 {
+        return Err(Failure::Usage(String::from("--cell-size must be greater than 0")));
+    }
+
+    let heightmap = load_heightmap(&flags)?;
+    let layer = topography::generate_sky_view_layer(&heightmap, settings);
+    return write_output(&flags, &layer);
+}
+
+fn rivers(args: &[String]) -> Result<(), Failure> {
+    let flags = Flags::parse(args, &[
+        "heightmap", "output", "method", "sea-level", "threshold", "min-order", "simplify-tolerance", "smoothing", "source-width",
+        "mouth-width", "color", "color-from", "color-biome",
+    ])?;
+    flags.required("output")?;
+
+    let default = topography::RiverSettings::default();
+    let settings = topography::RiverSettings {
+        method: flags.parse_or("method", default.method)?,
+        sea_level: flags.parse_or("sea-level", default.sea_level)?,
+        threshold: flags.parse_or("threshold", default.threshold)?,
+        min_order: flags.parse_or("min-order", default.min_order)?,
+        simplify_tolerance: flags.parse_or("simplify-tolerance", default.simplify_tolerance)?,
+        smoothing: flags.parse_or("smoothing", default.smoothing)?,
+        source_width: flags.parse_or("source-width", default.source_width)?,
+        mouth_width: flags.parse_or("mouth-width", default.mouth_width)?,
+        color: flags.parse_or("color", default.color)?,
+    };
+
+    if settings.threshold < 1.0 {
+        return Err(Failure::Usage(String::from("--threshold must be at least 1")));
+    }
+
+    // Rivers may be drawn in the color of a biome, such as the water of the region configuration.
+    let settings = match flags.required("color-from") {
+        Ok(_) if flags.required("color").is_ok() => {
+            return Err(Failure::Usage(String::from("--color-from cannot be given along with --color")));
+        }
+        Ok(path) => {
+            let configuration = Configuration::from_file(path)
+                .map_err(|e| Failure::Input(format!("Could not load configuration: {}", describe(&e))))?;
+            let biome = flags.parse_or("color-biome", default.color)?;
+            let color = match configuration.biome(biome) {
+                Some(b) => b.color,
+                None => return Err(Failure::Input(format!("{} has no biome with the key color given by --color-biome", path))),
+            };
+            topography::RiverSettings { color, ..settings }
+        }
+        Err(_) => settings,
+    };
+
+    let heightmap = load_heightmap(&flags)?;
+    let layer = topography::generate_river_layer(&heightmap, settings);
+    return write_output(&flags, &layer);
+}
+
+fn basins(args: &[String]) -> Result<(), Failure> {
+    let flags = Flags::parse(args, &["heightmap", "output", "method", "sea-level", "min-area", "fill", "outline-color", "outline-thickness"])?;
+    flags.required("output")?;
+
+    let default = topography::BasinSettings::default();
+    let settings = topography::BasinSettings {
+        method: flags.parse_or("method", default.method)?,
+        sea_level: flags.parse_or("sea-level", default.sea_level)?,
+        min_area: flags.parse_or("min-area", default.min_area)?,
+        fill: flags.parse_or("fill", default.fill)?,
+        outline_color: flags.parse_or("outline-color", default.outline_color)?,
+        outline_thickness: flags.parse_or("outline-thickness", default.outline_thickness)?,
+    };
+
+    let heightmap = load_heightmap(&flags)?;
+    let layer = topography::generate_basin_layer(&heightmap, settings);
+    return write_output(&flags, &layer);
+}
+
+fn lakes(args: &[String]) -> Result<(), Failure> {
+    let flags = Flags::parse(args, &["heightmap", "output", "sea-level", "min-area", "min-depth", "color", "shore-color", "shore-thickness"])?;
+    flags.required("output")?;
+
+    let default = topography::LakeSettings::default();
+    let settings = topography::LakeSettings {
+        sea_level: flags.parse_or("sea-level", default.sea_level)?,
+        min_area: flags.parse_or("min-area", default.min_area)?,
+        min_depth: flags.parse_or("min-depth", default.min_depth)?,
+        color: flags.parse_or("color", default.color)?,
+        shore_color: flags.parse_or("shore-color", default.shore_color)?,
+        shore_thickness: flags.parse_or("shore-thickness", default.shore_thickness)?,
+    };
+
+    let heightmap = load_heightmap(&flags)?;
+    let layer = topography::generate_lake_layer(&heightmap, settings);
+    return write_output(&flags, &layer);
+}
+
+fn terrain(args: &[String]) -> Result<(), Failure> {
+    let flags = Flags::parse(args, &[
+        "output", "width", "height", "algorithm", "seed", "scale", "octaves", "lacunarity", "persistence", "falloff", "falloff-start",
+    ])?;
+    let output = flags.required("output")?;
+    let width: usize = flags.parse_or("width", 512)?;
+    let height: usize = flags.parse_or("height", 512)?;
+
+    let default = topography::TerrainSettings::default();
+    let settings = topography::TerrainSettings {
+        algorithm: flags.parse_or("algorithm", default.algorithm)?,
+        seed: flags.parse_or("seed", default.seed)?,
+        scale: flags.parse_or("scale", default.scale)?,
+        octaves: flags.parse_or("octaves", default.octaves)?,
+        lacunarity: flags.parse_or("lacunarity", default.lacunarity)?,
+        persistence: flags.parse_or("persistence", default.persistence)?,
+        falloff: flags.parse_or("falloff", default.falloff)?,
+        falloff_start: flags.parse_or("falloff-start", default.falloff_start)?,
+    };
+
+    if width == 0 || height == 0 {
+        return Err(Failure::Usage(String::from("--width and --height must be greater than 0")));
+    }
+    if settings.scale <= 0.0 {
+        return Err(Failure::Usage(String::from("--scale must be greater than 0")));
+    }
+
+    let heightmap = topography::generate_heightmap(width, height, settings);
+    return heightmap.write_to_file(output).map_err(|e| Failure::Output(describe(&e)));
+}
+
+fn erode(args: &[String]) -> Result<(), Failure> {
+    let flags = Flags::parse(args, &[
+        "heightmap", "output", "droplets", "seed", "lifetime", "inertia", "capacity", "erosion", "deposition", "evaporation", "radius",
+        "thermal-iterations", "talus-angle", "cell-size", "vertical-scale",
+    ])?;
+    let output = flags.required("output")?;
+
+    let default = topography::HydraulicSettings::default();
+    let hydraulic = topography::HydraulicSettings {
+        iterations: flags.parse_or("droplets", default.iterations)?,
+        seed: flags.parse_or("seed", default.seed)?,
+        lifetime: flags.parse_or("lifetime", default.lifetime)?,
+        inertia: flags.parse_or("inertia", default.inertia)?,
+        capacity: flags.parse_or("capacity", default.capacity)?,
+        erosion: flags.parse_or("erosion", default.erosion)?,
+        deposition: flags.parse_or("deposition", default.deposition)?,
+        evaporation: flags.parse_or("evaporation", default.evaporation)?,
+        radius: flags.parse_or("radius", default.radius)?,
+        ..default
+    };
+
+    let default = topography::ThermalSettings::default();
+    let thermal = topography::ThermalSettings {
+        iterations: flags.parse_or("thermal-iterations", default.iterations)?,
+        talus_angle: flags.parse_or("talus-angle", default.talus_angle)?,
+        cell_size: flags.parse_or("cell-size", default.cell_size)?,
+        vertical_scale: flags.parse_or("vertical-scale", default.vertical_scale)?,
+        ..default
+    };
+
+    if !(0.0..=1.0).contains(&hydraulic.inertia) || !(0.0..=1.0).contains(&hydraulic.evaporation) {
+        return Err(Failure::Usage(String::from("--inertia and --evaporation must be between 0 and 1")));
+    }
+    if !(0.0..90.0).contains(&thermal.talus_angle) {
+        return Err(Failure::Usage(String::from("--talus-angle must be between 0 and 90")));
+    }
+    if thermal.cell_size <= 0.0 {
+        return Err(Failure::Usage(String::from("--cell-size must be greater than 0")));
+    }
+    if thermal.vertical_scale.is_nan() || thermal.vertical_scale <= 0.0 {
+        return Err(Failure::Usage(String::from("--vertical-scale must be greater than 0")));
+    }
+
+    let progress = |pass: &'static str| {
+        move |done: usize, total: usize| {
+            eprint!("\r{} erosion {}%", pass, done * 100 / total);
+            if done == total {
+                eprintln!();
+            }
+        }
+    };
+    let heightmap = load_heightmap(&flags)?;
+    let heightmap = topography::erosion::hydraulic(&heightmap, hydraulic, progress("hydraulic"));
+    let heightmap = topography::erosion::thermal(&heightmap, thermal, progress("thermal"));
+
+    return heightmap.write_to_file(output).map_err(|e| Failure::Output(describe(&e)));
+}
+
+fn analysis(args: &[String], analysis: topography::Analysis) -> Result<(), Failure> {
+    let flags = Flags::parse(args, &["heightmap", "output", "cell-size", "vertical-scale", "ramp", "range"])?;
+    flags.required("output")?;
+
+    let default = topography::AnalysisSettings::default();
+    let (min, max) = analysis.range();
+    let range = flags.vector_or("range", Vector::from([min, max]))?;
+    let settings = topography::AnalysisSettings {
+        analysis,
+        cell_size: flags.parse_or("cell-size", default.cell_size)?,
+        vertical_scale: flags.parse_or("vertical-scale", default.vertical_scale)?,
+        ramp: flags.parse_or("ramp", default.ramp)?,
+        range: Some((range[0], range[1])),
+    };
+
+    if settings.cell_size <= 0.0 {
+        return Err(Failure::Usage(String::from("--cell-size must be greater than 0")));
+    }
+
+    let heightmap = load_heightmap(&flags)?;
+    let layer = topography::generate_analysis_layer(&heightmap, settings);
+    return write_output(&flags, &layer);
+}
+
+fn run(args: &[String]) -> Result<(), Failure> {
+    let (command, rest) = match args.split_first() {
+        Some((command, rest)) => (command.as_str(), rest),
+        None => return Err(Failure::Usage(String::from("No command given"))),
+    };
+
+    return match command {
+        "render" => render(rest),
+        "region" => region(rest),
+        "contour" => contour(rest),
+        "isolines" => isolines(rest),
+        "tanaka" => tanaka(rest),
+        "tint" => tint(rest),
+        "shade" => shade(rest),
+        "shadow" => shadow(rest),
+        "sky-view" => sky_view(rest),
+        "terrain" => terrain(rest),
+        "erode" => erode(rest),
+        "rivers" => rivers(rest),
+        "basins" => basins(rest),
+        "lakes" => lakes(rest),
+        "slope" => analysis(rest, topography::Analysis::Slope),
+        "aspect" => analysis(rest, topography::Analysis::Aspect),
+        _ => Err(Failure::Usage(format!("Unknown command {}", command))),
+    };
+}
 
 fn main() {
-    println!("Hello, world!");
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    if args.iter().any(|a| a == "--help" || a == "-h") {
+        println!("{}", USAGE);
+        return;
+    }
+
+    if let Err(failure) = run(&args) {
+        eprintln!("error: {}", failure.message());
+        if let Failure::Usage(_) = failure {
+            eprintln!("\n{}", USAGE);
+        }
+        std::process::exit(failure.exit_code());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn flag_parsing() {
+        let flags = Flags::parse(&args(&["--output", "a.png", "--light-dir", "1, -2"]), &["output", "light-dir"]).unwrap();
+        assert_eq!(flags.required("output").unwrap(), "a.png");
+        assert_eq!(flags.vector_or("light-dir", Vector::new()).unwrap(), Vector::from([1.0, -2.0]));
+        assert_eq!(flags.parse_or("line-divisions", 32u16).unwrap(), 32);
+        assert!(flags.required("heightmap").is_err());
+
+        assert!(Flags::parse(&args(&["--unknown", "1"]), &["output"]).is_err());
+        assert!(Flags::parse(&args(&["--output"]), &["output"]).is_err());
+        assert!(Flags::parse(&args(&["--output", "a", "--output", "b"]), &["output"]).is_err());
+    }
+
+    #[test]
+    fn exit_codes() {
+        assert_eq!(run(&args(&[])).unwrap_err().exit_code(), 2);
+        assert_eq!(run(&args(&["paint"])).unwrap_err().exit_code(), 2);
+        assert_eq!(run(&args(&["contour", "--output", "a.out.png"])).unwrap_err().exit_code(), 2);
+        assert_eq!(run(&args(&["contour", "--heightmap", "missing.png", "--output", "a.out.png"])).unwrap_err().exit_code(), 3);
+        assert_eq!(run(&args(&["contour", "--heightmap", "missing.png", "--output", "a.out.png", "--line-color", "red"])).unwrap_err().exit_code(), 2);
+        assert_eq!(run(&args(&["rivers", "--heightmap", "missing.png", "--output", "a.out.png", "--color", "#0000ff", "--color-from", "samples/biomes.json"])).unwrap_err().exit_code(), 2);
+        assert_eq!(run(&args(&["tanaka", "--heightmap", "missing.png", "--output", "a.out.png", "--light-dir", "0,0"])).unwrap_err().exit_code(), 2);
+        assert_eq!(run(&args(&["erode", "--heightmap", "missing.png", "--output", "a.out.png", "--vertical-scale", "0"])).unwrap_err().exit_code(), 2);
+        assert_eq!(run(&args(&["isolines", "--heightmap", "missing.png", "--output", "a.out.png", "--label-size", "-2"])).unwrap_err().exit_code(), 2);
+        assert_eq!(run(&args(&["isolines", "--heightmap", "missing.png", "--output", "a.out.png", "--label-spacing", "-1"])).unwrap_err().exit_code(), 2);
+    }
 }
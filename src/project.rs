@@ -0,0 +1,580 @@
+use json::JsonValue;
+
+use crate::error::{Error, Result};
+use crate::image::{BlendMode, Color, Image};
+use crate::region::Configuration;
+use crate::roads::{CostSurface, Network, RoutingSettings};
+use crate::section::Section;
+use crate::topography::{self, AnalysisSettings, BasinSettings, ContourSettings, HeightMap, HypsometricSettings, IsolineSettings, LakeSettings, RiverSettings, ShadedSettings, ShadowSettings, SkyViewSettings, TanakaSettings};
+use crate::vector::Vector;
+use std::ffi::OsStr;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// What a single layer in a project draws.
+pub enum LayerKind {
+    /// Shaded and outlined regions of the biome map.
+    Region(Configuration),
+    /// Glyphs scattered over the regions of the biome map.
+    Glyphs(Configuration),
+    /// Colors of the heightmap by elevation.
+    Hypsometric(HypsometricSettings),
+    /// Relief shading of the heightmap.
+    Hillshade(ShadedSettings),
+    /// Shadows cast by the heightmap.
+    Shadow(ShadowSettings),
+    /// Shading of the heightmap by how much of the sky each point sees.
+    SkyView(SkyViewSettings),
+    /// Slope or aspect of the heightmap along a color ramp.
+    Analysis(AnalysisSettings),
+    /// Contour lines of the heightmap.
+    Contours(ContourSettings),
+    /// Smooth contour lines traced through the heightmap.
+    Isolines(IsolineSettings),
+    /// Tanaka contour lines of the heightmap.
+    Tanaka(TanakaSettings),
+    /// Rivers traced down the heightmap.
+    Rivers(RiverSettings),
+    /// Drainage basins of the heightmap.
+    Basins(BasinSettings),
+    /// Lakes filling the depressions of the heightmap.
+    Lakes(LakeSettings),
+    /// Roads drawn along a network of waypoints. Roads marked to be routed find their way over
+    /// the heightmap and biome map, with biome travel costs taken from `costs`.
+    Roads {
+        network: Network,
+        routing: RoutingSettings,
+        costs: Option<Configuration>,
+    },
+}
+
+/// A layer in the project stack along with how it is composited onto the layers below it.
+pub struct Layer {
+    pub kind: LayerKind,
+    pub opacity: f64,
+    pub blend: BlendMode,
+}
+
+/// A map made of input data and an ordered stack of layers, painted bottom to top.
+pub struct Project {
+    /// Path of the biome map used by region and glyph layers.
+    pub biomes: Option<PathBuf>,
+    /// Path of the heightmap used by the topographical layers.
+    pub heightmap: Option<PathBuf>,
+    /// Lakes found in the heightmap and painted into the biome map in their `color`, so they are drawn like that biome.
+    pub lakes: Option<LakeSettings>,
+    /// Color the layers are painted on to.
+    pub background: Color,
+    /// Where the rendered map should be written.
+    pub output: Option<PathBuf>,
+    pub layers: Vec<Layer>,
+}
+
+impl Project {
+    /// Loads a project from a json file.
+    pub fn from_file(file: impl AsRef<OsStr>) -> Result<Self> {
+        let path = PathBuf::from(file.as_ref());
+        let io_error = |source| Error::Io { path: path.clone(), source };
+
+        let mut file = std::fs::File::open(&path).map_err(io_error)?;
+        let mut string = String::new();
+        file.read_to_string(&mut string).map_err(io_error)?;
+
+        // Paths in the project are relative to the project file, wherever it is rendered from.
+        let json = json::parse(&string)?;
+        return Self::from_json_in(json, path.parent().unwrap_or_else(|| Path::new("")));
+    }
+
+    /// Parses a project from a json string.
+    pub fn from_string(string: impl AsRef<str>) -> Result<Self> {
+        let json = json::parse(string.as_ref())?;
+        return Self::from_json(json);
+    }
+
+    /// Builds a project from parsed json, with paths relative to the current directory.
+    pub fn from_json(json: JsonValue) -> Result<Self> {
+        return Self::from_json_in(json, Path::new(""));
+    }
+
+    /// Builds a project from parsed json, with relative paths resolved against `base`.
+    fn from_json_in(json: JsonValue, base: &Path) -> Result<Self> {
+        let root = Section { json: &json, prefix: String::new(), base };
+        let inputs = Section { json: &json["inputs"], prefix: String::from("inputs."), base };
+
+        let biomes = inputs.path("biomes")?;
+        let heightmap = inputs.path("heightmap")?;
+        let output = root.path("output")?;
+        let background = root.color("background", Color::from([0, 0, 0, 0]))?;
+
+        let lakes = match inputs.json.has_key("lakes") {
+            true if biomes.is_none() || heightmap.is_none() => {
+                return Err(inputs.error("lakes", "lakes require both a biome map and a heightmap input"));
+            }
+            true => Some(lake_settings(&Section { json: &inputs.json["lakes"], prefix: inputs.key("lakes."), base })?),
+            false => None,
+        };
+
+        if !json["layers"].is_array() {
+            return Err(root.error("layers", "must be an array of layers"));
+        }
+
+        let mut layers = Vec::new();
+        for (i, settings) in json["layers"].members().enumerate() {
+            let settings = Section { json: settings, prefix: format!("layers[{}].", i), base };
+
+            let kind = match settings.json["type"].as_str() {
+                Some(t) => t,
+                None => return Err(settings.error("type", "every layer must have a type")),
+            };
+
+            let kind = match kind {
+                "region" | "glyphs" => {
+                    if biomes.is_none() {
+                        return Err(settings.error("type", format!("{} layers require a biome map input", kind)));
+                    }
+
+                    let configuration = load_configuration(&settings, "config")?;
+
+                    if kind == "region" {
+                        LayerKind::Region(configuration)
+                    } else {
+                        LayerKind::Glyphs(configuration)
+                    }
+                }
+
+                "roads" => {
+                    let network = match &settings.json["config"] {
+                        JsonValue::Object(_) => Network::from_json(settings.json["config"].clone()),
+                        c => match c.as_str() {
+                            Some(path) => Network::from_file(base.join(path)),
+                            None => return Err(settings.error("config", "must be a road network file or object")),
+                        },
+                    };
+
+                    let network = network.map_err(|e| Error::Config {
+                        key: settings.key("config"),
+                        biome: None,
+                        reason: String::from("invalid road network"),
+                        source: Some(Box::new(e)),
+                    })?;
+
+                    let costs = match settings.json.has_key("costs") {
+                        true => Some(load_configuration(&settings, "costs")?),
+                        false => None,
+                    };
+
+                    let routing = Section { json: &settings.json["routing"], prefix: settings.key("routing."), base };
+                    let default = RoutingSettings::default();
+                    let routing = RoutingSettings {
+                        height_scale: routing.number("height_scale", default.height_scale)?,
+                        slope_cost: routing.number("slope_cost", default.slope_cost)?,
+                        max_grade: routing.number("max_grade", default.max_grade)?,
+                        simplify_tolerance: routing.number("simplify_tolerance", default.simplify_tolerance)?,
+                    };
+
+                    LayerKind::Roads { network, routing, costs }
+                }
+
+                "hypsometric" | "hillshade" | "shadow" | "sky_view" | "slope" | "aspect" | "contours" | "isolines" | "tanaka" | "rivers" | "basins" | "lakes" if heightmap.is_none() => {
+                    return Err(settings.error("type", format!("{} layers require a heightmap input", kind)));
+                }
+
+                "hypsometric" => {
+                    let default = HypsometricSettings::default();
+                    LayerKind::Hypsometric(HypsometricSettings {
+                        ramp: settings.ramp("ramp", default.ramp)?,
+                        line_divisions: match settings.json.has_key("line_divisions") {
+                            true => Some(settings.divisions(0)?),
+                            false => None,
+                        },
+                    })
+                }
+
+                "hillshade" => {
+                    let default = ShadedSettings::default();
+                    let shaded = ShadedSettings {
+                        mode: settings.named("mode", default.mode, "unknown shading mode")?,
+                        azimuth: settings.number("azimuth", default.azimuth)?,
+                        altitude: settings.number("altitude", default.altitude)?,
+                        z_factor: settings.number("z_factor", default.z_factor)?,
+                        cell_size: settings.number("cell_size", default.cell_size)?,
+                        vertical_scale: settings.number("vertical_scale", default.vertical_scale)?,
+                        background_color: settings.color("background_color", default.background_color)?,
+                        light_color: settings.color("light_color", default.light_color)?,
+                        dark_color: settings.color("dark_color", default.dark_color)?,
+                    };
+
+                    if shaded.altitude > 90.0 {
+                        return Err(settings.error("altitude", "must be between 0 and 90"));
+                    }
+                    if shaded.cell_size == 0.0 {
+                        return Err(settings.error("cell_size", "must be greater than 0"));
+                    }
+
+                    LayerKind::Hillshade(shaded)
+                }
+
+                "shadow" => {
+                    let default = ShadowSettings::default();
+                    let shadow = ShadowSettings {
+                        azimuth: settings.number("azimuth", default.azimuth)?,
+                        altitude: settings.number("altitude", default.altitude)?,
+                        cell_size: settings.number("cell_size", default.cell_size)?,
+                        vertical_scale: settings.number("vertical_scale", default.vertical_scale)?,
+                        shadow_color: settings.color("shadow_color", default.shadow_color)?,
+                    };
+
+                    if shadow.altitude > 90.0 {
+                        return Err(settings.error("altitude", "must be between 0 and 90"));
+                    }
+                    if shadow.cell_size == 0.0 {
+                        return Err(settings.error("cell_size", "must be greater than 0"));
+                    }
+
+                    LayerKind::Shadow(shadow)
+                }
+
+                "sky_view" => {
+                    let default = SkyViewSettings::default();
+                    let sky_view = SkyViewSettings {
+                        directions: settings.number("directions", default.directions as f64)? as usize,
+                        radius: settings.number("radius", default.radius)?,
+                        cell_size: settings.number("cell_size", default.cell_size)?,
+                        vertical_scale: settings.number("vertical_scale", default.vertical_scale)?,
+                        ramp: settings.ramp("ramp", default.ramp)?,
+                    };
+
+                    if sky_view.cell_size == 0.0 {
+                        return Err(settings.error("cell_size", "must be greater than 0"));
+                    }
+
+                    LayerKind::SkyView(sky_view)
+                }
+
+                "slope" | "aspect" => {
+                    let default = AnalysisSettings::default();
+                    let analysis = AnalysisSettings {
+                        analysis: kind.parse().unwrap(),
+                        cell_size: settings.number("cell_size", default.cell_size)?,
+                        vertical_scale: settings.number("vertical_scale", default.vertical_scale)?,
+                        ramp: settings.ramp("ramp", default.ramp)?,
+                        range: match settings.json.has_key("range") {
+                            true => {
+                                let range = settings.vector("range", Vector::new())?;
+                                Some((range[0], range[1]))
+                            }
+                            false => None,
+                        },
+                    };
+
+                    if analysis.cell_size == 0.0 {
+                        return Err(settings.error("cell_size", "must be greater than 0"));
+                    }
+
+                    LayerKind::Analysis(analysis)
+                }
+
+                "contours" => {
+                    let default = ContourSettings::default();
+                    LayerKind::Contours(ContourSettings {
+                        line_divisions: settings.divisions(default.line_divisions)?,
+                        line_color: settings.color("line_color", default.line_color)?,
+                        background_color: settings.color("background_color", default.background_color)?,
+                        cleaning_factor: settings.number("cleaning_factor", default.cleaning_factor as f64)? as usize,
+                    })
+                }
+
+                "isolines" => {
+                    let default = IsolineSettings::default();
+                    let isolines = IsolineSettings {
+                        line_divisions: settings.divisions(default.line_divisions)?,
+                        line_color: settings.color("line_color", default.line_color)?,
+                        line_width: settings.number("line_width", default.line_width)?,
+                        smoothing: settings.number("smoothing", default.smoothing as f64)? as usize,
+                        index_interval: settings.number("index_interval", default.index_interval as f64)? as u16,
+                        index_color: settings.color("index_color", default.index_color)?,
+                        index_width: settings.number("index_width", default.index_width)?,
+                        labels: settings.flag("labels", default.labels)?,
+                        label_color: settings.color("label_color", default.label_color)?,
+                        label_size: settings.number("label_size", default.label_size)?,
+                        label_spacing: settings.number("label_spacing", default.label_spacing)?,
+                        label_scale: settings.number("label_scale", default.label_scale)?,
+                    };
+
+                    if isolines.label_size == 0.0 {
+                        return Err(settings.error("label_size", "must be greater than 0"));
+                    }
+
+                    LayerKind::Isolines(isolines)
+                }
+
+                "tanaka" => {
+                    let default = TanakaSettings::default();
+                    let tanaka = TanakaSettings {
+                        line_divisions: settings.divisions(default.line_divisions)?,
+                        light_color: settings.color("light_color", default.light_color)?,
+                        dark_color: settings.color("dark_color", default.dark_color)?,
+                        background_color: settings.color("background_color", default.background_color)?,
+                        light_dir: settings.vector("light_dir", default.light_dir)?,
+                        cleaning_factor: settings.number("cleaning_factor", default.cleaning_factor as f64)? as usize,
+                    };
+
+                    if tanaka.light_dir.magnitude() == 0.0 {
+                        return Err(settings.error("light_dir", "must not be 0,0"));
+                    }
+
+                    LayerKind::Tanaka(tanaka)
+                }
+
+                "rivers" => {
+                    let default = RiverSettings::default();
+                    let rivers = RiverSettings {
+                        method: settings.named("method", default.method, "unknown flow method")?,
+                        sea_level: settings.number("sea_level", default.sea_level as f64)? as u16,
+                        threshold: settings.number("threshold", default.threshold)?,
+                        min_order: settings.number("min_order", default.min_order as f64)? as u32,
+                        simplify_tolerance: settings.number("simplify_tolerance", default.simplify_tolerance)?,
+                        smoothing: settings.number("smoothing", default.smoothing as f64)? as usize,
+                        source_width: settings.number("source_width", default.source_width)?,
+                        mouth_width: settings.number("mouth_width", default.mouth_width)?,
+                        color: match settings.json.has_key("color_from") {
+                            true if settings.json.has_key("color") => {
+                                return Err(settings.error("color_from", "cannot be given along with a color"));
+                            }
+                            true => {
+                                let color_from = Section { json: &settings.json["color_from"], prefix: settings.key("color_from."), base };
+                                biome_color(&color_from, default.color)?
+                            }
+                            false => settings.color("color", default.color)?,
+                        },
+                    };
+
+                    if rivers.threshold < 1.0 {
+                        return Err(settings.error("threshold", "must be at least 1"));
+                    }
+
+                    LayerKind::Rivers(rivers)
+                }
+
+                "basins" => {
+                    let default = BasinSettings::default();
+                    LayerKind::Basins(BasinSettings {
+                        method: settings.named("method", default.method, "unknown flow method")?,
+                        sea_level: settings.number("sea_level", default.sea_level as f64)? as u16,
+                        min_area: settings.number("min_area", default.min_area as f64)? as usize,
+                        fill: settings.flag("fill", default.fill)?,
+                        outline_color: settings.color("outline_color", default.outline_color)?,
+                        outline_thickness: settings.number("outline_thickness", default.outline_thickness as f64)? as u64,
+                    })
+                }
+
+                "lakes" => LayerKind::Lakes(lake_settings(&settings)?),
+
+                _ => return Err(settings.error("type", format!("unknown layer type {}", kind))),
+            };
+
+            let opacity = settings.number("opacity", 1.0)?;
+            if !(0.0..=1.0).contains(&opacity) {
+                return Err(settings.error("opacity", "must be between 0 and 1"));
+            }
+
+            let blend = settings.named("blend", BlendMode::Normal, "unknown blend mode")?;
+
+            layers.push(Layer { kind, opacity, blend });
+        }
+
+        return Ok(Project { biomes, heightmap, lakes, background, output, layers });
+    }
+
+    /// Loads the inputs and paints every layer on top of each other into one image.
+    pub fn render(&self) -> Result<Image> {
+        let mut biomes = match &self.biomes {
+            Some(path) => Some(Image::from_file(path)?),
+            None => None,
+        };
+
+        let heightmap = match &self.heightmap {
+            Some(path) => Some(HeightMap::from_file(path)?),
+            None => None,
+        };
+
+        let (width, height) = match (&biomes, &heightmap) {
+            (Some(b), Some(h)) if b.width() != h.width() || b.height() != h.height() => {
+                return Err(Error::DimensionMismatch { expected: (b.width(), b.height()), found: (h.width(), h.height()) });
+            }
+            (Some(b), _) => (b.width(), b.height()),
+            (None, Some(h)) => (h.width(), h.height()),
+            (None, None) => return Err(Error::config("inputs", None, "a biome map or heightmap is needed to size the map")),
+        };
+
+        if let (Some(biomes), Some(heightmap), Some(lakes)) = (&mut biomes, &heightmap, &self.lakes) {
+            topography::lakes::paint(&topography::lakes::find(heightmap, *lakes), biomes, lakes.color)?;
+        }
+
+        let mut map = Image::new(width, height).fill(self.background);
+
+        for layer in &self.layers {
+            // Inputs required by each layer were checked when the project was parsed.
+            let image = match &layer.kind {
+                LayerKind::Region(c) => c.generate_region_layer(biomes.as_ref().unwrap())?,
+                LayerKind::Glyphs(c) => c.generate_glyph_layer(biomes.as_ref().unwrap()),
+                LayerKind::Hypsometric(s) => topography::generate_hypsometric_layer(heightmap.as_ref().unwrap(), s.clone()),
+                LayerKind::Hillshade(s) => topography::generate_shaded_layer(heightmap.as_ref().unwrap(), *s),
+                LayerKind::Shadow(s) => topography::generate_shadow_layer(heightmap.as_ref().unwrap(), *s),
+                LayerKind::SkyView(s) => topography::generate_sky_view_layer(heightmap.as_ref().unwrap(), s.clone()),
+                LayerKind::Analysis(s) => topography::generate_analysis_layer(heightmap.as_ref().unwrap(), s.clone()),
+                LayerKind::Contours(s) => topography::generate_contour_layer(heightmap.as_ref().unwrap(), *s),
+                LayerKind::Isolines(s) => topography::generate_isoline_layer(heightmap.as_ref().unwrap(), *s),
+                LayerKind::Tanaka(s) => topography::generate_tanaka_layer(heightmap.as_ref().unwrap(), *s),
+                LayerKind::Rivers(s) => topography::generate_river_layer(heightmap.as_ref().unwrap(), *s),
+                LayerKind::Basins(s) => topography::generate_basin_layer(heightmap.as_ref().unwrap(), *s),
+                LayerKind::Lakes(s) => topography::generate_lake_layer(heightmap.as_ref().unwrap(), *s),
+                LayerKind::Roads { network, routing, costs } if network.needs_routing() => {
+                    let mut surface = CostSurface::new(width, height, *routing);
+                    if let (Some(biomes), Some(costs)) = (&biomes, costs) {
+                        surface = surface.with_biomes(biomes, costs)?;
+                    }
+                    if let Some(heightmap) = &heightmap {
+                        surface = surface.with_heightmap(heightmap)?;
+                    }
+
+                    let mut network = network.clone();
+                    network.route(&surface)?;
+                    network.generate_layer(width, height)
+                }
+                LayerKind::Roads { network, .. } => network.generate_layer(width, height),
+            };
+
+            map.blend(&image, layer.blend, layer.opacity)?;
+        }
+
+        return Ok(map);
+    }
+}
+
+/// Parses the settings of a lake layer, or of the lakes painted into the biome map.
+fn lake_settings(settings: &Section) -> Result<LakeSettings> {
+    let default = LakeSettings::default();
+    return Ok(LakeSettings {
+        sea_level: settings.number("sea_level", default.sea_level as f64)? as u16,
+        min_area: settings.number("min_area", default.min_area as f64)? as usize,
+        min_depth: settings.number("min_depth", default.min_depth as f64)? as u16,
+        color: settings.color("color", default.color)?,
+        shore_color: settings.color("shore_color", default.shore_color)?,
+        shore_thickness: settings.number("shore_thickness", default.shore_thickness as f64)? as u64,
+    });
+}
+
+/// Loads a region configuration given as either a file path or an inline object.
+fn load_configuration(settings: &Section, key: &str) -> Result<Configuration> {
+    let configuration = match &settings.json[key] {
+        JsonValue::Object(_) => Configuration::from_json_in(settings.json[key].clone(), settings.base),
+        c => match c.as_str() {
+            Some(path) => Configuration::from_file(settings.base.join(path)),
+            None => return Err(settings.error(key, "must be a config file or object")),
+        },
+    };
+
+    return configuration.map_err(|e| Error::Config {
+        key: settings.key(key),
+        biome: None,
+        reason: String::from("invalid region configuration"),
+        source: Some(Box::new(e)),
+    });
+}
+
+/// Looks up the fill color of a biome in a region configuration, so layers can be drawn in the colors of the regions.
+/// The biome is picked by its key color, `default_biome` if none is given.
+fn biome_color(settings: &Section, default_biome: Color) -> Result<Color> {
+    let configuration = load_configuration(settings, "config")?;
+    let key = settings.color("biome", default_biome)?;
+
+    return match configuration.biome(key) {
+        Some(biome) => Ok(biome.color),
+        None => Err(settings.error("biome", "no biome with this key color in the config")),
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn invalid_projects() {
+        assert!(Project::from_string(r#"{ "inputs": {} }"#).is_err());
+        assert!(Project::from_string(r#"{ "layers": [{ "type": "contours" }] }"#).is_err());
+        assert!(Project::from_string(r#"{ "layers": [{ "type": "region", "config": {} }] }"#).is_err());
+        assert!(Project::from_string(r#"{ "inputs": { "heightmap": "h.png" }, "layers": [{ "type": "sketch" }] }"#).is_err());
+        assert!(Project::from_string(r#"{ "inputs": { "heightmap": "h.png" }, "layers": [{ "type": "tanaka", "opacity": 2 }] }"#).is_err());
+        assert!(Project::from_string(r#"{ "inputs": { "heightmap": "h.png" }, "layers": [{ "type": "hillshade", "blend": "dodge" }] }"#).is_err());
+        assert!(Project::from_string(r#"{ "inputs": { "heightmap": "h.png" }, "layers": [{ "type": "hillshade", "altitude": 100 }] }"#).is_err());
+        assert!(Project::from_string(r##"{ "inputs": { "heightmap": "h.png" }, "layers": [{ "type": "sky_view", "ramp": [[0, "#000000"], [2, "#ffffff"]] }] }"##).is_err());
+        assert!(Project::from_string(r##"{ "inputs": { "heightmap": "h.png" }, "layers": [{ "type": "sky_view", "ramp": ["#000000", 1] }] }"##).is_err());
+        assert!(Project::from_string(r##"{ "inputs": { "heightmap": "h.png" }, "layers": [{ "type": "sky_view", "ramp": ["#000000", "#ffffff"] }] }"##).is_ok());
+        assert!(Project::from_string(r#"{ "inputs": { "heightmap": "h.png" }, "layers": [{ "type": "hypsometric", "ramp": "samples/hypsometric.json" }] }"#).is_ok());
+        assert!(Project::from_string(r#"{ "inputs": { "heightmap": "h.png" }, "layers": [{ "type": "hypsometric", "ramp": "samples/missing.json" }] }"#).is_err());
+        assert!(Project::from_string(r#"{ "inputs": { "heightmap": "h.png" }, "layers": [{ "type": "hypsometric", "ramp": "rainbow" }] }"#).is_err());
+        assert!(Project::from_string(r#"{ "inputs": { "heightmap": "h.png" }, "layers": [{ "type": "rivers", "method": "d16" }] }"#).is_err());
+        assert!(Project::from_string(r#"{ "inputs": { "heightmap": "h.png" }, "layers": [{ "type": "rivers", "threshold": 0 }] }"#).is_err());
+        assert!(Project::from_string(r#"{ "inputs": { "heightmap": "h.png" }, "layers": [{ "type": "basins", "fill": "no" }] }"#).is_err());
+        assert!(Project::from_string(r#"{ "inputs": { "heightmap": "h.png" }, "layers": [{ "type": "isolines", "label_size": 0 }] }"#).is_err());
+        assert!(Project::from_string(r#"{ "inputs": { "heightmap": "h.png" }, "layers": [{ "type": "isolines", "label_spacing": -1 }] }"#).is_err());
+        assert!(Project::from_string(r#"{ "inputs": { "heightmap": "h.png", "lakes": {} }, "layers": [{ "type": "lakes" }] }"#).is_err());
+        assert!(Project::from_string(r#"{ "inputs": { "heightmap": "h.png", "biomes": "b.png", "lakes": { "min_area": 20 } }, "layers": [] }"#).is_ok());
+    }
+
+    #[test]
+    fn rivers_take_the_color_of_a_biome() {
+        let color = |layer: &str| {
+            let project = format!(r#"{{ "inputs": {{ "heightmap": "h.png" }}, "layers": [{}] }}"#, layer);
+            return Project::from_string(project).map(|p| match &p.layers[0].kind {
+                LayerKind::Rivers(rivers) => rivers.color,
+                _ => unreachable!(),
+            });
+        };
+
+        let water = r##"{ "#40ffff": { "color": "#2060c0" }, "#00c000": {} }"##;
+        assert_eq!(color(&format!(r#"{{ "type": "rivers", "color_from": {{ "config": {} }} }}"#, water)).unwrap(), Color::from([32, 96, 192]));
+        assert_eq!(color(r##"{ "type": "rivers", "color_from": { "config": "samples/biomes.json", "biome": "#004040" } }"##).unwrap(), Color::from([0, 64, 64, 128]));
+        assert_eq!(color(r#"{ "type": "rivers" }"#).unwrap(), Color::from([64, 255, 255]));
+
+        assert!(color(r##"{ "type": "rivers", "color_from": { "config": "samples/biomes.json", "biome": "#123456" } }"##).is_err());
+        assert!(color(r##"{ "type": "rivers", "color": "#0000ff", "color_from": { "config": "samples/biomes.json" } }"##).is_err());
+    }
+
+    #[test]
+    fn paths_are_relative_to_the_project_file() {
+        let project = Project::from_file("samples/project.json").unwrap();
+        assert_eq!(project.biomes, Some(PathBuf::from("samples/biomes.png")));
+        assert_eq!(project.output, Some(PathBuf::from("samples/project.out.png")));
+        assert!(project.render().is_ok());
+
+        // The same paths given without a project file are relative to the current directory, where they are missing.
+        let json = std::fs::read_to_string("samples/project.json").unwrap();
+        assert!(Project::from_string(json).is_err());
+    }
+
+    #[test]
+    fn render_project() {
+        let project = Project::from_string(r##"{
+            "inputs": { "biomes": "samples/biomes.png" },
+            "background": "#ffffff",
+            "layers": [
+                { "type": "region", "config": "samples/biomes.json", "opacity": 0.75 },
+                { "type": "glyphs", "config": "samples/biomes.json", "blend": "multiply" },
+                { "type": "roads", "config": {
+                    "waypoints": { "a": [300, 185], "b": [395, 280] },
+                    "edges": [{ "from": "a", "to": "b", "style": { "width": 3, "casing_width": 1 }, "route": true }]
+                }, "costs": { "#ffffff": { "travel_cost": "impassable" }, "#c0c0c0": { "travel_cost": 8 } } }
+            ]
+        }"##).unwrap();
+
+        assert_eq!(project.layers.len(), 3);
+        assert_eq!(project.layers[1].blend, BlendMode::Multiply);
+
+        let map = project.render().unwrap();
+        let biomes = Image::from_file("samples/biomes.png").unwrap();
+        assert_eq!((map.width(), map.height()), (biomes.width(), biomes.height()));
+        assert!(map.data().iter().all(|c| c[3] == 255));
+    }
+}
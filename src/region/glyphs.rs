@@ -1,103 +1,403 @@
 use crate::image::Image;
 use rand::rngs::SmallRng;
-use rand::{SeedableRng, RngCore};
+use rand::{SeedableRng, RngCore, Rng};
 use crate::color::Color;
-use std::sync::{Arc, Mutex};
+use crate::error::Error;
+use crate::vector::Vector;
+use super::BiomeSettings;
+use std::path::PathBuf;
 
 const OVERLAP_PREVENTION: usize = 1;
+/// Number of candidates tried around each point before poisson disc sampling gives up on it.
+const POISSON_ATTEMPTS: usize = 30;
+
+/// How the possible glyph locations are spread over the map.
+#[derive(Copy, Clone, Debug, PartialEq, Default)]
+pub enum GlyphPlacement {
+    /// A jittered grid spaced at 1.5 times the glyph size, thinned out at random.
+    #[default]
+    Grid,
+    /// Blue noise from poisson disc sampling, spaced further apart as density decreases.
+    Poisson,
+}
+
+impl GlyphPlacement {
+    /// The name used for this placement in configuration files.
+    pub fn name(&self) -> &'static str {
+        match self {
+            GlyphPlacement::Grid => "grid",
+            GlyphPlacement::Poisson => "poisson",
+        }
+    }
+}
+
+impl std::str::FromStr for GlyphPlacement {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "grid" => Ok(GlyphPlacement::Grid),
+            "poisson" => Ok(GlyphPlacement::Poisson),
+            _ => Err(Error::InvalidValue { expected: "glyph placement", value: s.to_string() }),
+        }
+    }
+}
+
+/// One of the images a biome scatters, picked with a probability proportional to its weight.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GlyphVariant {
+    pub image: PathBuf,
+    pub weight: f64,
+    /// Pixel of the image that stands on the glyphs location, such as the base of a trunk.
+    /// Glyphs without an anchor are centered on their location and stand on their bottom row.
+    pub anchor: Option<(usize, usize)>,
+}
+
+impl GlyphVariant {
+    pub fn new(image: impl Into<PathBuf>, weight: f64) -> Self {
+        GlyphVariant { image: image.into(), weight, anchor: None }
+    }
+
+    /// Sets the pixel of the image that stands on the glyphs location.
+    pub fn anchored(mut self, x: usize, y: usize) -> Self {
+        self.anchor = Some((x, y));
+        return self;
+    }
+}
+
+/// A glyph image ready to be stamped on to the map.
+pub struct PlacedGlyph {
+    /// Position of the top left corner of the image.
+    position: (isize, isize),
+    /// Row of the map the glyph stands on, glyphs standing lower are drawn in front.
+    baseline: isize,
+    image: Image,
+}
 
 pub struct GlyphDistribution {
-    glyph: Image,
-    layer_size: (usize, usize),
-    locations: Vec<(usize, usize)>,
+    glyphs: Vec<PlacedGlyph>,
 }
 
 impl GlyphDistribution {
-    pub fn new(seed: [u8; 32], density: usize, threshold: usize, glyph: &Image, map: &Image, color: Color) -> Self {
-        let mut possible_locations = Vec::new();
-
-        let mut x = 0;
-        while x < map.width() {
-            let mut y = 0;
-            while y < map.height() {
-                possible_locations.push((x, y));
-                y += glyph.height() + glyph.height() / 2 + OVERLAP_PREVENTION;
+    /// `images` holds the loaded image of each of the biomes glyph variants, in the same order.
+    pub fn new(seed: [u8; 32], settings: &BiomeSettings, images: &[&Image], map: &Image, color: Color) -> Self {
+        let mut rng = SmallRng::from_seed(seed);
+
+        // Space locations out for the largest glyph that could be drawn.
+        let max_scale = settings.glyph_scale.0.max(settings.glyph_scale.1);
+        let footprint = (
+            (images.iter().map(|i| i.width()).max().unwrap_or(0) as f64 * max_scale).round() as usize,
+            (images.iter().map(|i| i.height()).max().unwrap_or(0) as f64 * max_scale).round() as usize,
+        );
+
+        let possible_locations = match settings.glyph_placement {
+            GlyphPlacement::Grid => grid_locations(&mut rng, settings.glyph_density, footprint, map, color),
+            GlyphPlacement::Poisson => poisson_locations(&mut rng, settings.glyph_density, footprint, map, color),
+        };
+
+        let threshold = settings.glyph_threshold as f64 / 100.0;
+        let mut glyphs = Vec::new();
+
+        for (x, y) in possible_locations {
+            // Remove out of bounds locations
+            if x <= 0 || y <= 0 {
+                continue;
             }
-            x += glyph.width() + glyph.width() / 2 + OVERLAP_PREVENTION;
-        }
 
-        let rng = Arc::new(Mutex::new(SmallRng::from_seed(seed)));
+            let (glyph, anchor) = vary_glyph(&mut rng, settings, images);
+            let position = match anchor {
+                Some((ax, ay)) => (x - ax, y - ay),
+                None => (x - glyph.width() as isize / 2, y - glyph.height() as isize / 2),
+            };
 
-        possible_locations = possible_locations.into_iter()
-            // Apply random selection
-            .filter(|_| {
-                let r_value = rng.lock().unwrap().next_u32() as usize;
-                (r_value % 100) < density
-            })
-            // Remove locations not in region
-            .filter(|(x, y)| {
-                map.pixel_at(*x as usize, *y as usize).unwrap() == color
-            })
-            // Wiggle the locations a small amount
-            .map(|(x, y)| {
-                let r1 = (rng.lock().unwrap().next_u32() as usize % (glyph.width() - OVERLAP_PREVENTION)) as isize - glyph.width() as isize / 2;
-                let r2 = (rng.lock().unwrap().next_u32() as usize % (glyph.height() - OVERLAP_PREVENTION)) as isize - glyph.height() as isize / 2;
-                (x as isize + r1, y as isize + r2)
-            })
-            // Remove out of bounds locations and revert to usize
-            .filter(|(x, y)| *x > 0 && *y > 0)
-            .map(|(x, y)| (x as usize, y as usize))
             // Remove glyphs which overlap with wrong biomes
-            .filter(|(x, y)| {
-                let gx = *x as isize;
-                let gy = *y as isize;
-
-                let mut total = 0;
-                let mut in_count = 0;
-                for x in 0..glyph.width() {
-                    for y in 0..glyph.height() {
-                        let map_pixel = map.pixel_at(
-                            (gx + x as isize - glyph.width() as isize / 2) as usize,
-                            (gy + y as isize - glyph.height() as isize / 2) as usize,
-                        ).unwrap();
-
-                        if glyph.pixel_at(x, y).unwrap() != Color::from([0, 0, 0, 0]) {
-                            if map_pixel == color {
-                                in_count += 1;
-                            }
-
-                            total += 1;
+            let mut total = 0;
+            let mut in_count = 0;
+            for gx in 0..glyph.width() {
+                for gy in 0..glyph.height() {
+                    let map_pixel = map.pixel_at_isize(position.0 + gx as isize, position.1 + gy as isize);
+
+                    if glyph.pixel_at(gx, gy).unwrap() != Color::from([0, 0, 0, 0]) {
+                        if map_pixel == Some(color) {
+                            in_count += 1;
                         }
+
+                        total += 1;
                     }
                 }
+            }
 
-                let ratio = in_count as f64 / total as f64;
-                return ratio >= (threshold as f64 / 100.0);
-            })
-            .collect();
+            let ratio = in_count as f64 / total as f64;
+            if ratio >= threshold {
+                let baseline = match anchor {
+                    Some(_) => y,
+                    None => position.1 + glyph.height() as isize - 1,
+                };
+                glyphs.push(PlacedGlyph { position, baseline, image: glyph });
+            }
+        }
 
-        return GlyphDistribution {
-            glyph: glyph.clone(),
-            layer_size: (map.width(), map.height()),
-            locations: possible_locations,
-        };
+        return GlyphDistribution { glyphs };
     }
 
-    pub fn to_layer(&self) -> Image {
-        let mut layer = Image::new(self.layer_size.0, self.layer_size.1);
-        for location in &self.locations {
-            let x = match location.0.checked_sub(self.glyph.width() / 2) {
-                Some(v) => v,
-                None => continue,
-            };
+    /// Takes the placed glyphs, in the order they were generated.
+    pub fn into_glyphs(self) -> Vec<PlacedGlyph> {
+        self.glyphs
+    }
+}
 
-            let y = match location.1.checked_sub(self.glyph.height() / 2) {
-                Some(v) => v,
-                None => continue,
-            };
+/// Stamps glyphs from back to front so that glyphs standing lower on the map overlap those above them.
+/// Glyphs on the same row keep their order. Glyphs reaching past the top or left edge are skipped.
+pub fn stamp_depth_sorted(layer: &mut Image, mut glyphs: Vec<PlacedGlyph>) {
+    glyphs.sort_by_key(|g| g.baseline);
+
+    for glyph in glyphs {
+        if glyph.position.0 < 0 || glyph.position.1 < 0 {
+            continue;
+        }
+
+        layer.stamp((glyph.position.0 as usize, glyph.position.1 as usize), &glyph.image);
+    }
+}
+
+/// Picks a glyph variant by weight and randomly flips, scales and tints it as the settings allow.
+/// Random numbers are only drawn for the choices that are enabled.
+/// Returns the glyph along with its anchor moved to match, if it has one.
+fn vary_glyph(rng: &mut SmallRng, settings: &BiomeSettings, images: &[&Image]) -> (Image, Option<(isize, isize)>) {
+    let mut index = 0;
+    if images.len() > 1 {
+        let total: f64 = settings.glyph_images.iter().map(|v| v.weight).sum();
+        if total > 0.0 {
+            let mut choice = rng.gen_range(0.0..total);
+            for (i, variant) in settings.glyph_images.iter().enumerate() {
+                index = i;
+                if choice < variant.weight {
+                    break;
+                }
+                choice -= variant.weight;
+            }
+        }
+    }
+
+    let mut glyph = images[index].clone();
+    let mut anchor = settings.glyph_images.get(index)
+        .and_then(|v| v.anchor)
+        .map(|(x, y)| (x as f64, y as f64));
+
+    if settings.glyph_flip && rng.gen_bool(0.5) {
+        glyph = glyph.flip_horizontal();
+        anchor = anchor.map(|(x, y)| (glyph.width() as f64 - 1.0 - x, y));
+    }
+
+    let (min_scale, max_scale) = settings.glyph_scale;
+    let factor = if min_scale < max_scale {
+        rng.gen_range(min_scale..=max_scale)
+    } else {
+        min_scale
+    };
+
+    if factor != 1.0 {
+        let original = (glyph.width() as f64, glyph.height() as f64);
+        glyph = glyph.scale(factor);
+
+        // Keep the anchor on the same part of the image after resizing.
+        let ratio = (glyph.width() as f64 / original.0, glyph.height() as f64 / original.1);
+        anchor = anchor.map(|(x, y)| ((x + 0.5) * ratio.0 - 0.5, (y + 0.5) * ratio.1 - 0.5));
+    }
+
+    if let Some((color, amount)) = settings.glyph_tint {
+        if amount > 0.0 {
+            glyph = glyph.tint(color, rng.gen_range(0.0..=amount));
+        }
+    }
+
+    return (glyph, anchor.map(|(x, y)| (x.round() as isize, y.round() as isize)));
+}
+
+/// Locations on a grid, randomly selected according to density and then wiggled a little.
+fn grid_locations(rng: &mut SmallRng, density: usize, footprint: (usize, usize), map: &Image, color: Color) -> Vec<(isize, isize)> {
+    let (width, height) = footprint;
+    let mut locations = Vec::new();
+
+    let mut x = 0;
+    while x < map.width() {
+        let mut y = 0;
+        while y < map.height() {
+            // Apply random selection
+            let r_value = rng.next_u32() as usize;
+            if (r_value % 100) < density && map.pixel_at(x, y).unwrap() == color {
+                // Wiggle the locations a small amount
+                let r1 = (rng.next_u32() as usize % width.saturating_sub(OVERLAP_PREVENTION).max(1)) as isize - width as isize / 2;
+                let r2 = (rng.next_u32() as usize % height.saturating_sub(OVERLAP_PREVENTION).max(1)) as isize - height as isize / 2;
+                locations.push((x as isize + r1, y as isize + r2));
+            }
+            y += height + height / 2 + OVERLAP_PREVENTION;
+        }
+        x += width + width / 2 + OVERLAP_PREVENTION;
+    }
+
+    return locations;
+}
+
+/// Minimum distance between poisson disc samples for a glyph at the given density.
+/// At a density of 100 glyphs may touch, lower densities spread them out so that
+/// the number of glyphs per area falls in proportion.
+fn poisson_spacing(density: usize, footprint: (usize, usize)) -> f64 {
+    let size = (footprint.0.max(footprint.1) + OVERLAP_PREVENTION) as f64;
+    return size * (100.0 / density.min(100) as f64).sqrt();
+}
+
+/// Locations within the region from Bridson's poisson disc sampling over the whole map.
+fn poisson_locations(rng: &mut SmallRng, density: usize, footprint: (usize, usize), map: &Image, color: Color) -> Vec<(isize, isize)> {
+    if density == 0 || map.width() == 0 || map.height() == 0 {
+        return Vec::new();
+    }
+
+    let spacing = poisson_spacing(density, footprint);
+    let width = map.width() as f64;
+    let height = map.height() as f64;
+
+    // Cells small enough that each holds at most one sample.
+    let cell_size = spacing / std::f64::consts::SQRT_2;
+    let columns = (width / cell_size).ceil() as usize;
+    let rows = (height / cell_size).ceil() as usize;
+    let cell_of = |p: Vector<2>| ((p[0] / cell_size) as usize, (p[1] / cell_size) as usize);
+
+    let mut cells: Vec<Option<usize>> = vec![None; columns * rows];
+    let mut samples: Vec<Vector<2>> = Vec::new();
+    let mut active = Vec::new();
+
+    let first = Vector::from([rng.gen_range(0.0..width), rng.gen_range(0.0..height)]);
+    let (cx, cy) = cell_of(first);
+    cells[cx + cy * columns] = Some(0);
+    samples.push(first);
+    active.push(0);
+
+    while !active.is_empty() {
+        let a = rng.gen_range(0..active.len());
+        let origin = samples[active[a]];
+        let mut placed = false;
+
+        for _ in 0..POISSON_ATTEMPTS {
+            let angle = rng.gen_range(0.0..std::f64::consts::TAU);
+            let distance = rng.gen_range(spacing..2.0 * spacing);
+            let candidate = origin + Vector::from([angle.cos(), angle.sin()]) * distance;
+
+            if candidate[0] < 0.0 || candidate[1] < 0.0 || candidate[0] >= width || candidate[1] >= height {
+                continue;
+            }
+
+            let (cx, cy) = cell_of(candidate);
+            let too_close = (cx.saturating_sub(2)..(cx + 3).min(columns))
+                .flat_map(|x| (cy.saturating_sub(2)..(cy + 3).min(rows)).map(move |y| (x, y)))
+                .filter_map(|(x, y)| cells[x + y * columns])
+                .any(|s| (samples[s] - candidate).magnitude() < spacing);
+
+            if !too_close {
+                cells[cx + cy * columns] = Some(samples.len());
+                active.push(samples.len());
+                samples.push(candidate);
+                placed = true;
+                break;
+            }
+        }
+
+        if !placed {
+            active.swap_remove(a);
+        }
+    }
+
+    return samples.into_iter()
+        .map(|p| (p[0] as isize, p[1] as isize))
+        .filter(|(x, y)| map.pixel_at_isize(*x, *y) == Some(color))
+        .collect();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn poisson_spacing_is_respected() {
+        let color = Color::from([0, 192, 0]);
+        let map = Image::new(200, 150).fill(color);
+        let glyph = Image::new(6, 8).fill(Color::from([0, 0, 0, 255]));
+        let locations = |d: &GlyphDistribution| d.glyphs.iter().map(|g| g.position).collect::<Vec<_>>();
+
+        let settings = BiomeSettings::new(color).glyph("glyph.png", 100, 100).placement(GlyphPlacement::Poisson);
+        let dense = GlyphDistribution::new([7; 32], &settings, &[&glyph], &map, color);
+        let sparse = GlyphDistribution::new([7; 32], &settings.clone().glyph("glyph.png", 25, 100), &[&glyph], &map, color);
+        assert!(sparse.glyphs.len() < dense.glyphs.len());
+        assert!(!sparse.glyphs.is_empty());
+
+        let spacing = poisson_spacing(100, (glyph.width(), glyph.height()));
+        let dense_locations = locations(&dense);
+        for (i, a) in dense_locations.iter().enumerate() {
+            for b in &dense_locations[i + 1..] {
+                let d = Vector::from([a.0 as f64 - b.0 as f64, a.1 as f64 - b.1 as f64]).magnitude();
+                // Locations are truncated to whole pixels after sampling.
+                assert!(d > spacing - 2.0, "{:?} and {:?} are {} apart", a, b, d);
+            }
+        }
+
+        let again = GlyphDistribution::new([7; 32], &settings, &[&glyph], &map, color);
+        assert_eq!(dense_locations, locations(&again));
+    }
+
+    #[test]
+    fn glyph_variation() {
+        let color = Color::from([0, 192, 0]);
+        let map = Image::new(200, 150).fill(color);
+        let small = Image::new(4, 4).fill(Color::from([0, 0, 0, 255]));
+        let large = Image::new(8, 8).fill(Color::from([0, 0, 0, 255]));
+
+        // A variant without weight is never picked.
+        let settings = BiomeSettings::new(color)
+            .glyph_variant("small.png", 1.0)
+            .glyph_variant("large.png", 0.0)
+            .glyph_scale(1.0, 2.0)
+            .glyph_tint(Color::from([255, 0, 0]), 1.0);
+        let settings = BiomeSettings { glyph_density: 100, ..settings };
+        let distribution = GlyphDistribution::new([3; 32], &settings, &[&small, &large], &map, color);
+
+        assert!(!distribution.glyphs.is_empty());
+        let images = || distribution.glyphs.iter().map(|g| &g.image);
+        assert!(images().all(|g| (4..=8).contains(&g.width()) && g.width() == g.height()));
+        assert!(images().any(|g| g.width() > 4));
+        assert!(images().any(|g| g.pixel_at(0, 0).unwrap()[0] > 0));
+        assert!(images().all(|g| g.pixel_at(0, 0).unwrap()[3] == 255));
+    }
+
+    #[test]
+    fn lower_glyphs_are_drawn_in_front() {
+        let red = Color::from([255, 0, 0, 255]);
+        let blue = Color::from([0, 0, 255, 255]);
+        let glyph = |color, position: (isize, isize), baseline| PlacedGlyph { position, baseline, image: Image::new(4, 4).fill(color) };
+
+        // The blue glyph is generated first but stands lower, so it must cover the red one.
+        let mut layer = Image::new(10, 10);
+        stamp_depth_sorted(&mut layer, vec![glyph(blue, (2, 3), 6), glyph(red, (1, 1), 4)]);
+        assert_eq!(layer.pixel_at(3, 3), Some(blue));
+        assert_eq!(layer.pixel_at(1, 1), Some(red));
+    }
+
+    #[test]
+    fn anchors_follow_variation() {
+        let color = Color::from([0, 192, 0]);
+        let map = Image::new(100, 100).fill(color);
+        let glyph = Image::new(5, 7).fill(Color::from([0, 0, 0, 255]));
+
+        let settings = BiomeSettings {
+            glyph_images: vec![GlyphVariant::new("tree.png", 1.0).anchored(1, 6)],
+            glyph_density: 100,
+            ..BiomeSettings::new(color).glyph_flip(true).glyph_scale(2.0, 2.0)
+        };
 
-            layer.stamp((x, y), &self.glyph);
+        let distribution = GlyphDistribution::new([9; 32], &settings, &[&glyph], &map, color);
+        assert!(!distribution.glyphs.is_empty());
+        for placed in &distribution.glyphs {
+            // The anchor on the bottom row stays on the bottom row once scaled.
+            assert_eq!(placed.baseline, placed.position.1 + 13);
+            assert_eq!((placed.image.width(), placed.image.height()), (10, 14));
         }
-        return layer;
     }
 }
@@ -1,176 +1,551 @@
 use json::{JsonValue};
 
 use crate::color::Color;
+use crate::error::{Error, Result};
 use std::ffi::OsStr;
 use std::io::Read;
 use crate::image::Image;
-use std::collections::HashMap;
-use std::path::{PathBuf};
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
 
 mod glyphs;
 
-struct BiomeSettings {
-    color: Color,
-    outline_color: Color,
-    outline_thickness: u64,
-    glyph_image: Option<PathBuf>,
-    glyph_density: usize,
-    glyph_threshold: usize,
+/// Largest fraction of the tint color mixed into a glyph when a configuration does not give one.
+const DEFAULT_TINT_AMOUNT: f64 = 0.5;
+
+pub use glyphs::{GlyphPlacement, GlyphVariant};
+
+/// How the pixels of a single biome are drawn.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BiomeSettings {
+    /// Color to fill the region with.
+    pub color: Color,
+    /// Color of the regions outline.
+    pub outline_color: Color,
+    /// Width of the outline in pixels, 0 for no outline.
+    pub outline_thickness: u64,
+    /// Images scattered over the region, each picked in proportion to its weight. Empty for no glyphs.
+    pub glyph_images: Vec<GlyphVariant>,
+    /// Percentage chance of a glyph being placed at each possible location.
+    pub glyph_density: usize,
+    /// Percentage of a glyphs pixels that must lie within the region for it to be placed.
+    pub glyph_threshold: usize,
+    /// How possible glyph locations are spread over the region.
+    pub glyph_placement: GlyphPlacement,
+    /// Whether each glyph has an even chance of being mirrored left to right.
+    pub glyph_flip: bool,
+    /// Range each glyph is randomly scaled within.
+    pub glyph_scale: (f64, f64),
+    /// Color glyphs are tinted towards, by a random amount up to the given fraction.
+    pub glyph_tint: Option<(Color, f64)>,
+    /// How many times harder the region is to travel over than open ground when routing roads.
+    /// Infinite for regions that cannot be crossed, such as water.
+    pub travel_cost: f64,
 }
 
+impl BiomeSettings {
+    /// Settings that fill a region with a color, without an outline or glyphs.
+    pub fn new(fill: Color) -> Self {
+        BiomeSettings {
+            color: fill,
+            outline_color: Color::from([0, 0, 0, 255]),
+            outline_thickness: 0,
+            glyph_images: Vec::new(),
+            glyph_density: 1,
+            glyph_threshold: 100,
+            glyph_placement: GlyphPlacement::Grid,
+            glyph_flip: false,
+            glyph_scale: (1.0, 1.0),
+            glyph_tint: None,
+            travel_cost: 1.0,
+        }
+    }
+
+    /// Outlines the region with the given color and thickness.
+    pub fn outline(mut self, color: Color, thickness: u64) -> Self {
+        self.outline_color = color;
+        self.outline_thickness = thickness;
+        return self;
+    }
+
+    /// Scatters a single glyph image over the region, replacing any variants.
+    pub fn glyph(mut self, image: impl Into<PathBuf>, density: usize, threshold: usize) -> Self {
+        self.glyph_images = vec![GlyphVariant::new(image, 1.0)];
+        self.glyph_density = density;
+        self.glyph_threshold = threshold;
+        return self;
+    }
+
+    /// Changes how the glyph locations are spread over the region.
+    pub fn placement(mut self, placement: GlyphPlacement) -> Self {
+        self.glyph_placement = placement;
+        return self;
+    }
+
+    /// Adds another glyph image, picked in proportion to its weight.
+    pub fn glyph_variant(mut self, image: impl Into<PathBuf>, weight: f64) -> Self {
+        self.glyph_images.push(GlyphVariant::new(image, weight));
+        return self;
+    }
+
+    /// Randomly mirrors half of the glyphs.
+    pub fn glyph_flip(mut self, flip: bool) -> Self {
+        self.glyph_flip = flip;
+        return self;
+    }
+
+    /// Scales each glyph by a random factor between `min` and `max`.
+    pub fn glyph_scale(mut self, min: f64, max: f64) -> Self {
+        self.glyph_scale = (min, max);
+        return self;
+    }
+
+    /// Tints each glyph towards a color by a random amount between 0 and `amount`.
+    pub fn glyph_tint(mut self, color: Color, amount: f64) -> Self {
+        self.glyph_tint = Some((color, amount));
+        return self;
+    }
+}
+
+/// Indices of the pixels above, below, left and right of a pixel that lie within the map.
+fn neighbours(i: usize, width: usize, height: usize) -> impl Iterator<Item = usize> {
+    let (x, y) = (i % width, i / width);
+    let offsets = [(-1, 0), (1, 0), (0, -1), (0, 1)];
+    IntoIterator::into_iter(offsets).filter_map(move |(dx, dy): (isize, isize)| {
+        let (nx, ny) = (x as isize + dx, y as isize + dy);
+        match nx >= 0 && ny >= 0 && (nx as usize) < width && (ny as usize) < height {
+            true => Some(nx as usize + ny as usize * width),
+            false => None,
+        }
+    })
+}
+
+/// Maps each color of a biome map to how the region of that color is drawn.
+#[derive(Default)]
 pub struct Configuration {
     glyphs: HashMap<PathBuf, Image>,
     mapping: Vec<(Color, BiomeSettings)>,
 }
 
+/// Collects biomes in code before loading their glyphs into a `Configuration`.
+#[derive(Default)]
+pub struct ConfigurationBuilder {
+    biomes: Vec<(Color, BiomeSettings)>,
+}
+
+impl ConfigurationBuilder {
+    /// Adds the settings for the region of the biome map with the color `key`.
+    pub fn biome(mut self, key: Color, settings: BiomeSettings) -> Self {
+        self.biomes.push((key, settings));
+        return self;
+    }
+
+    /// Loads the glyph images of every biome and creates the configuration.
+    pub fn build(self) -> Result<Configuration> {
+        let mut configuration = Configuration::new();
+        for (key, settings) in self.biomes {
+            configuration.add_biome(key, settings)?;
+        }
+        return Ok(configuration);
+    }
+}
+
 impl Configuration {
-    fn from_file(file: impl AsRef<OsStr>) -> Result<Self, String> {
-        let mut file = std::fs::File::open(file.as_ref()).map_err(|e| e.to_string())?;
+    /// Creates a configuration without any biomes.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts building a configuration in code.
+    pub fn builder() -> ConfigurationBuilder {
+        ConfigurationBuilder::default()
+    }
+
+    /// Loads a configuration from a json file. Glyph images are relative to the directory of the file.
+    pub fn from_file(file: impl AsRef<OsStr>) -> Result<Self> {
+        let path = PathBuf::from(file.as_ref());
+        let io_error = |source| Error::Io { path: path.clone(), source };
+
+        let mut file = std::fs::File::open(&path).map_err(io_error)?;
         let mut string = String::new();
-        file.read_to_string(&mut string).map_err(|e| e.to_string())?;
-        return Self::from_string(string);
+        file.read_to_string(&mut string).map_err(io_error)?;
+        let json = json::parse(&string)?;
+        return Self::from_json_in(json, path.parent().unwrap_or_else(|| Path::new("")));
     }
 
-    fn from_string(string: impl AsRef<str>) -> Result<Self, String> {
-        let json = json::parse(string.as_ref()).map_err(|e| e.to_string())?;
+    /// Parses a configuration from a json string.
+    pub fn from_string(string: impl AsRef<str>) -> Result<Self> {
+        let json = json::parse(string.as_ref())?;
         return Self::from_json(json);
     }
 
-    fn from_json(json: JsonValue) -> Result<Self, String> {
+    /// Builds a configuration from parsed json, with glyph images relative to the current directory.
+    pub fn from_json(json: JsonValue) -> Result<Self> {
+        return Self::from_json_in(json, Path::new(""));
+    }
+
+    /// Builds a configuration from parsed json, with glyph images relative to `base`.
+    pub(crate) fn from_json_in(json: JsonValue, base: &Path) -> Result<Self> {
         use std::str::FromStr;
 
-        let mut configuration = Configuration { glyphs: HashMap::new(), mapping: Vec::new() };
+        let mut configuration = Configuration::new();
 
         for (key, settings) in json.entries() {
             let key_color = match Color::from_str(key) {
                 Ok(c) => c,
-                Err(_) => return Err(format!("Unable to parse {} into a color!", key)),
+                Err(e) => return Err(Error::Config {
+                    key: key.to_string(),
+                    biome: None,
+                    reason: String::from("biome keys must be colors"),
+                    source: Some(Box::new(e)),
+                }),
+            };
+
+            let biome = Some(key_color);
+            let color_error = |setting: &str, e| Error::Config {
+                key: setting.to_string(),
+                biome,
+                reason: String::from("not a valid color"),
+                source: Some(Box::new(e)),
             };
 
+            let defaults = BiomeSettings::new(key_color);
+
             let color = match &settings["color"].as_str() {
                 Some(s) => {
-                    Color::from_str(s)?
+                    Color::from_str(s).map_err(|e| color_error("color", e))?
                 }
 
-                None if settings.has_key("color") => return Err(format!("Cannot parse {} as region color", &settings["color"])),
-                _ => key_color,
+                None if settings.has_key("color") => return Err(Error::config("color", biome, "region color must be a color string")),
+                _ => defaults.color,
             };
 
             let outline_color = match &settings["outline_color"].as_str() {
-                Some(s) => Color::from_str(s)?,
-                None if settings.has_key("outline_color") => return Err(String::from("Non color string given as region outline color")),
-                _ => Color::from([0, 0, 0, 255]),
+                Some(s) => Color::from_str(s).map_err(|e| color_error("outline_color", e))?,
+                None if settings.has_key("outline_color") => return Err(Error::config("outline_color", biome, "outline color must be a color string")),
+                _ => defaults.outline_color,
             };
 
             let outline_thickness = match &settings["outline_thickness"].as_u64() {
                 Some(n) => *n,
-                None if settings.has_key("outline_thickness") => return Err(format!("Could not parse outline_thickness for {}", key)),
-                _ => 0,
+                None if settings.has_key("outline_thickness") => return Err(Error::config("outline_thickness", biome, "must be a positive integer")),
+                _ => defaults.outline_thickness,
             };
 
-            let glyph_image = match settings["glyph_image"].as_str() {
-                Some(s) => {
-                    let as_path = PathBuf::from(s);
-                    if !configuration.glyphs.contains_key(&as_path) {
-                        if !as_path.exists() {
-                            return Err(format!("Image file: {} does not exist", as_path.to_str().unwrap()));
-                        }
-
-                        let image = match Image::from_file(s) {
-                            Ok(i) => i,
-                            Err(m) => return Err(format!("Could not load glyph {} because {}", as_path.to_str().unwrap(), m)),
+            let glyph_images = match &settings["glyph_image"] {
+                JsonValue::Array(variants) => {
+                    let mut images = Vec::new();
+                    for variant in variants {
+                        let image = variant.as_str().or_else(|| variant["image"].as_str());
+                        let weight = if variant.has_key("weight") { variant["weight"].as_f64() } else { Some(1.0) };
+                        let anchor: Vec<_> = variant["anchor"].members().map(|v| v.as_usize()).collect();
+                        let anchor = match anchor[..] {
+                            [Some(x), Some(y)] => Some(Some((x, y))),
+                            _ if variant.has_key("anchor") => None,
+                            _ => Some(None),
                         };
 
-                        configuration.glyphs.insert(as_path.clone(), image);
+                        match (image, weight, anchor) {
+                            (Some(image), Some(weight), Some(anchor)) if weight >= 0.0 => {
+                                images.push(GlyphVariant { image: base.join(image), weight, anchor });
+                            }
+                            _ => return Err(Error::config(
+                                "glyph_image",
+                                biome,
+                                "variants must be filepath strings or objects with an image, positive weight and [x, y] anchor",
+                            )),
+                        }
                     }
-
-                    Some(as_path)
+                    images
                 }
-                None if settings.has_key("glyph_image") => return Err(String::from("Non string/filepath given for glyph image")),
-                _ => None,
+
+                g => match g.as_str() {
+                    Some(s) => vec![GlyphVariant::new(base.join(s), 1.0)],
+                    None if settings.has_key("glyph_image") => return Err(Error::config("glyph_image", biome, "must be a filepath string or array of variants")),
+                    _ => defaults.glyph_images,
+                },
             };
 
             let glyph_density = match settings["glyph_density"].as_u64() {
                 Some(v) => v as usize,
-                None if settings.has_key("glyph_density") => return Err(String::from("Invalid data for glyph density")),
-                _ => 1,
+                None if settings.has_key("glyph_density") => return Err(Error::config("glyph_density", biome, "must be a positive integer")),
+                _ => defaults.glyph_density,
             };
 
             let glyph_threshold = match settings["glyph_threshold"].as_u64() {
                 Some(v) => v as usize,
-                None if settings.has_key("glyph_threshold") => return Err(String::from("Invalid data for glyph density")),
-                _ => 100,
+                None if settings.has_key("glyph_threshold") => return Err(Error::config("glyph_threshold", biome, "must be a positive integer")),
+                _ => defaults.glyph_threshold,
+            };
+
+            let glyph_placement = match settings["glyph_placement"].as_str() {
+                Some(s) => GlyphPlacement::from_str(s).map_err(|e| Error::Config {
+                    key: String::from("glyph_placement"),
+                    biome,
+                    reason: String::from("must be grid or poisson"),
+                    source: Some(Box::new(e)),
+                })?,
+                None if settings.has_key("glyph_placement") => return Err(Error::config("glyph_placement", biome, "must be grid or poisson")),
+                _ => defaults.glyph_placement,
+            };
+
+            let glyph_flip = match settings["glyph_flip"].as_bool() {
+                Some(b) => b,
+                None if settings.has_key("glyph_flip") => return Err(Error::config("glyph_flip", biome, "must be true or false")),
+                _ => defaults.glyph_flip,
             };
 
-            configuration.mapping.push((key_color, BiomeSettings {
+            let glyph_scale = match &settings["glyph_scale"] {
+                JsonValue::Array(range) => match range[..] {
+                    [ref min, ref max] => (min.as_f64(), max.as_f64()),
+                    _ => (None, None),
+                },
+                s if settings.has_key("glyph_scale") => (s.as_f64(), s.as_f64()),
+                _ => (Some(defaults.glyph_scale.0), Some(defaults.glyph_scale.1)),
+            };
+
+            let glyph_scale = match glyph_scale {
+                (Some(min), Some(max)) if min > 0.0 && min <= max => (min, max),
+                _ => return Err(Error::config("glyph_scale", biome, "must be a positive number or an array of a minimum and maximum")),
+            };
+
+            let glyph_tint_amount = match settings["glyph_tint_amount"].as_f64() {
+                Some(n) if (0.0..=1.0).contains(&n) => n,
+                _ if settings.has_key("glyph_tint_amount") => return Err(Error::config("glyph_tint_amount", biome, "must be between 0 and 1")),
+                _ => DEFAULT_TINT_AMOUNT,
+            };
+
+            let glyph_tint = match settings["glyph_tint"].as_str() {
+                Some(s) => Some((Color::from_str(s).map_err(|e| color_error("glyph_tint", e))?, glyph_tint_amount)),
+                None if settings.has_key("glyph_tint") => return Err(Error::config("glyph_tint", biome, "must be a color string")),
+                _ => defaults.glyph_tint,
+            };
+
+            let travel_cost = match &settings["travel_cost"] {
+                c if c.as_str() == Some("impassable") => f64::INFINITY,
+                c => match c.as_f64() {
+                    Some(n) if n >= 0.0 => n,
+                    _ if settings.has_key("travel_cost") => return Err(Error::config("travel_cost", biome, "must be a positive number or \"impassable\"")),
+                    _ => defaults.travel_cost,
+                },
+            };
+
+            configuration.add_biome(key_color, BiomeSettings {
                 color,
                 outline_color,
                 outline_thickness,
-                glyph_image,
+                glyph_images,
                 glyph_density,
                 glyph_threshold,
-            }));
+                glyph_placement,
+                glyph_flip,
+                glyph_scale,
+                glyph_tint,
+                travel_cost,
+            })?;
         }
 
-        configuration.mapping.sort_by(|(_, s1), (_, s2)| s1.outline_thickness.cmp(&s2.outline_thickness));
-
         return Ok(configuration);
     }
 
-    pub fn generate_layer(&self, biome_map: &Image) -> Result<Image, String> {
-        let width = biome_map.width();
-        let height = biome_map.height();
-        let mut layer = Image::new(width, height);
-        let mut glyph_layer = Image::new(width, height);
+    /// Converts the configuration into the json format read by `from_json`.
+    pub fn to_json(&self) -> JsonValue {
+        return self.to_json_in(Path::new(""));
+    }
 
-        for entry in &self.mapping {
-            let mut biome = Image::new(width, height).fill(Color::from([0, 0, 0, 0]));
+    /// Converts the configuration into json with glyph images relative to `base` where they lie within it, and
+    /// absolute otherwise.
+    fn to_json_in(&self, base: &Path) -> JsonValue {
+        let image_path = |image: &Path| -> JsonValue {
+            let path = match image.strip_prefix(base) {
+                Ok(relative) => relative.to_path_buf(),
+                Err(_) => std::fs::canonicalize(image).unwrap_or_else(|_| image.to_path_buf()),
+            };
+            path.to_string_lossy().as_ref().into()
+        };
+        let mut json = JsonValue::new_object();
+
+        for (key, settings) in &self.mapping {
+            let mut biome = JsonValue::new_object();
+            biome["color"] = settings.color.to_hex().into();
+            biome["outline_color"] = settings.outline_color.to_hex().into();
+            biome["outline_thickness"] = settings.outline_thickness.into();
+            biome["travel_cost"] = match settings.travel_cost {
+                c if c.is_infinite() => "impassable".into(),
+                c => c.into(),
+            };
 
-            for x in 0..width {
-                for y in 0..height {
-                    if biome_map.pixel_at(x, y).unwrap() == entry.0 {
-                        biome.set_pixel_at(x, y, entry.1.color)?;
-                    }
+            if !settings.glyph_images.is_empty() {
+                biome["glyph_image"] = match &settings.glyph_images[..] {
+                    [single] if single.weight == 1.0 && single.anchor.is_none() => image_path(&single.image),
+                    variants => JsonValue::Array(variants.iter().map(|v| {
+                        let mut variant = JsonValue::new_object();
+                        variant["image"] = image_path(&v.image);
+                        variant["weight"] = v.weight.into();
+                        if let Some((x, y)) = v.anchor {
+                            variant["anchor"] = json::array![x, y];
+                        }
+                        variant
+                    }).collect()),
+                };
+                biome["glyph_density"] = settings.glyph_density.into();
+                biome["glyph_threshold"] = settings.glyph_threshold.into();
+                biome["glyph_placement"] = settings.glyph_placement.name().into();
+                biome["glyph_flip"] = settings.glyph_flip.into();
+                biome["glyph_scale"] = json::array![settings.glyph_scale.0, settings.glyph_scale.1];
+
+                if let Some((color, amount)) = settings.glyph_tint {
+                    biome["glyph_tint"] = color.to_hex().into();
+                    biome["glyph_tint_amount"] = amount.into();
                 }
             }
 
-            for _i in 0..entry.1.outline_thickness {
-                let mut outlined = biome.clone();
+            json[key.to_hex()] = biome;
+        }
 
-                for x in 0..width {
-                    for y in 0..height {
-                        // Check if neighbour is region color or outline color (and must also be itself region color)
-                        if biome.pixel_at(x, y).unwrap() != entry.1.color { continue; }
-                        let neighbours = biome.get_neighbouring_pixels(x as isize, y as isize)
-                            .iter().filter(|(_, c)| *c == Color::from([0, 0, 0, 0]) || *c == entry.1.outline_color)
-                            .count();
+        return json;
+    }
 
-                        if neighbours > 0 {
-                            outlined.set_pixel_at(x, y, entry.1.outline_color)?;
-                        }
-                    }
-                }
+    /// Writes the configuration to a json file, with glyph images relative to the directory of the file.
+    pub fn write_to_file(&self, file: impl AsRef<OsStr>) -> Result<()> {
+        let path = PathBuf::from(file.as_ref());
+        let json = self.to_json_in(path.parent().unwrap_or_else(|| Path::new("")));
+        return std::fs::write(&path, json.pretty(2)).map_err(|source| Error::Io { path, source });
+    }
 
-                biome = outlined;
+    /// Adds a biome, replacing any existing settings for the same key color.
+    /// Its glyph images are loaded unless another biome already uses them.
+    pub fn add_biome(&mut self, key: Color, settings: BiomeSettings) -> Result<()> {
+        for path in settings.glyph_images.iter().map(|v| &v.image) {
+            if !self.glyphs.contains_key(path) {
+                let image = Image::from_file(path).map_err(|e| Error::Config {
+                    key: String::from("glyph_image"),
+                    biome: Some(key),
+                    reason: format!("could not load glyph {}", path.display()),
+                    source: Some(Box::new(e)),
+                })?;
+
+                self.glyphs.insert(path.clone(), image);
             }
+        }
+
+        for variant in &settings.glyph_images {
+            let glyph = &self.glyphs[&variant.image];
+            if let Some((x, y)) = variant.anchor.filter(|(x, y)| *x >= glyph.width() || *y >= glyph.height()) {
+                return Err(Error::config(
+                    "glyph_image",
+                    Some(key),
+                    format!("anchor [{}, {}] is outside the {}x{} glyph {}", x, y, glyph.width(), glyph.height(), variant.image.display()),
+                ));
+            }
+        }
 
-            if entry.1.glyph_image.is_some() {
-                let glyph = entry.1.glyph_image.clone().unwrap();
-                let glyph = self.glyphs.get(&glyph).unwrap();
+        self.mapping.retain(|(k, _)| *k != key);
+        self.mapping.push((key, settings));
+        // Thicker outlines are drawn last so they are not covered by neighbouring regions.
+        self.mapping.sort_by_key(|(_, s)| s.outline_thickness);
 
-                let distrib = glyphs::GlyphDistribution::new([52; 32], entry.1.glyph_density, entry.1.glyph_threshold, glyph, &biome_map, entry.0);
-                let layer = distrib.to_layer();
+        return Ok(());
+    }
+
+    /// Removes the biome with the given key color, returning its settings.
+    pub fn remove_biome(&mut self, key: Color) -> Option<BiomeSettings> {
+        let index = self.mapping.iter().position(|(k, _)| *k == key)?;
+        let (_, settings) = self.mapping.remove(index);
 
-                glyph_layer.overlay(&layer).unwrap();
+        for path in settings.glyph_images.iter().map(|v| &v.image) {
+            let still_used = self.mapping.iter().any(|(_, s)| s.glyph_images.iter().any(|v| v.image == *path));
+            if !still_used {
+                self.glyphs.remove(path);
             }
+        }
+
+        return Some(settings);
+    }
+
+    /// Returns the settings of the biome with the given key color.
+    pub fn biome(&self, key: Color) -> Option<&BiomeSettings> {
+        return self.mapping.iter().find(|(k, _)| *k == key).map(|(_, s)| s);
+    }
+
+    /// Returns every key color and its settings, in drawing order.
+    pub fn biomes(&self) -> impl Iterator<Item = (Color, &BiomeSettings)> {
+        return self.mapping.iter().map(|(k, s)| (*k, s));
+    }
 
-            layer.overlay(&biome)?;
+    /// Generates the shaded and outlined regions of the biome map, with the glyphs drawn on top.
+    pub fn generate_layer(&self, biome_map: &Image) -> Result<Image> {
+        let mut layer = self.generate_region_layer(biome_map)?;
+        layer.overlay(&self.generate_glyph_layer(biome_map))?;
+        return Ok(layer);
+    }
+
+    /// Generates only the shaded and outlined regions of the biome map.
+    pub fn generate_region_layer(&self, biome_map: &Image) -> Result<Image> {
+        let width = biome_map.width();
+        let height = biome_map.height();
+        let index: HashMap<Color, usize> = self.mapping.iter().enumerate().map(|(i, (key, _))| (*key, i)).collect();
+        let regions: Vec<Option<usize>> = biome_map.data().iter().map(|c| index.get(c).copied()).collect();
+
+        // Outlines run inwards from where a region meets any other color, as far as the outline of its biome is thick.
+        let mut depth = vec![0; width * height];
+        let mut queue = VecDeque::new();
+        for (i, region) in regions.iter().enumerate() {
+            if let Some(r) = region {
+                if self.mapping[*r].1.outline_thickness > 0 && neighbours(i, width, height).any(|n| regions[n] != *region) {
+                    depth[i] = 1;
+                    queue.push_back(i);
+                }
+            }
         }
 
-        layer.overlay(&glyph_layer).unwrap();
+        while let Some(i) = queue.pop_front() {
+            if depth[i] >= self.mapping[regions[i].unwrap()].1.outline_thickness {
+                continue;
+            }
+            for n in neighbours(i, width, height) {
+                if depth[n] == 0 && regions[n] == regions[i] {
+                    depth[n] = depth[i] + 1;
+                    queue.push_back(n);
+                }
+            }
+        }
+
+        let mut layer = Image::new(width, height);
+        for (i, region) in regions.iter().enumerate() {
+            if let Some(r) = region {
+                let (x, y) = (i % width, i / width);
+                let biome = &self.mapping[*r].1;
+                let color = if depth[i] > 0 { biome.outline_color } else { biome.color };
+                let mixed = Color::interpolate(layer.pixel_at(x, y).unwrap(), color, color[3] as f64 / u8::MAX as f64);
+                layer.set_pixel_at(x, y, mixed)?;
+            }
+        }
 
         return Ok(layer);
     }
+
+    /// Generates only the glyphs scattered over the regions of the biome map.
+    pub fn generate_glyph_layer(&self, biome_map: &Image) -> Image {
+        let mut glyph_layer = Image::new(biome_map.width(), biome_map.height());
+        let mut placed = Vec::new();
+
+        for entry in &self.mapping {
+            if !entry.1.glyph_images.is_empty() {
+                let images: Vec<&Image> = entry.1.glyph_images.iter()
+                    .map(|v| self.glyphs.get(&v.image).unwrap())
+                    .collect();
+
+                let distrib = glyphs::GlyphDistribution::new([52; 32], &entry.1, &images, biome_map, entry.0);
+                placed.extend(distrib.into_glyphs());
+            }
+        }
+
+        // Glyphs of every biome are drawn together so they overlap by depth rather than by biome.
+        glyphs::stamp_depth_sorted(&mut glyph_layer, placed);
+
+        return glyph_layer;
+    }
 }
 
 #[cfg(test)]
@@ -179,7 +554,107 @@ mod test {
 
     #[test]
     fn read_configuration() {
-        Configuration::from_file("samples/biomes.json").unwrap();
+        let configuration = Configuration::from_file("samples/biomes.json").unwrap();
+
+        // Glyphs are relative to the file, and are written relative to wherever the configuration is written.
+        let forest = configuration.biome(Color::from([0, 192, 0])).unwrap();
+        assert_eq!(forest.glyph_images[0].image, PathBuf::from("samples/tree_glyph.png"));
+        configuration.write_to_file("samples/biomes.out.json").unwrap();
+        let written = json::parse(&std::fs::read_to_string("samples/biomes.out.json").unwrap()).unwrap();
+        assert_eq!(written["#00c000"]["glyph_image"], "tree_glyph.png");
+        let reloaded = Configuration::from_file("samples/biomes.out.json").unwrap();
+        assert_eq!(configuration.biomes().collect::<Vec<_>>(), reloaded.biomes().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn configuration_errors() {
+        use std::error::Error as _;
+
+        match Configuration::from_string(r##"{ "#00c000": { "outline_color": "#ff00zz" } }"##) {
+            Err(Error::Config { key, biome, source, .. }) => {
+                assert_eq!(key, "outline_color");
+                assert_eq!(biome, Some(Color::from([0, 192, 0])));
+                assert!(matches!(source.as_deref(), Some(Error::InvalidValue { .. })));
+            }
+            _ => panic!("expected a config error"),
+        }
+
+        match Configuration::from_string(r##"{ "#00c000": { "glyph_image": "samples/missing.png" } }"##) {
+            Err(e @ Error::Config { .. }) => assert!(matches!(e.source().unwrap().downcast_ref(), Some(Error::Io { .. }))),
+            _ => panic!("expected a config error"),
+        }
+
+        assert!(matches!(Configuration::from_string("{"), Err(Error::Json(_))));
+    }
+
+    #[test]
+    fn build_and_serialize() {
+        let forest = Color::from([0, 192, 0]);
+        let sea = Color::from([64, 255, 255]);
+
+        let mut configuration = Configuration::builder()
+            .biome(forest, BiomeSettings::new(forest).outline(Color::from([255, 0, 231]), 2).glyph("samples/tree_glyph.png", 75, 90).placement(GlyphPlacement::Poisson))
+            .biome(sea, BiomeSettings { travel_cost: f64::INFINITY, ..BiomeSettings::new(Color::from([0, 64, 64, 128])) })
+            .build()
+            .unwrap();
+
+        assert_eq!(configuration.biomes().map(|(k, _)| k).collect::<Vec<_>>(), vec![sea, forest]);
+
+        let reloaded = Configuration::from_string(configuration.to_json().dump()).unwrap();
+        assert_eq!(configuration.biomes().collect::<Vec<_>>(), reloaded.biomes().collect::<Vec<_>>());
+
+        configuration.add_biome(sea, BiomeSettings::new(sea).outline(sea, 3)).unwrap();
+        assert_eq!(configuration.biome(sea).unwrap().outline_thickness, 3);
+        assert_eq!(configuration.biomes().count(), 2);
+
+        assert!(configuration.remove_biome(forest).is_some());
+        assert!(configuration.glyphs.is_empty());
+        assert!(configuration.biome(forest).is_none());
+    }
+
+    #[test]
+    fn glyph_variants() {
+        let forest = Color::from([0, 192, 0]);
+        let grass = Color::from([128, 255, 0]);
+
+        let mut configuration = Configuration::from_string(r##"{
+            "#00c000": {
+                "glyph_image": ["samples/tree_glyph.png", { "image": "samples/grass_glyph.png", "weight": 3, "anchor": [2, 2] }],
+                "glyph_flip": true,
+                "glyph_scale": [0.5, 1.5],
+                "glyph_tint": "#406020"
+            },
+            "#80ff00": { "glyph_image": "samples/grass_glyph.png", "glyph_scale": 2 }
+        }"##).unwrap();
+
+        let settings = configuration.biome(forest).unwrap();
+        assert_eq!(settings.glyph_images[1], GlyphVariant::new("samples/grass_glyph.png", 3.0).anchored(2, 2));
+        assert_eq!(settings.glyph_scale, (0.5, 1.5));
+        assert_eq!(settings.glyph_tint, Some((Color::from([64, 96, 32]), DEFAULT_TINT_AMOUNT)));
+        assert_eq!(configuration.biome(grass).unwrap().glyph_scale, (2.0, 2.0));
+        assert_eq!(configuration.glyphs.len(), 2);
+
+        let reloaded = Configuration::from_string(configuration.to_json().dump()).unwrap();
+        assert_eq!(configuration.biomes().collect::<Vec<_>>(), reloaded.biomes().collect::<Vec<_>>());
+
+        configuration.remove_biome(forest);
+        assert_eq!(configuration.glyphs.keys().collect::<Vec<_>>(), vec![&PathBuf::from("samples/grass_glyph.png")]);
+
+        assert!(Configuration::from_string(r##"{ "#00c000": { "glyph_scale": [2, 1] } }"##).is_err());
+        assert!(Configuration::from_string(r##"{ "#00c000": { "glyph_image": [{ "weight": 1 }] } }"##).is_err());
+        assert!(Configuration::from_string(r##"{ "#00c000": { "glyph_image": [{ "image": "samples/tree_glyph.png", "anchor": 2 }] } }"##).is_err());
+
+        // Anchors must be a pixel of their glyph.
+        let grass_glyph = &configuration.glyphs[&PathBuf::from("samples/grass_glyph.png")];
+        let (width, height) = (grass_glyph.width(), grass_glyph.height());
+        let outside = format!(r##"{{ "#00c000": {{ "glyph_image": [{{ "image": "samples/grass_glyph.png", "anchor": [{}, 0] }}] }} }}"##, width);
+        let error = Configuration::from_string(outside).err().unwrap();
+        assert!(matches!(error, Error::Config { .. }));
+        assert!(error.to_string().starts_with("invalid glyph_image for biome #00c000: "));
+        let outside = format!(r##"{{ "#00c000": {{ "glyph_image": [{{ "image": "samples/grass_glyph.png", "anchor": [0, {}] }}] }} }}"##, height);
+        assert!(Configuration::from_string(outside).is_err());
+        let corner = format!(r##"{{ "#00c000": {{ "glyph_image": [{{ "image": "samples/grass_glyph.png", "anchor": [{}, {}] }}] }} }}"##, width - 1, height - 1);
+        assert!(Configuration::from_string(corner).is_ok());
     }
 
     #[test]
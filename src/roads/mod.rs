@@ -0,0 +1,410 @@
+use json::JsonValue;
+
+use crate::error::{Error, Result};
+use crate::image::draw::{self, LineCap, LineJoin, Mask};
+use crate::image::{Color, Image};
+use crate::section::Section;
+use crate::vector::Vector;
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+mod routing;
+
+pub use routing::{CostSurface, RoutingSettings};
+
+/// Number of times corners are cut when an edge does not set its own smoothing.
+const DEFAULT_SMOOTHING: usize = 3;
+
+/// How a road is drawn.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RoadStyle {
+    /// Width of the road in pixels.
+    pub width: f64,
+    pub color: Color,
+    /// Width of the casing drawn either side of the road, 0 for no casing.
+    pub casing_width: f64,
+    pub casing_color: Color,
+    /// Lengths of each dash and of the gaps between them, for trails drawn as dashed lines.
+    pub dash: Option<(f64, f64)>,
+    pub cap: LineCap,
+    pub join: LineJoin,
+}
+
+impl Default for RoadStyle {
+    fn default() -> Self {
+        RoadStyle {
+            width: 2.0,
+            color: Color::from([139, 90, 43]),
+            casing_width: 0.0,
+            casing_color: Color::from([0, 0, 0]),
+            dash: None,
+            cap: LineCap::Round,
+            join: LineJoin::Round,
+        }
+    }
+}
+
+/// A road between two named waypoints.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Edge {
+    pub from: String,
+    pub to: String,
+    /// Points the road passes through on the way, in order.
+    pub via: Vec<Vector<2>>,
+    pub style: RoadStyle,
+    /// Number of times the corners of the road are cut to round them off, 0 for straight lines.
+    pub smoothing: usize,
+    /// Whether the road should find its own way over the land, passing through each `via` point in turn.
+    pub route: bool,
+}
+
+impl Edge {
+    /// A straight road with the default style.
+    pub fn new(from: impl Into<String>, to: impl Into<String>) -> Self {
+        Edge {
+            from: from.into(),
+            to: to.into(),
+            via: Vec::new(),
+            style: RoadStyle::default(),
+            smoothing: DEFAULT_SMOOTHING,
+            route: false,
+        }
+    }
+}
+
+/// Named waypoints in pixel coordinates, along with the roads between them.
+#[derive(Clone, Default)]
+pub struct Network {
+    waypoints: Vec<(String, Vector<2>)>,
+    edges: Vec<Edge>,
+}
+
+impl Network {
+    /// Creates a network without any waypoints or roads.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads a network from a json file.
+    pub fn from_file(file: impl AsRef<OsStr>) -> Result<Self> {
+        let path = PathBuf::from(file.as_ref());
+        let io_error = |source| Error::Io { path: path.clone(), source };
+
+        let mut file = std::fs::File::open(&path).map_err(io_error)?;
+        let mut string = String::new();
+        file.read_to_string(&mut string).map_err(io_error)?;
+        return Self::from_string(string);
+    }
+
+    /// Parses a network from a json string.
+    pub fn from_string(string: impl AsRef<str>) -> Result<Self> {
+        let json = json::parse(string.as_ref())?;
+        return Self::from_json(json);
+    }
+
+    /// Builds a network from parsed json.
+    pub fn from_json(json: JsonValue) -> Result<Self> {
+        let root = Section { json: &json, prefix: String::new(), base: Path::new("") };
+        let mut network = Network::new();
+
+        let mut styles = HashMap::new();
+        for (name, style) in json["styles"].entries() {
+            let style = Section { json: style, prefix: format!("styles.{}.", name), base: root.base };
+            styles.insert(name.to_string(), parse_style(&style)?);
+        }
+
+        let waypoints = Section { json: &json["waypoints"], prefix: String::from("waypoints."), base: root.base };
+        for (name, _) in json["waypoints"].entries() {
+            network.add_waypoint(name, waypoints.vector(name, Vector::new())?);
+        }
+
+        if !json["edges"].is_array() {
+            return Err(root.error("edges", "must be an array of edges"));
+        }
+
+        for (i, edge) in json["edges"].members().enumerate() {
+            let edge = Section { json: edge, prefix: format!("edges[{}].", i), base: root.base };
+
+            let mut endpoints = Vec::new();
+            for key in ["from", "to"] {
+                match edge.json[key].as_str() {
+                    Some(name) if network.waypoint(name).is_some() => endpoints.push(name.to_string()),
+                    Some(name) => return Err(edge.error(key, format!("unknown waypoint {}", name))),
+                    None => return Err(edge.error(key, "must be the name of a waypoint")),
+                }
+            }
+
+            let mut via = Vec::new();
+            for point in edge.json["via"].members() {
+                match point.members().map(|v| v.as_f64()).collect::<Vec<_>>()[..] {
+                    [Some(x), Some(y)] => via.push(Vector::from([x, y])),
+                    _ => return Err(edge.error("via", "must be an array of [x, y] points")),
+                }
+            }
+
+            let style = match &edge.json["style"] {
+                JsonValue::Object(_) => parse_style(&Section { json: &edge.json["style"], prefix: edge.key("style."), base: edge.base })?,
+                s => match s.as_str() {
+                    Some(name) => match styles.get(name) {
+                        Some(style) => *style,
+                        None => return Err(edge.error("style", format!("unknown style {}", name))),
+                    },
+                    None if edge.json.has_key("style") => return Err(edge.error("style", "must be a style name or object")),
+                    None => RoadStyle::default(),
+                },
+            };
+
+            let smoothing = edge.number("smoothing", DEFAULT_SMOOTHING as f64)? as usize;
+
+            let route = edge.flag("route", false)?;
+
+            let to = endpoints.pop().unwrap();
+            let from = endpoints.pop().unwrap();
+            network.add_edge(Edge { from, to, via, style, smoothing, route })?;
+        }
+
+        return Ok(network);
+    }
+
+    /// Adds a waypoint, moving any existing waypoint with the same name.
+    pub fn add_waypoint(&mut self, name: impl Into<String>, position: Vector<2>) {
+        let name = name.into();
+        self.waypoints.retain(|(n, _)| *n != name);
+        self.waypoints.push((name, position));
+    }
+
+    /// Returns the position of the waypoint with the given name.
+    pub fn waypoint(&self, name: &str) -> Option<Vector<2>> {
+        return self.waypoints.iter().find(|(n, _)| n == name).map(|(_, p)| *p);
+    }
+
+    /// Returns every waypoint name and position.
+    pub fn waypoints(&self) -> impl Iterator<Item = (&str, Vector<2>)> {
+        return self.waypoints.iter().map(|(n, p)| (n.as_str(), *p));
+    }
+
+    /// Adds a road. Both of its ends must already be waypoints.
+    pub fn add_edge(&mut self, edge: Edge) -> Result<()> {
+        for name in [&edge.from, &edge.to] {
+            if self.waypoint(name).is_none() {
+                return Err(Error::config("edges", None, format!("unknown waypoint {}", name)));
+            }
+        }
+
+        self.edges.push(edge);
+        return Ok(());
+    }
+
+    /// Returns every road, in drawing order.
+    pub fn edges(&self) -> &[Edge] {
+        &self.edges
+    }
+
+    /// Converts the network into the json format read by `from_json`, with every style written inline.
+    pub fn to_json(&self) -> JsonValue {
+        let mut json = JsonValue::new_object();
+        let point = |p: &Vector<2>| json::array![p[0], p[1]];
+
+        json["waypoints"] = JsonValue::new_object();
+        for (name, position) in &self.waypoints {
+            json["waypoints"][name.as_str()] = point(position);
+        }
+
+        json["edges"] = JsonValue::new_array();
+        for edge in &self.edges {
+            let mut style = JsonValue::new_object();
+            style["width"] = edge.style.width.into();
+            style["color"] = edge.style.color.to_hex().into();
+            style["casing_width"] = edge.style.casing_width.into();
+            style["casing_color"] = edge.style.casing_color.to_hex().into();
+            if let Some((on, off)) = edge.style.dash {
+                style["dash"] = json::array![on, off];
+            }
+            style["cap"] = edge.style.cap.name().into();
+            style["join"] = edge.style.join.name().into();
+
+            json["edges"].push(json::object! {
+                from: edge.from.as_str(),
+                to: edge.to.as_str(),
+                via: JsonValue::Array(edge.via.iter().map(point).collect()),
+                style: style,
+                smoothing: edge.smoothing,
+                route: edge.route,
+            }).unwrap();
+        }
+
+        return json;
+    }
+
+    /// Writes the network to a json file.
+    pub fn write_to_file(&self, file: impl AsRef<OsStr>) -> Result<()> {
+        let path = PathBuf::from(file.as_ref());
+        return std::fs::write(&path, self.to_json().pretty(2)).map_err(|source| Error::Io { path, source });
+    }
+
+    /// Whether any road still needs to find its way over the land.
+    pub fn needs_routing(&self) -> bool {
+        self.edges.iter().any(|e| e.route)
+    }
+
+    /// Finds the cheapest way over the surface for every road marked to be routed, through each of its `via` points.
+    /// The points of the route found replace its `via` points, and the road is no longer marked.
+    pub fn route(&mut self, surface: &CostSurface) -> Result<()> {
+        for i in 0..self.edges.len() {
+            if !self.edges[i].route {
+                continue;
+            }
+
+            let edge = &self.edges[i];
+            let mut stops = vec![(edge.from.clone(), self.waypoint(&edge.from).unwrap())];
+            stops.extend(edge.via.iter().map(|p| (format!("({}, {})", p[0], p[1]), *p)));
+            stops.push((edge.to.clone(), self.waypoint(&edge.to).unwrap()));
+
+            let mut path: Vec<Vector<2>> = Vec::new();
+            for pair in stops.windows(2) {
+                let ((from_name, from), (to_name, to)) = (&pair[0], &pair[1]);
+                let (from, to) = (surface.pixel_of(*from)?, surface.pixel_of(*to)?);
+                let leg = match surface.route(from, to) {
+                    Some(leg) => leg,
+                    None => return Err(Error::NoRoute { from: from_name.clone(), to: to_name.clone() }),
+                };
+
+                // Each leg starts where the last one finished.
+                let skip = if path.is_empty() { 0 } else { 1 };
+                path.extend(leg.into_iter().skip(skip));
+            }
+
+            let edge = &mut self.edges[i];
+            edge.via = path.get(1..path.len().saturating_sub(1)).unwrap_or_default().to_vec();
+            edge.route = false;
+        }
+
+        return Ok(());
+    }
+
+    /// Returns the smoothed lines every road is drawn along, in drawing order.
+    pub fn polylines(&self) -> Vec<Vec<Vector<2>>> {
+        return self.edges.iter().map(|e| self.polyline(e)).collect();
+    }
+
+    /// Returns the smoothed line a road is drawn along.
+    pub fn polyline(&self, edge: &Edge) -> Vec<Vector<2>> {
+        let mut points = Vec::new();
+        points.extend(self.waypoint(&edge.from));
+        points.extend(edge.via.iter().copied());
+        points.extend(self.waypoint(&edge.to));
+        return draw::smooth(&points, edge.smoothing);
+    }
+
+    /// Draws every road, with all casings beneath all roads so that crossing roads join up.
+    pub fn generate_layer(&self, width: usize, height: usize) -> Image {
+        let mut layer = Image::new(width, height);
+        let lines: Vec<_> = self.edges.iter().map(|e| e.style).zip(self.polylines()).collect();
+
+        for (style, line) in &lines {
+            if style.casing_width > 0.0 {
+                let mut mask = Mask::new(width, height);
+                mask.polyline(line, style.width + 2.0 * style.casing_width, style.cap, style.join);
+                mask.paint(&mut layer, style.casing_color).unwrap();
+            }
+        }
+
+        for (style, line) in &lines {
+            let pieces = match style.dash {
+                Some((on, off)) => draw::dash(line, on, off),
+                None => vec![line.clone()],
+            };
+
+            let mut mask = Mask::new(width, height);
+            for piece in &pieces {
+                mask.polyline(piece, style.width, style.cap, style.join);
+            }
+            mask.paint(&mut layer, style.color).unwrap();
+        }
+
+        return layer;
+    }
+}
+
+fn parse_style(style: &Section) -> Result<RoadStyle> {
+    let default = RoadStyle::default();
+
+    let dash = match style.json["dash"].members().map(|v| v.as_f64()).collect::<Vec<_>>()[..] {
+        [Some(on), Some(off)] if on > 0.0 && off > 0.0 => Some((on, off)),
+        _ if style.json.has_key("dash") => return Err(style.error("dash", "must be an array of the dash and gap lengths")),
+        _ => default.dash,
+    };
+
+    return Ok(RoadStyle {
+        width: style.number("width", default.width)?,
+        color: style.color("color", default.color)?,
+        casing_width: style.number("casing_width", default.casing_width)?,
+        casing_color: style.color("casing_color", default.casing_color)?,
+        dash,
+        cap: style.named("cap", default.cap, "must be butt, round or square")?,
+        join: style.named("join", default.join, "must be miter, round or bevel")?,
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn read_network() {
+        let network = Network::from_string(r##"{
+            "styles": { "trail": { "width": 1.5, "dash": [4, 3], "cap": "butt" } },
+            "waypoints": { "town": [10, 10], "castle": [50, 40], "mine": [20, 45] },
+            "edges": [
+                { "from": "town", "to": "castle", "via": [[40, 12]], "style": { "casing_width": 1, "join": "miter" } },
+                { "from": "castle", "to": "mine", "style": "trail", "smoothing": 0 }
+            ]
+        }"##).unwrap();
+
+        assert_eq!(network.waypoint("castle"), Some(Vector::from([50.0, 40.0])));
+        assert_eq!(network.edges()[0].style.join, LineJoin::Miter);
+        assert_eq!(network.edges()[1].style.dash, Some((4.0, 3.0)));
+        assert_eq!(network.polyline(&network.edges()[1]), vec![Vector::from([50.0, 40.0]), Vector::from([20.0, 45.0])]);
+        assert!(network.polyline(&network.edges()[0]).len() > 3);
+
+        let layer = network.generate_layer(60, 50);
+        assert_eq!(layer.pixel_at(10, 10), Some(RoadStyle::default().color));
+        assert_eq!(layer.pixel_at(0, 0), Some(Color::from([0, 0, 0, 0])));
+    }
+
+    #[test]
+    fn route_network() {
+        let mut network = Network::from_string(r#"{
+            "waypoints": { "a": [2, 2], "b": [27, 17] },
+            "edges": [{ "from": "a", "to": "b", "via": [[27, 2]], "route": true }]
+        }"#).unwrap();
+        assert!(network.needs_routing());
+
+        network.route(&CostSurface::new(30, 20, RoutingSettings::default())).unwrap();
+        assert!(!network.needs_routing());
+        assert_eq!(network.edges()[0].via, vec![Vector::from([27.0, 2.0])]);
+
+        let reloaded = Network::from_json(network.to_json()).unwrap();
+        assert_eq!(reloaded.edges(), network.edges());
+        assert_eq!(reloaded.polylines(), network.polylines());
+
+        network.add_waypoint("c", Vector::from([40.0, 2.0]));
+        network.add_edge(Edge { route: true, ..Edge::new("a", "c") }).unwrap();
+        assert!(matches!(network.route(&CostSurface::new(30, 20, RoutingSettings::default())), Err(Error::OutOfBounds { .. })));
+    }
+
+    #[test]
+    fn invalid_networks() {
+        assert!(Network::from_string(r#"{ "waypoints": { "a": [0, 0] } }"#).is_err());
+        assert!(Network::from_string(r#"{ "waypoints": { "a": [0] }, "edges": [] }"#).is_err());
+        assert!(Network::from_string(r#"{ "waypoints": { "a": [0, 0] }, "edges": [{ "from": "a", "to": "b" }] }"#).is_err());
+        assert!(Network::from_string(r#"{ "waypoints": { "a": [0, 0] }, "edges": [{ "from": "a", "to": "a", "style": "road" }] }"#).is_err());
+
+        match Network::from_string(r#"{ "styles": { "road": { "cap": "pointy" } }, "edges": [] }"#) {
+            Err(Error::Config { key, .. }) => assert_eq!(key, "styles.road.cap"),
+            _ => panic!("expected a config error"),
+        }
+    }
+}
@@ -0,0 +1,270 @@
+use crate::error::{Error, Result};
+use crate::image::Image;
+use crate::region::Configuration;
+use crate::topography::HeightMap;
+use crate::vector::Vector;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+/// Offsets of the eight neighbours a route can step to, along with the length of each step.
+const STEPS: [(isize, isize, f64); 8] = [
+    (-1, 0, 1.0), (1, 0, 1.0), (0, -1, 1.0), (0, 1, 1.0),
+    (-1, -1, std::f64::consts::SQRT_2), (-1, 1, std::f64::consts::SQRT_2),
+    (1, -1, std::f64::consts::SQRT_2), (1, 1, std::f64::consts::SQRT_2),
+];
+
+/// How the shape of the land affects the cost of a route.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RoutingSettings {
+    /// Height in pixels of the highest possible heightmap value, used to turn heights into grades.
+    pub height_scale: f64,
+    /// Extra cost of each pixel travelled for every unit of grade climbed or descended.
+    pub slope_cost: f64,
+    /// Steepest grade a road may take, steeper steps are impassable.
+    pub max_grade: f64,
+    /// Largest distance in pixels a routed road may stray from the path found before it is simplified.
+    pub simplify_tolerance: f64,
+}
+
+impl Default for RoutingSettings {
+    fn default() -> Self {
+        RoutingSettings {
+            height_scale: 100.0,
+            slope_cost: 20.0,
+            max_grade: f64::INFINITY,
+            simplify_tolerance: 1.0,
+        }
+    }
+}
+
+/// Cost of travelling over each pixel of the map, along with the heights used to add the cost of climbing.
+pub struct CostSurface {
+    width: usize,
+    height: usize,
+    costs: Vec<f64>,
+    heights: Option<Vec<f64>>,
+    settings: RoutingSettings,
+}
+
+impl CostSurface {
+    /// A flat surface where every pixel costs the same to cross.
+    pub fn new(width: usize, height: usize, settings: RoutingSettings) -> Self {
+        CostSurface { width, height, costs: vec![1.0; width * height], heights: None, settings }
+    }
+
+    /// Scales the cost of each pixel by the travel cost of its biome.
+    /// Colors without a biome in the configuration keep their cost.
+    pub fn with_biomes(mut self, biome_map: &Image, configuration: &Configuration) -> Result<Self> {
+        if biome_map.width() != self.width || biome_map.height() != self.height {
+            return Err(Error::DimensionMismatch { expected: (self.width, self.height), found: (biome_map.width(), biome_map.height()) });
+        }
+
+        for (cost, color) in self.costs.iter_mut().zip(biome_map.data()) {
+            if let Some(biome) = configuration.biome(*color) {
+                *cost *= biome.travel_cost;
+            }
+        }
+
+        return Ok(self);
+    }
+
+    /// Adds the cost of climbing the heightmap to every step.
+    pub fn with_heightmap(mut self, heightmap: &HeightMap) -> Result<Self> {
+        if heightmap.width() != self.width || heightmap.height() != self.height {
+            return Err(Error::DimensionMismatch { expected: (self.width, self.height), found: (heightmap.width(), heightmap.height()) });
+        }
+
+        let scale = self.settings.height_scale / u16::MAX as f64;
+        self.heights = Some(heightmap.data().iter().map(|h| *h as f64 * scale).collect());
+        return Ok(self);
+    }
+
+    /// Returns the cost of crossing a pixel before slope is taken into account. Returns None if out of bounds.
+    pub fn cost_at(&self, x: usize, y: usize) -> Option<f64> {
+        if x < self.width && y < self.height {
+            Some(self.costs[x + y * self.width])
+        } else {
+            None
+        }
+    }
+
+    /// Rounds a point to the pixel it lies in, which must be on the surface.
+    pub fn pixel_of(&self, point: Vector<2>) -> Result<(usize, usize)> {
+        let (x, y) = (point[0].round() as isize, point[1].round() as isize);
+        if x < 0 || y < 0 || x >= self.width as isize || y >= self.height as isize {
+            return Err(Error::OutOfBounds { x, y, width: self.width, height: self.height });
+        }
+        return Ok((x as usize, y as usize));
+    }
+
+    /// Cost of stepping between two neighbouring pixels, infinite if the step is impassable.
+    fn step_cost(&self, from: usize, to: usize, length: f64) -> f64 {
+        // Each pixel is crossed half way during the step.
+        let mut cost = length * (self.costs[from] + self.costs[to]) / 2.0;
+
+        if let Some(heights) = &self.heights {
+            let grade = (heights[to] - heights[from]).abs() / length;
+            if grade > self.settings.max_grade {
+                return f64::INFINITY;
+            }
+            cost += length * grade * self.settings.slope_cost;
+        }
+
+        return cost;
+    }
+
+    /// Finds the cheapest route between two pixels with A*, moving between neighbouring pixels.
+    /// The route is simplified so that it can be smoothed into a road. Returns None if there is no passable route.
+    pub fn route(&self, from: (usize, usize), to: (usize, usize)) -> Option<Vec<Vector<2>>> {
+        if from.0 >= self.width || from.1 >= self.height || to.0 >= self.width || to.1 >= self.height {
+            return None;
+        }
+
+        let index = |p: (usize, usize)| p.0 + p.1 * self.width;
+        let (start, goal) = (index(from), index(to));
+
+        // Steps never cost less than the cheapest pixel, which keeps the estimate from overshooting.
+        let cheapest = self.costs.iter().copied().fold(f64::INFINITY, f64::min).max(0.0);
+        let cheapest = if cheapest.is_finite() { cheapest } else { 0.0 };
+        let estimate = |i: usize| {
+            let dx = (i % self.width).abs_diff(to.0) as f64;
+            let dy = (i / self.width).abs_diff(to.1) as f64;
+            (dx.max(dy) + (std::f64::consts::SQRT_2 - 1.0) * dx.min(dy)) * cheapest
+        };
+
+        let mut best = vec![f64::INFINITY; self.costs.len()];
+        let mut previous = vec![usize::MAX; self.costs.len()];
+        let mut open = BinaryHeap::new();
+
+        best[start] = 0.0;
+        open.push(Candidate { priority: estimate(start), index: start });
+
+        while let Some(Candidate { priority, index: current }) = open.pop() {
+            if current == goal {
+                break;
+            }
+
+            // Skip entries that were superseded by a cheaper route.
+            if priority > best[current] + estimate(current) {
+                continue;
+            }
+
+            let (x, y) = ((current % self.width) as isize, (current / self.width) as isize);
+            for (dx, dy, length) in STEPS {
+                let (nx, ny) = (x + dx, y + dy);
+                if nx < 0 || ny < 0 || nx >= self.width as isize || ny >= self.height as isize {
+                    continue;
+                }
+
+                let next = nx as usize + ny as usize * self.width;
+                let cost = best[current] + self.step_cost(current, next, length);
+                if cost < best[next] {
+                    best[next] = cost;
+                    previous[next] = current;
+                    open.push(Candidate { priority: cost + estimate(next), index: next });
+                }
+            }
+        }
+
+        if !best[goal].is_finite() {
+            return None;
+        }
+
+        let mut path = vec![goal];
+        while *path.last().unwrap() != start {
+            path.push(previous[*path.last().unwrap()]);
+        }
+        path.reverse();
+
+        let points: Vec<Vector<2>> = path.into_iter()
+            .map(|i| Vector::from([(i % self.width) as f64, (i / self.width) as f64]))
+            .collect();
+
+        return Some(crate::image::draw::simplify(&points, self.settings.simplify_tolerance));
+    }
+}
+
+/// An entry in the A* queue, ordered so the lowest priority is popped first.
+struct Candidate {
+    priority: f64,
+    index: usize,
+}
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.priority.total_cmp(&self.priority).then_with(|| other.index.cmp(&self.index))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::image::Color;
+    use crate::region::BiomeSettings;
+
+    #[test]
+    fn routes_avoid_costly_terrain() {
+        let land = Color::from([0, 192, 0]);
+        let water = Color::from([64, 255, 255]);
+
+        // A lake fills the middle of the map apart from a gap along the bottom.
+        let mut map = Image::new(30, 20).fill(land);
+        for x in 10..20 {
+            for y in 0..17 {
+                map.set_pixel_at(x, y, water).unwrap();
+            }
+        }
+
+        let configuration = Configuration::builder()
+            .biome(water, BiomeSettings { travel_cost: f64::INFINITY, ..BiomeSettings::new(water) })
+            .build()
+            .unwrap();
+
+        let surface = CostSurface::new(30, 20, RoutingSettings::default()).with_biomes(&map, &configuration).unwrap();
+        let route = surface.route((2, 5), (27, 5)).unwrap();
+        assert_eq!(route.first(), Some(&Vector::from([2.0, 5.0])));
+        assert_eq!(route.last(), Some(&Vector::from([27.0, 5.0])));
+        assert!(route.iter().any(|p| p[1] >= 17.0));
+
+        for x in 10..20 {
+            map.set_pixel_at(x, 19, water).unwrap();
+            map.set_pixel_at(x, 18, water).unwrap();
+            map.set_pixel_at(x, 17, water).unwrap();
+        }
+        let surface = CostSurface::new(30, 20, RoutingSettings::default()).with_biomes(&map, &configuration).unwrap();
+        assert!(surface.route((2, 5), (27, 5)).is_none());
+    }
+
+    #[test]
+    fn routes_wind_around_hills() {
+        // A tall ridge runs down the middle, lowest at its top end.
+        let mut heightmap = HeightMap::new(40, 40);
+        for x in 15..25 {
+            for y in 0..40 {
+                heightmap.set_height_at(x, y, (y * 1500) as u16).unwrap();
+            }
+        }
+
+        let flat = CostSurface::new(40, 40, RoutingSettings::default());
+        assert_eq!(flat.route((5, 30), (35, 30)).unwrap().len(), 2);
+
+        let hilly = CostSurface::new(40, 40, RoutingSettings::default()).with_heightmap(&heightmap).unwrap();
+        let route = hilly.route((5, 30), (35, 30)).unwrap();
+        let crossing = route.iter().map(|p| p[1]).fold(f64::INFINITY, f64::min);
+        assert!(crossing < 10.0, "crossed the ridge at {}", crossing);
+    }
+}
@@ -0,0 +1,112 @@
+use json::JsonValue;
+
+use crate::error::{Error, Result};
+use crate::image::{Color, ColorRamp};
+use crate::vector::Vector;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// A json object within a project or other settings file, along with its location for error messages.
+pub(crate) struct Section<'a> {
+    pub json: &'a JsonValue,
+    pub prefix: String,
+    /// Directory relative paths are resolved against, such as the one holding the project file.
+    pub base: &'a Path,
+}
+
+impl<'a> Section<'a> {
+    pub fn key(&self, key: &str) -> String {
+        format!("{}{}", self.prefix, key)
+    }
+
+    pub fn error(&self, key: &str, reason: impl Into<String>) -> Error {
+        Error::config(self.key(key), None, reason)
+    }
+
+    pub fn path(&self, key: &str) -> Result<Option<PathBuf>> {
+        return match self.json[key].as_str() {
+            Some(s) => Ok(Some(self.base.join(s))),
+            None if self.json.has_key(key) => Err(self.error(key, "must be a filepath string")),
+            _ => Ok(None),
+        };
+    }
+
+    pub fn color(&self, key: &str, default: Color) -> Result<Color> {
+        return match self.json[key].as_str() {
+            Some(s) => Color::from_str(s).map_err(|e| Error::Config {
+                key: self.key(key),
+                biome: None,
+                reason: String::from("not a valid color"),
+                source: Some(Box::new(e)),
+            }),
+            None if self.json.has_key(key) => Err(self.error(key, "must be a color string")),
+            _ => Ok(default),
+        };
+    }
+
+    pub fn number(&self, key: &str, default: f64) -> Result<f64> {
+        return match self.json[key].as_f64() {
+            Some(n) if n >= 0.0 => Ok(n),
+            _ if self.json.has_key(key) => Err(self.error(key, "must be a positive number")),
+            _ => Ok(default),
+        };
+    }
+
+    pub fn flag(&self, key: &str, default: bool) -> Result<bool> {
+        return match self.json[key].as_bool() {
+            Some(b) => Ok(b),
+            None if self.json.has_key(key) => Err(self.error(key, "must be true or false")),
+            None => Ok(default),
+        };
+    }
+
+    pub fn divisions(&self, default: u16) -> Result<u16> {
+        return match self.json["line_divisions"].as_u16() {
+            Some(n) if n > 0 => Ok(n),
+            _ if self.json.has_key("line_divisions") => Err(self.error("line_divisions", "must be between 1 and 65535")),
+            _ => Ok(default),
+        };
+    }
+
+    /// Parses a string setting such as a mode name, using `FromStr`.
+    pub fn named<T: FromStr<Err = Error>>(&self, key: &str, default: T, reason: &str) -> Result<T> {
+        return match self.json[key].as_str() {
+            Some(s) => T::from_str(s).map_err(|e| Error::Config {
+                key: self.key(key),
+                biome: None,
+                reason: reason.to_string(),
+                source: Some(Box::new(e)),
+            }),
+            None if self.json.has_key(key) => Err(self.error(key, "must be a string")),
+            _ => Ok(default),
+        };
+    }
+
+    /// Parses a color ramp given as a json file path ending in `.json`, or in any form `ColorRamp::from_json` accepts.
+    pub fn ramp(&self, key: &str, default: ColorRamp) -> Result<ColorRamp> {
+        let ramp = match self.json[key].as_str() {
+            _ if !self.json.has_key(key) => return Ok(default),
+            Some(path) if path.ends_with(".json") => ColorRamp::from_file(self.base.join(path)),
+            _ => ColorRamp::from_json(self.json[key].clone()),
+        };
+
+        return ramp.map_err(|e| Error::Config {
+            key: self.key(key),
+            biome: None,
+            reason: String::from("not a valid color ramp"),
+            source: Some(Box::new(e)),
+        });
+    }
+
+    pub fn vector(&self, key: &str, default: Vector<2>) -> Result<Vector<2>> {
+        if !self.json.has_key(key) {
+            return Ok(default);
+        }
+
+        let components: Vec<_> = self.json[key].members().map(|v| v.as_f64()).collect();
+        return match components[..] {
+            [Some(x), Some(y)] => Ok(Vector::from([x, y])),
+            _ => Err(self.error(key, "must be an array of two numbers")),
+        };
+    }
+}
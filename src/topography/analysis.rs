@@ -0,0 +1,108 @@
+use super::*;
+use crate::error::Error;
+
+/// Which property of the terrain an analysis layer shows.
+#[derive(Copy, Clone, Debug, PartialEq, Default)]
+pub enum Analysis {
+    /// Steepness in degrees, from 0 to 90.
+    #[default]
+    Slope,
+    /// Compass bearing in degrees the ground faces, from 0 to 360.
+    Aspect,
+}
+
+impl Analysis {
+    /// The name used for this analysis in configuration files.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Analysis::Slope => "slope",
+            Analysis::Aspect => "aspect",
+        }
+    }
+
+    /// The full range of values the analysis can take.
+    pub fn range(&self) -> (f64, f64) {
+        match self {
+            Analysis::Slope => (0.0, 90.0),
+            Analysis::Aspect => (0.0, 360.0),
+        }
+    }
+}
+
+impl std::str::FromStr for Analysis {
+    type Err = Error;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "slope" => Ok(Analysis::Slope),
+            "aspect" => Ok(Analysis::Aspect),
+            _ => Err(Error::InvalidValue { expected: "terrain analysis", value: s.to_string() }),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Settings {
+    /// Property of the terrain to show.
+    pub analysis: Analysis,
+    /// Width of a cell, in the same units as `vertical_scale`.
+    pub cell_size: f64,
+    /// Elevation of the highest possible heightmap value.
+    pub vertical_scale: f64,
+    /// Colors given to values from the start of the range to its end.
+    pub ramp: ColorRamp,
+    /// Values placed at either end of the ramp, the full range of the analysis if None.
+    pub range: Option<(f64, f64)>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            analysis: Analysis::Slope,
+            cell_size: 1.0,
+            vertical_scale: 100.0,
+            ramp: ColorRamp::greyscale(),
+            range: None,
+        }
+    }
+}
+
+/// Colors every cell of the heightmap by its slope or aspect. Flat cells have no aspect and are left transparent.
+pub fn generate(heightmap: &HeightMap, settings: Settings) -> Image {
+    let raster = match settings.analysis {
+        Analysis::Slope => heightmap.slope(settings.cell_size, settings.vertical_scale),
+        Analysis::Aspect => heightmap.aspect(settings.cell_size, settings.vertical_scale),
+    };
+
+    let (min, max) = settings.range.unwrap_or_else(|| settings.analysis.range());
+    return raster.render(&settings.ramp, min, max);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn steep_slopes_are_marked() {
+        // Flat ground to the west and a steep rise to the east.
+        let mut heightmap = HeightMap::new(20, 5);
+        for x in 10..20 {
+            for y in 0..5 {
+                heightmap.set_height_at(x, y, (x - 10) as u16 * 6000).unwrap();
+            }
+        }
+
+        // Mark ground steeper than 30 degrees in red.
+        let clear = Color::from([0, 0, 0, 0]);
+        let red = Color::from([255, 0, 0, 255]);
+        let ramp = ColorRamp::new(vec![(0.0, clear), (0.5, clear), (0.5, red)]).unwrap();
+        let settings = Settings { ramp, range: Some((0.0, 60.0)), ..Settings::default() };
+        let slope = generate(&heightmap, settings.clone());
+        assert_eq!(slope.pixel_at(5, 2), Some(clear));
+        assert_eq!(slope.pixel_at(15, 2), Some(red));
+
+        // Flat ground faces no direction, and the slope faces west beyond the end of the range.
+        let aspect = generate(&heightmap, Settings { analysis: Analysis::Aspect, ..settings });
+        assert_eq!(aspect.pixel_at(5, 2), Some(clear));
+        assert_eq!(aspect.pixel_at(15, 2), Some(red));
+    }
+}
@@ -0,0 +1,221 @@
+use super::hydrology::{Drainage, FlowMethod};
+use super::*;
+use crate::region::{BiomeSettings, Configuration};
+
+#[derive(Clone, Copy)]
+pub struct Settings {
+    /// How water is shared between the pixels below each pixel.
+    pub method: FlowMethod,
+    /// Heightmap values at or below this are sea, which basins drain into.
+    pub sea_level: u16,
+    /// Basins covering fewer pixels than this are left out, such as the slivers draining straight off the map.
+    pub min_area: usize,
+    /// Whether each basin is filled with its own color, or only outlined.
+    pub fill: bool,
+    /// Color of the outline around each basin.
+    pub outline_color: Color,
+    /// Width of the outline in pixels, 0 for no outline.
+    pub outline_thickness: u64,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            method: FlowMethod::D8,
+            sea_level: 0,
+            min_area: 100,
+            fill: true,
+            outline_color: Color::from([0, 0, 0]),
+            outline_thickness: 1,
+        }
+    }
+}
+
+/// The drainage basin of every pixel of a heightmap: all of the pixels that drain out of the map at the same outlet.
+pub struct Basins {
+    width: usize,
+    height: usize,
+    /// Basin of each pixel.
+    labels: Vec<usize>,
+    /// Where each basin drains out of the map.
+    outlets: Vec<(usize, usize)>,
+    /// Number of pixels in each basin.
+    areas: Vec<usize>,
+}
+
+impl Basins {
+    /// Labels every pixel with the basin it drains into, following the neighbour taking the most water from each pixel.
+    /// Basins are numbered in the order of their outlets, from the top left of the map.
+    pub fn new(drainage: &Drainage) -> Self {
+        let (width, height) = (drainage.filled().width(), drainage.filled().height());
+        let mut labels = vec![usize::MAX; width * height];
+        let mut outlets = Vec::new();
+        let mut areas = Vec::new();
+
+        for y in 0..height {
+            for x in 0..width {
+                if drainage.is_outlet(x, y) {
+                    labels[x + y * width] = outlets.len();
+                    outlets.push((x, y));
+                    areas.push(0);
+                }
+            }
+        }
+
+        let mut path = Vec::new();
+        for i in 0..width * height {
+            // Follow the water down until it reaches a pixel whose basin is already known.
+            let mut current = i;
+            while labels[current] == usize::MAX {
+                path.push(current);
+                let (x, y) = drainage.downstream(current % width, current / width).unwrap();
+                current = x + y * width;
+            }
+
+            for p in path.drain(..) {
+                labels[p] = labels[current];
+            }
+            areas[labels[i]] += 1;
+        }
+
+        Basins { width, height, labels, outlets, areas }
+    }
+
+    /// Getter for the number of basins.
+    pub fn len(&self) -> usize {
+        self.outlets.len()
+    }
+
+    /// Returns whether there are no basins, which is only the case for an empty heightmap.
+    pub fn is_empty(&self) -> bool {
+        self.outlets.is_empty()
+    }
+
+    /// Returns the basin a pixel drains into. Returns None if out of bounds.
+    pub fn basin_at(&self, x: usize, y: usize) -> Option<usize> {
+        if x < self.width && y < self.height {
+            Some(self.labels[x + y * self.width])
+        } else {
+            None
+        }
+    }
+
+    /// Returns the pixel where a basin drains out of the map.
+    pub fn outlet(&self, basin: usize) -> Option<(usize, usize)> {
+        self.outlets.get(basin).copied()
+    }
+
+    /// Returns the number of pixels in a basin.
+    pub fn area(&self, basin: usize) -> Option<usize> {
+        self.areas.get(basin).copied()
+    }
+
+    /// Paints every basin of at least `min_area` pixels in its own key color, leaving smaller basins transparent, so the
+    /// basins can be drawn as the regions of a biome map.
+    pub fn biome_map(&self, min_area: usize) -> Image {
+        let mut map = Image::new(self.width, self.height);
+        for (i, basin) in self.labels.iter().enumerate() {
+            if self.areas[*basin] >= min_area {
+                map.set_pixel_at(i % self.width, i / self.width, key_color(*basin)).unwrap();
+            }
+        }
+        return map;
+    }
+
+    /// Returns a region configuration drawing each basin of at least `min_area` pixels in a distinct color with an outline,
+    /// for the regions of `biome_map`.
+    pub fn configuration(&self, settings: Settings) -> Configuration {
+        let mut configuration = Configuration::new();
+        for basin in (0..self.len()).filter(|b| self.areas[*b] >= settings.min_area) {
+            let mut fill = basin_color(basin);
+            if !settings.fill {
+                fill[3] = 0;
+            }
+
+            let biome = BiomeSettings::new(fill).outline(settings.outline_color, settings.outline_thickness);
+            configuration.add_biome(key_color(basin), biome).unwrap();
+        }
+        return configuration;
+    }
+}
+
+/// A color unique to each basin, for telling basins apart in a biome map.
+fn key_color(basin: usize) -> Color {
+    Color::from([(basin >> 16) as u8, (basin >> 8) as u8, basin as u8])
+}
+
+/// A color for filling a basin, stepping around the color wheel by the golden ratio so neighbouring basins differ.
+pub fn basin_color(basin: usize) -> Color {
+    let hue = (basin as f64 * 0.618_033_988_75).fract() * 6.0;
+    let (saturation, value) = (0.45 + 0.2 * (basin % 3) as f64, 0.95 - 0.15 * (basin % 2) as f64);
+
+    let f = hue.fract();
+    let (p, q, t) = (value * (1.0 - saturation), value * (1.0 - saturation * f), value * (1.0 - saturation * (1.0 - f)));
+    let (r, g, b) = match hue as usize {
+        0 => (value, t, p),
+        1 => (q, value, p),
+        2 => (p, value, t),
+        3 => (p, q, value),
+        4 => (t, p, value),
+        _ => (value, p, q),
+    };
+
+    return Color::from([(r * 255.0).round() as u8, (g * 255.0).round() as u8, (b * 255.0).round() as u8]);
+}
+
+pub fn generate(heightmap: &HeightMap, settings: Settings) -> Image {
+    let drainage = Drainage::new(heightmap, HydrologySettings { method: settings.method, sea_level: settings.sea_level });
+    let basins = Basins::new(&drainage);
+    return basins.configuration(settings).generate_region_layer(&basins.biome_map(settings.min_area)).unwrap();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A ridge running down the middle of a 21x11 heightmap, with valleys falling away from it to the left and right edges.
+    fn ridge() -> HeightMap {
+        let mut heightmap = HeightMap::new(21, 11);
+        for x in 0..21 {
+            for y in 0..11 {
+                let height = 2000 - 100 * (x as i32 - 10).abs() + 60 * (y as i32 - 5).abs();
+                heightmap.set_height_at(x, y, height as u16).unwrap();
+            }
+        }
+        return heightmap;
+    }
+
+    #[test]
+    fn basins_split_at_ridges() {
+        let drainage = Drainage::new(&ridge(), HydrologySettings::default());
+        let basins = Basins::new(&drainage);
+
+        let (west, east) = (basins.basin_at(5, 5).unwrap(), basins.basin_at(15, 5).unwrap());
+        assert_ne!(west, east);
+        assert_eq!(basins.outlet(west), Some((0, 5)));
+        assert_eq!(basins.outlet(east), Some((20, 5)));
+        assert!((1..10).all(|x| basins.basin_at(x, 5) == Some(west)));
+        assert!((11..20).all(|x| basins.basin_at(x, 5) == Some(east)));
+
+        let total: usize = (0..basins.len()).map(|b| basins.area(b).unwrap()).sum();
+        assert_eq!(total, 21 * 11);
+        assert!(basins.area(west).unwrap() > 5);
+    }
+
+    #[test]
+    fn basins_are_colored_and_outlined() {
+        let settings = Settings { min_area: 5, ..Settings::default() };
+        let layer = generate(&ridge(), settings);
+
+        let drainage = Drainage::new(&ridge(), HydrologySettings::default());
+        let basins = Basins::new(&drainage);
+        assert_eq!(layer.pixel_at(5, 5), Some(basin_color(basins.basin_at(5, 5).unwrap())));
+        assert_ne!(layer.pixel_at(5, 5), layer.pixel_at(15, 5));
+        assert_eq!(layer.pixel_at(0, 0).unwrap()[3], 0);
+
+        // Basins meet at the ridge, where each is outlined.
+        let outlines = generate(&ridge(), Settings { fill: false, ..settings });
+        assert_eq!(outlines.pixel_at(10, 5), Some(settings.outline_color));
+        assert_eq!(outlines.pixel_at(5, 5).unwrap()[3], 0);
+    }
+}
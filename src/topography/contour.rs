@@ -8,7 +8,7 @@ pub struct Settings {
     pub line_color: Color,
     /// The color to paint the space without contour lines.
     pub background_color: Color,
-
+    /// Minimum number of neighbouring line pixels a contour pixel needs to survive cleaning.
     pub cleaning_factor: usize,
 }
 
@@ -68,22 +68,22 @@ fn clean_contours(image: &mut Image, settings: Settings) {
                     if image.pixel_at(x - 1, y - 1).unwrap() == settings.line_color {
                         neighbours += 1;
                     }
-                    if image.pixel_at(x - 1, y + 0).unwrap() == settings.line_color {
+                    if image.pixel_at(x - 1, y).unwrap() == settings.line_color {
                         neighbours += 1;
                     }
                     if image.pixel_at(x - 1, y + 1).unwrap() == settings.line_color {
                         neighbours += 1;
                     }
-                    if image.pixel_at(x + 0, y - 1).unwrap() == settings.line_color {
+                    if image.pixel_at(x, y - 1).unwrap() == settings.line_color {
                         neighbours += 1;
                     }
-                    if image.pixel_at(x + 0, y + 1).unwrap() == settings.line_color {
+                    if image.pixel_at(x, y + 1).unwrap() == settings.line_color {
                         neighbours += 1;
                     }
                     if image.pixel_at(x + 1, y - 1).unwrap() == settings.line_color {
                         neighbours += 1;
                     }
-                    if image.pixel_at(x + 1, y + 0).unwrap() == settings.line_color {
+                    if image.pixel_at(x + 1, y).unwrap() == settings.line_color {
                         neighbours += 1;
                     }
                     if image.pixel_at(x + 1, y + 1).unwrap() == settings.line_color {
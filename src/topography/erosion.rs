@@ -0,0 +1,323 @@
+use super::*;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+#[derive(Clone, Copy)]
+pub struct HydraulicSettings {
+    /// Number of raindrops that run down the heightmap, each one carving and filling a little.
+    pub iterations: usize,
+    /// The same seed always drops the rain in the same places.
+    pub seed: u64,
+    /// Most steps a raindrop takes before it runs dry.
+    pub lifetime: usize,
+    /// How much of its direction a raindrop keeps rather than turning down hill, from 0 to 1.
+    pub inertia: f64,
+    /// How much sediment a raindrop can carry for its speed, water and the steepness of its fall.
+    pub capacity: f64,
+    /// Least sediment a raindrop can carry, so it keeps eroding on gentle slopes.
+    pub min_capacity: f64,
+    /// Fraction of its spare capacity a raindrop fills by eroding the ground each step.
+    pub erosion: f64,
+    /// Fraction of its excess sediment a raindrop drops each step.
+    pub deposition: f64,
+    /// Fraction of its water a raindrop loses each step.
+    pub evaporation: f64,
+    /// How quickly raindrops speed up running down hill.
+    pub gravity: f64,
+    /// Radius in pixels of the area a raindrop erodes around itself.
+    pub radius: usize,
+}
+
+impl Default for HydraulicSettings {
+    fn default() -> Self {
+        HydraulicSettings {
+            iterations: 50_000,
+            seed: 0,
+            lifetime: 30,
+            inertia: 0.05,
+            capacity: 4.0,
+            min_capacity: 0.01,
+            erosion: 0.3,
+            deposition: 0.3,
+            evaporation: 0.01,
+            gravity: 4.0,
+            radius: 3,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct ThermalSettings {
+    /// Number of times material slides down every slope steeper than the talus angle.
+    pub iterations: usize,
+    /// Steepest slope in degrees that loose material rests on.
+    pub talus_angle: f64,
+    /// Fraction of the material above the talus angle that slides each iteration, from 0 to 1.
+    pub rate: f64,
+    /// Width of a pixel, in the same units as `vertical_scale`.
+    pub cell_size: f64,
+    /// Elevation of the highest possible heightmap value. The heightmap is left as it is unless this is above 0.
+    pub vertical_scale: f64,
+}
+
+impl Default for ThermalSettings {
+    fn default() -> Self {
+        ThermalSettings {
+            iterations: 50,
+            talus_angle: 35.0,
+            rate: 0.5,
+            cell_size: 1.0,
+            vertical_scale: 100.0,
+        }
+    }
+}
+
+/// Heights of a heightmap from 0 to `scale`, so that many small changes are not lost to rounding.
+fn heights(heightmap: &HeightMap, scale: f64) -> Vec<f64> {
+    heightmap.data().iter().map(|v| *v as f64 / u16::MAX as f64 * scale).collect()
+}
+
+/// A heightmap of the same size with the given heights from 0 to `scale`.
+fn to_heightmap(heightmap: &HeightMap, heights: &[f64], scale: f64) -> HeightMap {
+    let mut result = HeightMap::new(heightmap.width(), heightmap.height());
+    let data = heights.iter().map(|h| (h / scale * u16::MAX as f64).round().clamp(0.0, u16::MAX as f64) as u16).collect();
+    result.set_data(data).unwrap();
+    return result;
+}
+
+/// Calls `progress` with the iterations done and the total whenever another percent is done, and at the end.
+fn report(done: usize, total: usize, progress: &mut impl FnMut(usize, usize)) {
+    if done == total || (done * 100 / total) != ((done - 1) * 100 / total) {
+        progress(done, total);
+    }
+}
+
+/// Height and gradient at a point by bilinear interpolation between the four surrounding pixels.
+/// The point must lie at least one pixel inside the right and bottom edges.
+fn height_and_gradient(heights: &[f64], width: usize, x: f64, y: f64) -> (f64, f64, f64) {
+    let (cx, cy) = (x.floor() as usize, y.floor() as usize);
+    let (u, v) = (x - cx as f64, y - cy as f64);
+    let i = cx + cy * width;
+    let (nw, ne, sw, se) = (heights[i], heights[i + 1], heights[i + width], heights[i + width + 1]);
+
+    let gx = (ne - nw) * (1.0 - v) + (se - sw) * v;
+    let gy = (sw - nw) * (1.0 - u) + (se - ne) * u;
+    let h = nw * (1.0 - u) * (1.0 - v) + ne * u * (1.0 - v) + sw * (1.0 - u) * v + se * u * v;
+    return (h, gx, gy);
+}
+
+/// Simulates raindrops running down the heightmap, each picking up sediment where it speeds up down steep slopes
+/// and dropping it where it slows down, carving valleys and filling their floors. Heights are taken from 0 to 1.
+/// `progress` is called with the raindrops done and the total as the erosion goes on.
+pub fn hydraulic(heightmap: &HeightMap, settings: HydraulicSettings, mut progress: impl FnMut(usize, usize)) -> HeightMap {
+    let (width, height) = (heightmap.width(), heightmap.height());
+    let mut heights = heights(heightmap, 1.0);
+    if width < 2 || height < 2 {
+        return heightmap.clone();
+    }
+
+    // Each raindrop erodes the pixels within the radius around it, weighted towards the middle.
+    let r = settings.radius.max(1) as isize;
+    let brush: Vec<(isize, isize, f64)> = (-r..=r)
+        .flat_map(|dy| (-r..=r).map(move |dx| (dx, dy)))
+        .map(|(dx, dy)| (dx, dy, r as f64 - ((dx * dx + dy * dy) as f64).sqrt()))
+        .filter(|(_, _, w)| *w > 0.0)
+        .collect();
+
+    let mut rng = ChaCha8Rng::seed_from_u64(settings.seed);
+    let (max_x, max_y) = ((width - 1) as f64, (height - 1) as f64);
+
+    for iteration in 0..settings.iterations {
+        let (mut x, mut y) = (rng.gen_range(0.0..max_x), rng.gen_range(0.0..max_y));
+        let (mut dx, mut dy) = (0.0, 0.0);
+        let (mut speed, mut water, mut sediment) = (1.0, 1.0, 0.0);
+
+        for _ in 0..settings.lifetime {
+            let (cx, cy) = (x.floor() as usize, y.floor() as usize);
+            let (u, v) = (x - cx as f64, y - cy as f64);
+            let (h, gx, gy) = height_and_gradient(&heights, width, x, y);
+
+            // Turn down hill, keeping some of the way the raindrop was going.
+            dx = dx * settings.inertia - gx * (1.0 - settings.inertia);
+            dy = dy * settings.inertia - gy * (1.0 - settings.inertia);
+            let length = dx.hypot(dy);
+            if length == 0.0 {
+                break;
+            }
+            dx /= length;
+            dy /= length;
+            x += dx;
+            y += dy;
+
+            if x < 0.0 || y < 0.0 || x >= max_x || y >= max_y {
+                break;
+            }
+
+            let drop = height_and_gradient(&heights, width, x, y).0 - h;
+            let capacity = (-drop * speed * water * settings.capacity).max(settings.min_capacity);
+
+            if sediment > capacity || drop > 0.0 {
+                // Going up hill the raindrop fills the hollow behind it, otherwise it drops what it can not carry.
+                let amount = if drop > 0.0 { drop.min(sediment) } else { (sediment - capacity) * settings.deposition };
+                sediment -= amount;
+
+                let i = cx + cy * width;
+                heights[i] += amount * (1.0 - u) * (1.0 - v);
+                heights[i + 1] += amount * u * (1.0 - v);
+                heights[i + width] += amount * (1.0 - u) * v;
+                heights[i + width + 1] += amount * u * v;
+            } else {
+                let amount = ((capacity - sediment) * settings.erosion).min(-drop);
+                let inside: Vec<(usize, f64)> = brush
+                    .iter()
+                    .filter_map(|(bx, by, w)| {
+                        let (px, py) = (cx as isize + bx, cy as isize + by);
+                        match px >= 0 && py >= 0 && (px as usize) < width && (py as usize) < height {
+                            true => Some((px as usize + py as usize * width, *w)),
+                            false => None,
+                        }
+                    })
+                    .collect();
+                let total: f64 = inside.iter().map(|(_, w)| w).sum();
+
+                for (i, w) in inside {
+                    let eroded = (amount * w / total).min(heights[i]);
+                    heights[i] -= eroded;
+                    sediment += eroded;
+                }
+            }
+
+            speed = (speed * speed + drop * settings.gravity).max(0.0).sqrt();
+            water *= 1.0 - settings.evaporation;
+        }
+
+        report(iteration + 1, settings.iterations, &mut progress);
+    }
+
+    return to_heightmap(heightmap, &heights, 1.0);
+}
+
+/// Lets material slide down every slope steeper than the talus angle onto the pixels below, wearing down cliffs and
+/// spikes into scree slopes. `progress` is called with the iterations done and the total as the erosion goes on.
+pub fn thermal(heightmap: &HeightMap, settings: ThermalSettings, mut progress: impl FnMut(usize, usize)) -> HeightMap {
+    let (width, height) = (heightmap.width() as isize, heightmap.height() as isize);
+    // Without a vertical scale every height is 0 and slopes cannot be compared with the talus angle.
+    if settings.vertical_scale.is_nan() || settings.vertical_scale <= 0.0 {
+        return heightmap.clone();
+    }
+    let mut heights = heights(heightmap, settings.vertical_scale);
+    let talus = settings.talus_angle.to_radians().tan() * settings.cell_size;
+    let neighbours: Vec<(isize, isize, f64)> = [(-1, -1), (0, -1), (1, -1), (-1, 0), (1, 0), (-1, 1), (0, 1), (1, 1)]
+        .iter()
+        .map(|(dx, dy): &(isize, isize)| (*dx, *dy, talus * ((dx * dx + dy * dy) as f64).sqrt()))
+        .collect();
+
+    for iteration in 0..settings.iterations {
+        let mut change = vec![0.0; heights.len()];
+
+        for y in 0..height {
+            for x in 0..width {
+                let i = (x + y * width) as usize;
+
+                // How far each lower neighbour lies below the talus slope.
+                let mut steepest = 0.0;
+                let mut total = 0.0;
+                let mut below = Vec::new();
+                for (dx, dy, limit) in &neighbours {
+                    let (nx, ny) = (x + dx, y + dy);
+                    if nx < 0 || ny < 0 || nx >= width || ny >= height {
+                        continue;
+                    }
+                    let n = (nx + ny * width) as usize;
+                    let excess = heights[i] - heights[n] - limit;
+                    if excess > 0.0 {
+                        steepest = f64::max(steepest, excess);
+                        total += excess;
+                        below.push((n, excess));
+                    }
+                }
+
+                // Half of the steepest excess levels the slope, shared out by how far each neighbour lies below.
+                let moved = settings.rate * steepest / 2.0;
+                for (n, excess) in below {
+                    change[n] += moved * excess / total;
+                }
+                change[i] -= moved;
+            }
+        }
+
+        for (h, c) in heights.iter_mut().zip(change) {
+            *h += c;
+        }
+
+        report(iteration + 1, settings.iterations, &mut progress);
+    }
+
+    return to_heightmap(heightmap, &heights, settings.vertical_scale);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A cone rising to the middle of a 41x41 heightmap.
+    fn cone() -> HeightMap {
+        let mut heightmap = HeightMap::new(41, 41);
+        for x in 0..41 {
+            for y in 0..41 {
+                let distance = (x as f64 - 20.0).hypot(y as f64 - 20.0);
+                heightmap.set_height_at(x, y, (40000.0 - 1500.0 * distance).max(0.0) as u16).unwrap();
+            }
+        }
+        return heightmap;
+    }
+
+    #[test]
+    fn rain_carves_the_slopes() {
+        let settings = HydraulicSettings { iterations: 2000, seed: 3, ..HydraulicSettings::default() };
+        let mut reports = Vec::new();
+        let eroded = hydraulic(&cone(), settings, |done, total| reports.push((done, total)));
+
+        assert_eq!(reports.len(), 100);
+        assert_eq!(reports.last(), Some(&(2000, 2000)));
+        assert_ne!(eroded.data(), cone().data());
+
+        // Material is carried down the slopes, and the same seed erodes the same way.
+        let lost = |h: &HeightMap| cone().data().iter().zip(h.data()).filter(|(c, e)| e < c).count();
+        assert!(lost(&eroded) > 100);
+        assert_eq!(eroded.data(), hydraulic(&cone(), settings, |_, _| {}).data());
+        assert_ne!(eroded.data(), hydraulic(&cone(), HydraulicSettings { seed: 4, ..settings }, |_, _| {}).data());
+    }
+
+    #[test]
+    fn seeds_give_the_same_rain_everywhere() {
+        // Pinned so that a change of generator, or of how it is used, is noticed rather than quietly changing worlds.
+        let settings = HydraulicSettings { iterations: 500, seed: 42, ..HydraulicSettings::default() };
+        let eroded = hydraulic(&cone(), settings, |_, _| {});
+        let found = [(20, 20), (15, 12), (28, 25), (8, 30)].map(|(x, y)| eroded.height_at(x, y).unwrap());
+        assert_eq!(found, [39408, 25123, 24634, 14952]);
+    }
+
+    #[test]
+    fn cliffs_slump_to_the_talus_angle() {
+        let mut spike = HeightMap::new(21, 21);
+        spike.set_height_at(10, 10, 30000).unwrap();
+        let settings = ThermalSettings { iterations: 200, talus_angle: 45.0, vertical_scale: 65.535, ..ThermalSettings::default() };
+
+        let mut reports = 0;
+        let slumped = thermal(&spike, settings, |_, _| reports += 1);
+        assert_eq!(reports, 100);
+
+        // Nothing is lost, and no slope is much steeper than 1 height unit per pixel.
+        let total = |h: &HeightMap| h.data().iter().map(|v| *v as f64).sum::<f64>();
+        assert!((total(&slumped) - total(&spike)).abs() < 0.01 * total(&spike));
+        let peak = slumped.height_at(10, 10).unwrap();
+        assert!(peak < 30000);
+        assert!(peak as i32 - slumped.height_at(11, 10).unwrap() as i32 <= 1500);
+
+        // A heightmap without a vertical scale has no slopes to slump.
+        for vertical_scale in [0.0, -65.535, f64::NAN] {
+            assert_eq!(thermal(&spike, ThermalSettings { vertical_scale, ..settings }, |_, _| {}).data(), spike.data());
+        }
+    }
+}
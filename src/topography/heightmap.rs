@@ -1,4 +1,5 @@
 use super::*;
+use crate::error::{Error, Result};
 use std::ffi::OsStr;
 
 /// Struct representing a grayscale heightmap
@@ -19,46 +20,27 @@ impl HeightMap {
         }
     }
 
-    /// Loads a heightmap from a 8/16 bit grayscale image.
-    pub fn from_file(path: impl AsRef<OsStr>) -> Result<Self, String> {
-        use png::Decoder;
-        use std::io::Read;
-
-        let decoder = std::fs::File::open(path.as_ref()).map_err(|e| e.to_string())?;
-        let decoder = Decoder::new(decoder);
-
-        let (info, mut reader) = decoder.read_info().map_err(|e| e.to_string())?;
-        let mut pixel_data = vec![0u8; reader.output_buffer_size()];
-        reader.next_frame(&mut pixel_data[..]).map_err(|e| e.to_string())?;
-        let mut reader = std::io::Cursor::new(pixel_data);
-
-        let width = info.width as usize;
-        let height = info.height as usize;
-        let mut data = Vec::with_capacity(width * height);
-
-        while data.len() < width * height {
-            if let png::ColorType::Grayscale = info.color_type {
-                match info.bit_depth {
-                    png::BitDepth::Eight => {
-                        let mut bytes = [0u8; 1];
-                        reader.read(&mut bytes).map_err(|e| e.to_string())?;
-                        data.push((bytes[0] as u16) << 8);
-                    }
-
-                    png::BitDepth::Sixteen => {
-                        let mut bytes = [0u8; 2];
-                        reader.read(&mut bytes).map_err(|e| e.to_string())?;
-                        bytes.reverse();
-                        let value = unsafe { std::mem::transmute::<[u8; 2], u16>(bytes) };
-                        data.push(value);
-                    }
-
-                    _ => { return Err(String::from("Invalid Bit depth for height map")); }
-                }
-            }
+    /// Loads a heightmap from a grayscale image of any bit depth.
+    /// Values are scaled to fill the full range of a u16.
+    pub fn from_file(path: impl AsRef<OsStr>) -> Result<Self> {
+        use crate::image::decode::read_samples;
+        use png::ColorType;
+
+        let samples = read_samples(std::path::Path::new(path.as_ref()))?;
+
+        match samples.color_type {
+            ColorType::Grayscale | ColorType::GrayscaleAlpha => {}
+            _ => return Err(Error::UnsupportedImage { path: samples.path, reason: String::from("height maps must be grayscale images") }),
         }
 
-        Ok(HeightMap { width, height, data })
+        let data = samples.data.chunks(samples.channels()).map(|p| samples.to_u16(p[0])).collect();
+
+        Ok(HeightMap { width: samples.width, height: samples.height, data })
+    }
+
+    /// Writes the heightmap as a 16 bit grayscale png, which `from_file` reads back unchanged.
+    pub fn write_to_file(&self, path: impl AsRef<OsStr>) -> Result<()> {
+        crate::image::encode::write_grayscale(self.width, self.height, &self.data, std::path::Path::new(path.as_ref()))
     }
 
     /// Getter for heightmaps width.
@@ -76,12 +58,12 @@ impl HeightMap {
 
     /// Sets the data to a difference vector of height values.
     /// Returns Err if the vector is the incorrect length.
-    pub fn set_data(&mut self, data: Vec<u16>) -> Result<(), ()> {
+    pub fn set_data(&mut self, data: Vec<u16>) -> Result<()> {
         if data.len() == self.data.len() {
             self.data = data;
             Ok(())
         } else {
-            Err(())
+            Err(Error::DataLength { expected: self.data.len(), found: data.len() })
         }
     }
 
@@ -97,12 +79,12 @@ impl HeightMap {
 
     /// Sets the value of the cell at the given position.
     /// Returns an Err if coordinate is out of bounds.
-    pub fn set_height_at(&mut self, x: usize, y: usize, v: u16) -> Result<(), String> {
+    pub fn set_height_at(&mut self, x: usize, y: usize, v: u16) -> Result<()> {
         if x < self.width && y < self.height {
             self.data[x + y * self.width] = v;
             Ok(())
         } else {
-            Err(String::from("Coordinate out of bounds"))
+            Err(Error::OutOfBounds { x: x as isize, y: y as isize, width: self.width, height: self.height })
         }
     }
 
@@ -126,12 +108,12 @@ impl HeightMap {
                 continue;
             }
 
-            let height = self.height_at(x as usize, y as usize);
+            let height = self.height_at(x, y);
 
             if height.is_none() { continue; }
 
             neighbours.push((
-                [x as usize, y as usize],
+                [x, y],
                 height.unwrap()
             ));
         }
@@ -160,8 +142,8 @@ impl HeightMap {
             }
 
             neighbours.push((
-                [x as usize, y as usize],
-                self.height_at(x as usize, y as usize).unwrap(),
+                [x, y],
+                self.height_at(x, y).unwrap(),
             ));
         }
 
@@ -173,14 +155,75 @@ impl HeightMap {
         return self
             .orthogonal_neighbours(x, y)
             .into_iter()
-            .chain(self.diagonal_neighbours(x, y).into_iter())
+            .chain(self.diagonal_neighbours(x, y))
             .collect();
     }
 
+    /// Returns how steeply the elevation rises towards the east (x) and south (y) of a cell,
+    /// from Horn's weighted differences of its eight neighbours.
+    /// `cell_size` is the width of a cell and `vertical_scale` the elevation of the highest possible value, in the same units.
+    /// Cells beyond the edge are taken to be as high as the nearest cell on the edge.
+    /// Returns None if coordinate is out of range.
+    pub fn gradient(&self, x: usize, y: usize, cell_size: f64, vertical_scale: f64) -> Option<Vector<2>> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+
+        let z = |dx: isize, dy: isize| {
+            let nx = (x as isize + dx).clamp(0, self.width as isize - 1) as usize;
+            let ny = (y as isize + dy).clamp(0, self.height as isize - 1) as usize;
+            self.data[nx + ny * self.width] as f64
+        };
+
+        let east = z(1, -1) + 2.0 * z(1, 0) + z(1, 1);
+        let west = z(-1, -1) + 2.0 * z(-1, 0) + z(-1, 1);
+        let south = z(-1, 1) + 2.0 * z(0, 1) + z(1, 1);
+        let north = z(-1, -1) + 2.0 * z(0, -1) + z(1, -1);
+
+        let scale = vertical_scale / u16::MAX as f64 / (8.0 * cell_size);
+        return Some(Vector::from([(east - west) * scale, (south - north) * scale]));
+    }
+
+    /// Returns the unit vector pointing straight out of the surface at a cell, where x is east, y is south and z is up.
+    /// See `gradient` for the meaning of the scales.
+    pub fn normal(&self, x: usize, y: usize, cell_size: f64, vertical_scale: f64) -> Option<Vector<3>> {
+        let gradient = self.gradient(x, y, cell_size, vertical_scale)?;
+        return Some(Vector::from([-gradient[0], -gradient[1], 1.0]).normalise());
+    }
+
+    /// Returns the steepness of every cell in degrees, from 0 for flat ground to 90 for a sheer cliff.
+    /// See `gradient` for the meaning of the scales.
+    pub fn slope(&self, cell_size: f64, vertical_scale: f64) -> Raster {
+        let mut slope = Raster::new(self.width, self.height, 0.0);
+        for x in 0..self.width {
+            for y in 0..self.height {
+                let gradient = self.gradient(x, y, cell_size, vertical_scale).unwrap();
+                slope.set_value_at(x, y, gradient.magnitude().atan().to_degrees()).unwrap();
+            }
+        }
+        return slope;
+    }
+
+    /// Returns the compass bearing in degrees, clockwise from north (the top of the image), that every cell faces
+    /// down hill. Flat cells face no direction and are NaN.
+    pub fn aspect(&self, cell_size: f64, vertical_scale: f64) -> Raster {
+        let mut aspect = Raster::new(self.width, self.height, f64::NAN);
+        for x in 0..self.width {
+            for y in 0..self.height {
+                let gradient = self.gradient(x, y, cell_size, vertical_scale).unwrap();
+                if gradient[0] != 0.0 || gradient[1] != 0.0 {
+                    let bearing = (-gradient[0]).atan2(gradient[1]).to_degrees().rem_euclid(360.0);
+                    aspect.set_value_at(x, y, bearing).unwrap();
+                }
+            }
+        }
+        return aspect;
+    }
+
     /// Returns the direction a given cell faces (North facing, South facing .etc)
     /// Returns None if coordinate is out of range
     pub fn surface_normal(&self, x: usize, y: usize) -> Option<Vector<2>> {
-        return if x <= 0 || x >= self.width() - 1 || y <= 0 || y >= self.height() - 1 {
+        return if x == 0 || x >= self.width() - 1 || y == 0 || y >= self.height() - 1 {
             None
         } else {
             let l = self.height_at(x - 1, y)? as f64;
@@ -195,3 +238,108 @@ impl HeightMap {
         };
     }
 }
+
+/// Elevations of every cell of a heightmap, which can be sampled between cells.
+pub(crate) struct Elevations {
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<f64>,
+}
+
+impl Elevations {
+    pub fn new(heightmap: &HeightMap, vertical_scale: f64) -> Self {
+        let scale = vertical_scale / u16::MAX as f64;
+        let data = heightmap.data().iter().map(|h| *h as f64 * scale).collect();
+        Elevations { width: heightmap.width(), height: heightmap.height(), data }
+    }
+
+    /// Interpolates the elevation at a point between cell centres. Returns None outside of the heightmap.
+    pub fn sample(&self, x: f64, y: f64) -> Option<f64> {
+        if x < 0.0 || y < 0.0 || x > (self.width - 1) as f64 || y > (self.height - 1) as f64 {
+            return None;
+        }
+
+        let (left, top) = (x.floor() as usize, y.floor() as usize);
+        let (right, bottom) = ((left + 1).min(self.width - 1), (top + 1).min(self.height - 1));
+        let (fx, fy) = (x - left as f64, y - top as f64);
+
+        let at = |x: usize, y: usize| self.data[x + y * self.width];
+        let upper = at(left, top) * (1.0 - fx) + at(right, top) * fx;
+        let lower = at(left, bottom) * (1.0 - fx) + at(right, bottom) * fx;
+        return Some(upper * (1.0 - fy) + lower * fy);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sixteen_bit_precision() {
+        let heightmap = HeightMap::from_file("image/fixtures/gray_16.png").unwrap();
+        assert_eq!(heightmap.height_at(3, 2), Some(3 * 4099 + 2 * 8191));
+
+        let heightmap = HeightMap::from_file("image/fixtures/gray_4.png").unwrap();
+        assert_eq!(heightmap.height_at(15 - 3 * 4, 4), Some(u16::MAX));
+
+        assert!(HeightMap::from_file("image/fixtures/rgb_16.png").is_err());
+
+        let mut written = HeightMap::new(7, 3);
+        written.set_height_at(6, 2, 40_001).unwrap();
+        written.write_to_file("image/heightmap_round_trip.out.png").unwrap();
+        assert_eq!(HeightMap::from_file("image/heightmap_round_trip.out.png").unwrap().data(), written.data());
+    }
+
+    #[test]
+    fn gradient_of_a_plane() {
+        // Rises by 100 per cell to the east and falls by 50 per cell to the south.
+        let mut heightmap = HeightMap::new(5, 4);
+        for x in 0..5 {
+            for y in 0..4 {
+                heightmap.set_height_at(x, y, (1000 + 100 * x - 50 * y) as u16).unwrap();
+            }
+        }
+
+        let g = heightmap.gradient(2, 1, 2.0, u16::MAX as f64).unwrap();
+        assert!((g[0] - 50.0).abs() < 1e-9 && (g[1] + 25.0).abs() < 1e-9);
+
+        // Edge cells see half the difference, as the missing neighbours are as high as the edge.
+        let corner = heightmap.gradient(0, 0, 2.0, u16::MAX as f64).unwrap();
+        assert!((corner[0] - 25.0).abs() < 1e-9 && (corner[1] + 12.5).abs() < 1e-9);
+        assert!(heightmap.gradient(5, 0, 1.0, 1.0).is_none());
+
+        let normal = heightmap.normal(2, 1, 100.0, u16::MAX as f64).unwrap();
+        assert!((normal.magnitude() - 1.0).abs() < 1e-9);
+        assert!(normal[0] < 0.0 && normal[1] > 0.0 && normal[2] > 0.0);
+    }
+
+    #[test]
+    fn slope_and_aspect() {
+        // A pyramid, with a flat top, rising by one unit per cell.
+        let mut heightmap = HeightMap::new(21, 21);
+        for x in 0..21 {
+            for y in 0..21 {
+                let distance = (x as isize - 10).abs().max((y as isize - 10).abs()).max(2);
+                heightmap.set_height_at(x, y, (20 - distance) as u16).unwrap();
+            }
+        }
+
+        let slope = heightmap.slope(1.0, u16::MAX as f64);
+        assert!((slope.value_at(10, 15).unwrap() - 45.0).abs() < 1e-9);
+        assert_eq!(slope.value_at(10, 10), Some(0.0));
+        // Edge cells still have a slope, half as steep as their missing neighbours are level with them.
+        assert!((slope.value_at(10, 0).unwrap() - 0.5f64.atan().to_degrees()).abs() < 1e-9);
+        assert_eq!(heightmap.slope(2.0, 2.0 * u16::MAX as f64), slope);
+
+        let aspect = heightmap.aspect(1.0, u16::MAX as f64);
+        let bearing = |x, y| aspect.value_at(x, y).unwrap().round();
+        assert_eq!(bearing(10, 2), 0.0);
+        assert_eq!(bearing(18, 10), 90.0);
+        assert_eq!(bearing(10, 18), 180.0);
+        assert_eq!(bearing(2, 10), 270.0);
+        assert_eq!(bearing(3, 3), 315.0);
+        assert!(aspect.value_at(10, 10).unwrap().is_nan());
+        let (lowest, highest) = aspect.range().unwrap();
+        assert!(lowest >= 0.0 && highest < 360.0);
+    }
+}
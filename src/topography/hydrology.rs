@@ -0,0 +1,364 @@
+use super::*;
+use crate::error::Error;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+/// Offsets of the eight neighbours of a cell, starting to the east and turning clockwise.
+const NEIGHBOURS: [(isize, isize); 8] = [(1, 0), (1, 1), (0, 1), (-1, 1), (-1, 0), (-1, -1), (0, -1), (1, -1)];
+
+/// How water leaving a cell is shared between the cells below it.
+#[derive(Copy, Clone, Debug, PartialEq, Default)]
+pub enum FlowMethod {
+    /// All of the water flows to the neighbour down the steepest slope.
+    #[default]
+    D8,
+    /// Water flows down the steepest slope in any direction, shared between the two neighbours either side of it.
+    DInfinity,
+}
+
+impl FlowMethod {
+    /// The name used for this method in configuration files.
+    pub fn name(&self) -> &'static str {
+        match self {
+            FlowMethod::D8 => "d8",
+            FlowMethod::DInfinity => "dinf",
+        }
+    }
+}
+
+impl std::str::FromStr for FlowMethod {
+    type Err = Error;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "d8" => Ok(FlowMethod::D8),
+            "dinf" | "d-infinity" => Ok(FlowMethod::DInfinity),
+            _ => Err(Error::InvalidValue { expected: "flow method", value: s.to_string() }),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Settings {
+    /// How water is shared between the cells below each cell.
+    pub method: FlowMethod,
+    /// Cells at or below this height are sea, which water drains into and leaves the map through.
+    pub sea_level: u16,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            method: FlowMethod::D8,
+            sea_level: 0,
+        }
+    }
+}
+
+/// How water drains over a heightmap, after every depression has been filled to the height it spills over at.
+/// Water leaves the map through the sea and over the edges of the map.
+pub struct Drainage {
+    width: usize,
+    height: usize,
+    filled: HeightMap,
+    /// Cells in the order they were reached when flooding inwards from the outlets, lowest first.
+    flood_order: Vec<usize>,
+    /// The cells each cell drains into and the share of its water each one takes.
+    receivers: Vec<Vec<(usize, f64)>>,
+    outlets: Vec<bool>,
+}
+
+impl Drainage {
+    /// Fills the depressions of a heightmap and works out where water flows from every cell.
+    pub fn new(heightmap: &HeightMap, settings: Settings) -> Self {
+        let (width, height) = (heightmap.width(), heightmap.height());
+        let mut filled = heightmap.clone();
+
+        // Priority flood: grow inwards from the outlets, always from the lowest cell reached so far.
+        // Cells lower than the cell they were reached from are in a depression, and are raised to its height.
+        let mut outlets = vec![false; width * height];
+        let mut reached_from = vec![usize::MAX; width * height];
+        let mut visited = vec![false; width * height];
+        let mut flood_order = Vec::with_capacity(width * height);
+        let mut queue = BinaryHeap::new();
+        let mut counter = 0;
+
+        for y in 0..height {
+            for x in 0..width {
+                let value = heightmap.height_at(x, y).unwrap();
+                if x == 0 || y == 0 || x == width - 1 || y == height - 1 || value <= settings.sea_level {
+                    let i = x + y * width;
+                    outlets[i] = true;
+                    visited[i] = true;
+                    queue.push(Reverse((value, counter, i)));
+                    counter += 1;
+                }
+            }
+        }
+
+        while let Some(Reverse((value, _, i))) = queue.pop() {
+            flood_order.push(i);
+            for n in neighbours(i, width, height) {
+                if visited[n] {
+                    continue;
+                }
+                visited[n] = true;
+                reached_from[n] = i;
+
+                let raised = filled.data()[n].max(value);
+                filled.set_height_at(n % width, n / width, raised).unwrap();
+                queue.push(Reverse((raised, counter, n)));
+                counter += 1;
+            }
+        }
+
+        let mut receivers = vec![Vec::new(); width * height];
+        for i in 0..width * height {
+            if outlets[i] {
+                continue;
+            }
+
+            receivers[i] = match settings.method {
+                FlowMethod::D8 => steepest_neighbour(&filled, i).map(|n| vec![(n, 1.0)]).unwrap_or_default(),
+                FlowMethod::DInfinity => steepest_facet(&filled, i),
+            };
+
+            // Flat ground, such as a filled depression, drains back along the way it was flooded.
+            if receivers[i].is_empty() {
+                receivers[i] = vec![(reached_from[i], 1.0)];
+            }
+        }
+
+        Drainage { width, height, filled, flood_order, receivers, outlets }
+    }
+
+    /// Getter for the heightmap with every depression filled to the height it spills over at.
+    pub fn filled(&self) -> &HeightMap {
+        &self.filled
+    }
+
+    /// Returns whether water leaves the map at a cell, through the sea or the edge of the map.
+    pub fn is_outlet(&self, x: usize, y: usize) -> bool {
+        x < self.width && y < self.height && self.outlets[x + y * self.width]
+    }
+
+    /// Returns the cells a cell drains into, along with the share of its water each one takes.
+    pub fn receivers(&self, x: usize, y: usize) -> Vec<((usize, usize), f64)> {
+        if x >= self.width || y >= self.height {
+            return Vec::new();
+        }
+        return self.receivers[x + y * self.width].iter().map(|(n, share)| ((n % self.width, n / self.width), *share)).collect();
+    }
+
+    /// Returns the cell that takes the most water from a cell, or None if water leaves the map there.
+    pub fn downstream(&self, x: usize, y: usize) -> Option<(usize, usize)> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        let receivers = &self.receivers[x + y * self.width];
+        let (n, _) = receivers.iter().fold(None, |best: Option<(usize, f64)>, (n, share)| match best {
+            Some((_, s)) if s >= *share => best,
+            _ => Some((*n, *share)),
+        })?;
+        return Some((n % self.width, n / self.width));
+    }
+
+    /// Returns the number of cells that drain through each cell, including itself, as if rain fell evenly on the map.
+    pub fn accumulation(&self) -> Raster {
+        let mut flow = vec![1.0; self.width * self.height];
+
+        // Cells are reached by the flood after every cell they drain into, so the reverse order passes water downhill.
+        for i in self.flood_order.iter().rev() {
+            for (n, share) in &self.receivers[*i] {
+                flow[*n] += flow[*i] * share;
+            }
+        }
+
+        let mut raster = Raster::new(self.width, self.height, 0.0);
+        for (i, f) in flow.into_iter().enumerate() {
+            raster.set_value_at(i % self.width, i / self.width, f).unwrap();
+        }
+        return raster;
+    }
+}
+
+/// Fills every depression of a heightmap to the height it spills over at. See `Drainage`.
+pub fn fill_sinks(heightmap: &HeightMap, sea_level: u16) -> HeightMap {
+    let settings = Settings { sea_level, ..Settings::default() };
+    return Drainage::new(heightmap, settings).filled;
+}
+
+/// Indices of the cells around a cell, in the order of `NEIGHBOURS`, skipping those off the map.
+fn neighbours(i: usize, width: usize, height: usize) -> impl Iterator<Item = usize> {
+    let (x, y) = ((i % width) as isize, (i / width) as isize);
+    NEIGHBOURS.iter().filter_map(move |(dx, dy)| {
+        let (nx, ny) = (x + dx, y + dy);
+        if nx < 0 || ny < 0 || nx >= width as isize || ny >= height as isize {
+            return None;
+        }
+        Some(nx as usize + ny as usize * width)
+    })
+}
+
+/// The neighbour down the steepest slope from a cell, or None if no neighbour is lower.
+fn steepest_neighbour(heightmap: &HeightMap, i: usize) -> Option<usize> {
+    let (width, data) = (heightmap.width(), heightmap.data());
+    let mut best = None;
+    let mut steepest = 0.0;
+
+    for n in neighbours(i, width, heightmap.height()) {
+        let distance = if n % width != i % width && n / width != i / width { std::f64::consts::SQRT_2 } else { 1.0 };
+        let slope = (data[i] as f64 - data[n] as f64) / distance;
+        if slope > steepest {
+            steepest = slope;
+            best = Some(n);
+        }
+    }
+
+    return best;
+}
+
+/// Tarboton's D-infinity direction: the steepest slope over the eight triangular facets around a cell, shared between
+/// the two lower cells of the facet by how close the slope points to each. Returns nothing if no neighbour is lower.
+fn steepest_facet(heightmap: &HeightMap, i: usize) -> Vec<(usize, f64)> {
+    let (width, height, data) = (heightmap.width(), heightmap.height(), heightmap.data());
+    let (x, y) = ((i % width) as isize, (i / width) as isize);
+    let at = |(dx, dy): (isize, isize)| {
+        let (nx, ny) = (x + dx, y + dy);
+        if nx < 0 || ny < 0 || nx >= width as isize || ny >= height as isize {
+            return None;
+        }
+        Some(nx as usize + ny as usize * width)
+    };
+
+    let center = data[i] as f64;
+    let quarter = std::f64::consts::FRAC_PI_4;
+    let mut best: Option<(f64, Vec<(usize, f64)>)> = None;
+
+    // Each facet lies between an orthogonal neighbour and the diagonal neighbour beside it.
+    for facet in 0..8 {
+        let (orthogonal, diagonal) = match facet % 2 {
+            0 => (NEIGHBOURS[facet], NEIGHBOURS[(facet + 1) % 8]),
+            _ => (NEIGHBOURS[(facet + 1) % 8], NEIGHBOURS[facet]),
+        };
+        let (a, b) = match (at(orthogonal), at(diagonal)) {
+            (Some(a), Some(b)) => (a, b),
+            _ => continue,
+        };
+
+        let (ea, eb) = (data[a] as f64, data[b] as f64);
+        let (s1, s2) = (center - ea, ea - eb);
+        let angle = s2.atan2(s1).clamp(0.0, quarter);
+        let slope = if angle == 0.0 {
+            s1
+        } else if angle == quarter {
+            (center - eb) / std::f64::consts::SQRT_2
+        } else {
+            s1.hypot(s2)
+        };
+
+        // Only cells lower than this one may take a share of the water.
+        let shares: Vec<(usize, f64)> = [(a, ea, 1.0 - angle / quarter), (b, eb, angle / quarter)]
+            .iter()
+            .filter(|(_, e, share)| *share > 0.0 && *e < center)
+            .map(|(n, _, share)| (*n, *share))
+            .collect();
+
+        if slope > 0.0 && !shares.is_empty() && best.as_ref().is_none_or(|(s, _)| slope > *s) {
+            let total: f64 = shares.iter().map(|(_, s)| s).sum();
+            best = Some((slope, shares.into_iter().map(|(n, s)| (n, s / total)).collect()));
+        }
+    }
+
+    return best.map(|(_, shares)| shares).unwrap_or_default();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A bowl with a pit in the middle, rising towards every edge apart from a notch in its bottom edge.
+    fn bowl() -> HeightMap {
+        let mut heightmap = HeightMap::new(21, 21);
+        for x in 0..21 {
+            for y in 0..21 {
+                let distance = (x as f64 - 10.0).hypot(y as f64 - 10.0);
+                heightmap.set_height_at(x, y, (1000.0 + 100.0 * distance) as u16).unwrap();
+            }
+        }
+        heightmap.set_height_at(10, 10, 500).unwrap();
+        for y in 11..21 {
+            heightmap.set_height_at(10, y, 1050).unwrap();
+        }
+        return heightmap;
+    }
+
+    #[test]
+    fn sinks_fill_to_their_spill_height() {
+        let heightmap = bowl();
+        let filled = fill_sinks(&heightmap, 0);
+
+        // The pit and the floor of the bowl fill up to the notch, the rest of the bowl is untouched.
+        assert_eq!(filled.height_at(10, 10), Some(1050));
+        assert_eq!(filled.height_at(9, 10), Some(1100));
+        assert_eq!(filled.height_at(10, 9), Some(1100));
+        assert_eq!(filled.height_at(3, 3), heightmap.height_at(3, 3));
+        assert!(heightmap.data().iter().zip(filled.data()).all(|(h, f)| f >= h));
+
+        // Sea can not be filled.
+        let filled = fill_sinks(&heightmap, 600);
+        assert_eq!(filled.height_at(10, 10), Some(500));
+    }
+
+    #[test]
+    fn water_drains_off_the_map() {
+        let heightmap = bowl();
+
+        for method in [FlowMethod::D8, FlowMethod::DInfinity] {
+            let drainage = Drainage::new(&heightmap, Settings { method, ..Settings::default() });
+            let flow = drainage.accumulation();
+
+            // Every drop of rain leaves the map somewhere.
+            let mut outflow = 0.0;
+            for x in 0..21 {
+                for y in 0..21 {
+                    if drainage.is_outlet(x, y) {
+                        outflow += flow.value_at(x, y).unwrap();
+                    }
+                    assert!(drainage.receivers(x, y).iter().map(|(_, s)| s).sum::<f64>() <= 1.0 + 1e-9);
+                }
+            }
+            assert!((outflow - 21.0 * 21.0).abs() < 1e-6, "{:?} lost water", method);
+
+            // The filled bowl drains out through the notch, from the pit down to the bottom edge.
+            let mut cell = (10, 10);
+            while let Some(next) = drainage.downstream(cell.0, cell.1) {
+                cell = next;
+            }
+            assert_eq!(cell, (10, 20));
+            assert!(flow.value_at(10, 20).unwrap() > 50.0, "{:?} missed the notch", method);
+        }
+    }
+
+    #[test]
+    fn d_infinity_shares_flow() {
+        // A plane falling to the south and a little to the east.
+        let mut heightmap = HeightMap::new(5, 5);
+        for x in 0..5 {
+            for y in 0..5 {
+                heightmap.set_height_at(x, y, (1000 - 100 * y - 30 * x) as u16).unwrap();
+            }
+        }
+
+        let d8 = Drainage::new(&heightmap, Settings::default());
+        assert_eq!(d8.receivers(2, 2), vec![((2, 3), 1.0)]);
+
+        let dinf = Drainage::new(&heightmap, Settings { method: FlowMethod::DInfinity, ..Settings::default() });
+        let receivers = dinf.receivers(2, 2);
+        assert_eq!(receivers.len(), 2);
+        assert_eq!(receivers[0].0, (2, 3));
+        assert_eq!(receivers[1].0, (3, 3));
+        assert!(receivers[0].1 > receivers[1].1);
+
+        assert_eq!("DInf".parse::<FlowMethod>().unwrap(), FlowMethod::DInfinity);
+    }
+}
@@ -0,0 +1,73 @@
+use super::*;
+
+#[derive(Clone)]
+pub struct Settings {
+    /// Colors of the heightmap from its lowest possible value (0) to its highest (1).
+    pub ramp: ColorRamp,
+    /// Splits the heights into this many hard edged bands, matching contour lines drawn with the same number of
+    /// divisions. Each band takes the color of the ramp at its lowest height. Heights are blended smoothly if None or 0.
+    pub line_divisions: Option<u16>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            ramp: ColorRamp::classic(),
+            line_divisions: None,
+        }
+    }
+}
+
+/// Colors every cell of the heightmap by its elevation.
+pub fn generate(heightmap: &HeightMap, settings: Settings) -> Image {
+    let mut result = Image::new(heightmap.width(), heightmap.height());
+
+    for x in 0..heightmap.width() {
+        for y in 0..heightmap.height() {
+            let value = heightmap.height_at(x, y).unwrap();
+            let color = match settings.line_divisions.filter(|d| *d > 0) {
+                Some(divisions) => {
+                    // Bands are the same as the levels contour lines are drawn between.
+                    let division_size = u16::MAX / divisions;
+                    let band = (value / division_size) as f64 * division_size as f64;
+                    settings.ramp.color_at(band / u16::MAX as f64)
+                }
+                None => settings.ramp.color_at(value as f64 / u16::MAX as f64),
+            };
+            result.set_pixel_at(x, y, color).unwrap();
+        }
+    }
+
+    return result;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn bands_follow_contour_levels() {
+        let mut heightmap = HeightMap::new(4, 1);
+        heightmap.set_data(vec![0, 16000, 16500, u16::MAX]).unwrap();
+
+        let smooth = generate(&heightmap, Settings { ramp: ColorRamp::greyscale(), line_divisions: None });
+        assert_eq!(smooth.pixel_at(0, 0), Some(Color::from([0, 0, 0, 255])));
+        assert_eq!(smooth.pixel_at(3, 0), Some(Color::from([255, 255, 255, 255])));
+        assert_ne!(smooth.pixel_at(1, 0), smooth.pixel_at(2, 0));
+
+        // With four bands the heights either side of 16383 fall into different bands, and each band is flat.
+        let banded = generate(&heightmap, Settings { ramp: ColorRamp::greyscale(), line_divisions: Some(4) });
+        assert_eq!(banded.pixel_at(1, 0), Some(Color::from([0, 0, 0, 255])));
+        assert_eq!(banded.pixel_at(2, 0), Some(Color::from([64, 64, 64, 255])));
+
+        heightmap.set_data(vec![16400, 16500, 32000, 32700]).unwrap();
+        let banded = generate(&heightmap, Settings { ramp: ColorRamp::classic(), line_divisions: Some(4) });
+        assert_eq!(banded.pixel_at(0, 0), banded.pixel_at(1, 0));
+        assert_eq!(banded.pixel_at(2, 0), banded.pixel_at(3, 0));
+
+        // No divisions is the same as none given.
+        let smooth = generate(&heightmap, Settings { ramp: ColorRamp::classic(), line_divisions: None });
+        let zero = generate(&heightmap, Settings { ramp: ColorRamp::classic(), line_divisions: Some(0) });
+        assert_eq!(zero.data(), smooth.data());
+    }
+}
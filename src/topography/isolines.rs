@@ -0,0 +1,524 @@
+use super::*;
+use crate::image::draw::{self, LineCap, LineJoin, Mask};
+use crate::image::text;
+use std::collections::HashMap;
+
+#[derive(Clone, Copy)]
+pub struct Settings {
+    /// The number of contour lines between 0 and u16::MAX.
+    pub line_divisions: u16,
+    /// The color to paint the contour lines.
+    pub line_color: Color,
+    /// Width of the contour lines in pixels.
+    pub line_width: f64,
+    /// Number of times the corners of each line are cut to smooth it, 0 to keep the traced points.
+    pub smoothing: usize,
+    /// Every this many lines is drawn as an index contour, 0 for none.
+    pub index_interval: u16,
+    /// The color to paint index contours.
+    pub index_color: Color,
+    /// Width of index contours in pixels.
+    pub index_width: f64,
+    /// Whether index contours are labelled with their elevation.
+    pub labels: bool,
+    /// The color to write labels in.
+    pub label_color: Color,
+    /// Height of the label text in pixels. No labels are placed unless this is above 0.
+    pub label_size: f64,
+    /// Smallest distance in pixels along a line between two of its labels.
+    pub label_spacing: f64,
+    /// Elevation printed for the highest possible heightmap value.
+    pub label_scale: f64,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            line_divisions: 32,
+            line_color: Color::from([255, 0, 0]),
+            line_width: 1.0,
+            smoothing: 2,
+            index_interval: 5,
+            index_color: Color::from([160, 0, 0]),
+            index_width: 2.0,
+            labels: true,
+            label_color: Color::from([160, 0, 0]),
+            label_size: 8.0,
+            label_spacing: 200.0,
+            label_scale: 3200.0,
+        }
+    }
+}
+
+/// A contour line through the points of a heightmap at one elevation.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Isoline {
+    /// Heightmap value the line follows. Higher ground lies to the left of the line.
+    pub elevation: u16,
+    /// Points along the line, in pixel coordinates where pixel centers lie on whole coordinates.
+    /// The first point of closed lines is repeated at their end.
+    pub points: Vec<Vector<2>>,
+    /// Whether the line loops back on itself, rather than running off the edge of the heightmap.
+    pub closed: bool,
+}
+
+impl Isoline {
+    /// Returns the line with its corners cut `iterations` times.
+    pub fn smoothed(&self, iterations: usize) -> Isoline {
+        let points = match self.closed {
+            true => draw::smooth_closed(&self.points, iterations),
+            false => draw::smooth(&self.points, iterations),
+        };
+        return Isoline { points, ..*self };
+    }
+}
+
+/// The heightmap values contour lines are drawn at for a number of divisions,
+/// matching the steps the raster contour layer is drawn at.
+pub fn levels(line_divisions: u16) -> Vec<u16> {
+    let division_size = u16::MAX / line_divisions.max(1);
+    return (1..=u16::MAX / division_size).map(|i| i * division_size).collect();
+}
+
+/// One of the edges between two neighbouring pixel centers, which a contour line may cross.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Edge {
+    /// The edge from (x, y) to (x + 1, y).
+    Horizontal(usize, usize),
+    /// The edge from (x, y) to (x, y + 1).
+    Vertical(usize, usize),
+}
+
+/// Traces the contour lines of a heightmap at the given elevations with marching squares.
+/// Crossings are placed between pixel centers by linear interpolation.
+pub fn trace(heightmap: &HeightMap, elevations: &[u16]) -> Vec<Isoline> {
+    let mut isolines = Vec::new();
+    for elevation in elevations {
+        isolines.extend(trace_level(heightmap, *elevation));
+    }
+    return isolines;
+}
+
+/// Traces the contour lines of a heightmap at a single elevation.
+fn trace_level(heightmap: &HeightMap, elevation: u16) -> Vec<Isoline> {
+    if heightmap.width() < 2 || heightmap.height() < 2 {
+        return Vec::new();
+    }
+
+    // Values exactly at the elevation count as above it, so lines never pass through pixel centers.
+    let threshold = elevation as f64 - 0.5;
+    let value = |x: usize, y: usize| heightmap.height_at(x, y).unwrap() as f64;
+
+    let point = |edge: Edge| {
+        let (a, b) = match edge {
+            Edge::Horizontal(x, y) => ((x, y), (x + 1, y)),
+            Edge::Vertical(x, y) => ((x, y), (x, y + 1)),
+        };
+        let (va, vb) = (value(a.0, a.1), value(b.0, b.1));
+        let t = (threshold - va) / (vb - va);
+        Vector::from([a.0 as f64 + t * (b.0 - a.0) as f64, a.1 as f64 + t * (b.1 - a.1) as f64])
+    };
+
+    // Every segment runs from one edge to another with higher ground on its left.
+    let mut segments: HashMap<Edge, Edge> = HashMap::new();
+    for x in 0..heightmap.width() - 1 {
+        for y in 0..heightmap.height() - 1 {
+            let corners = [value(x, y), value(x + 1, y), value(x + 1, y + 1), value(x, y + 1)];
+            let above: Vec<bool> = corners.iter().map(|v| *v > threshold).collect();
+
+            let top = Edge::Horizontal(x, y);
+            let right = Edge::Vertical(x + 1, y);
+            let bottom = Edge::Horizontal(x, y + 1);
+            let left = Edge::Vertical(x, y);
+
+            let case = (above[0] as u8) | (above[1] as u8) << 1 | (above[2] as u8) << 2 | (above[3] as u8) << 3;
+            let center_above = corners.iter().sum::<f64>() / 4.0 > threshold;
+
+            let cell: &[(Edge, Edge)] = match case {
+                0 | 15 => &[],
+                1 => &[(left, top)],
+                2 => &[(top, right)],
+                3 => &[(left, right)],
+                4 => &[(right, bottom)],
+                5 if center_above => &[(left, bottom), (right, top)],
+                5 => &[(left, top), (right, bottom)],
+                6 => &[(top, bottom)],
+                7 => &[(left, bottom)],
+                8 => &[(bottom, left)],
+                9 => &[(bottom, top)],
+                10 if center_above => &[(top, left), (bottom, right)],
+                10 => &[(top, right), (bottom, left)],
+                11 => &[(bottom, right)],
+                12 => &[(right, left)],
+                13 => &[(right, top)],
+                _ => &[(top, left)],
+            };
+
+            for (from, to) in cell {
+                segments.insert(*from, *to);
+            }
+        }
+    }
+
+    // Open lines start on the edge of the heightmap, at an edge no segment leads to.
+    let targets: std::collections::HashSet<Edge> = segments.values().copied().collect();
+    let mut starts: Vec<Edge> = segments.keys().filter(|e| !targets.contains(e)).copied().collect();
+    let mut loops: Vec<Edge> = segments.keys().filter(|e| targets.contains(e)).copied().collect();
+    starts.sort_by_key(edge_order);
+    loops.sort_by_key(edge_order);
+
+    let mut isolines = Vec::new();
+    for start in starts.into_iter().chain(loops) {
+        if !segments.contains_key(&start) {
+            continue;
+        }
+
+        let mut points = vec![point(start)];
+        let mut current = start;
+        while let Some(next) = segments.remove(&current) {
+            points.push(point(next));
+            current = next;
+        }
+
+        isolines.push(Isoline { elevation, points, closed: current == start });
+    }
+
+    return isolines;
+}
+
+/// Orders edges from the top left so that lines are traced in the same order every time.
+fn edge_order(edge: &Edge) -> (usize, usize, bool) {
+    match *edge {
+        Edge::Horizontal(x, y) => (y, x, false),
+        Edge::Vertical(x, y) => (y, x, true),
+    }
+}
+
+/// An elevation written along an index contour.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Label {
+    /// The elevation as it is written.
+    pub text: String,
+    /// Middle of the text.
+    pub center: Vector<2>,
+    /// Direction the text reads in, in radians clockwise from the x axis.
+    pub angle: f64,
+    /// Position of the labelled line in the list of lines.
+    pub line: usize,
+    /// Distances along the line between which it is cut to make room for the label.
+    pub gap: (f64, f64),
+}
+
+/// Returns whether a line is an index contour.
+fn is_index(isoline: &Isoline, settings: &Settings) -> bool {
+    let division_size = u16::MAX / settings.line_divisions.max(1);
+    return settings.index_interval > 0 && (isoline.elevation / division_size).is_multiple_of(settings.index_interval);
+}
+
+/// Writes the elevation of a line, as the nominal height of its division rather than its exact heightmap value.
+fn label_text(isoline: &Isoline, settings: &Settings) -> String {
+    let division_size = u16::MAX / settings.line_divisions.max(1);
+    let elevation = (isoline.elevation / division_size) as f64 * settings.label_scale / settings.line_divisions.max(1) as f64;
+    let rounded = (elevation * 10.0).round() / 10.0;
+    return match rounded.fract() == 0.0 {
+        true => format!("{}", rounded as i64),
+        false => format!("{:.1}", rounded),
+    };
+}
+
+/// Places elevation labels along the index contours, where the lines run straight enough to write along,
+/// spaced apart along each line and kept clear of each other and of the edge of the image.
+pub fn place_labels(isolines: &[Isoline], width: usize, height: usize, settings: Settings) -> Vec<Label> {
+    let size = settings.label_size;
+    let mut labels: Vec<Label> = Vec::new();
+    if size.is_nan() || size <= 0.0 {
+        return labels;
+    }
+    // Space taken up by each placed label, as the corners of an axis aligned box.
+    let mut taken: Vec<(Vector<2>, Vector<2>)> = Vec::new();
+
+    for (line, isoline) in isolines.iter().enumerate() {
+        if !is_index(isoline, &settings) {
+            continue;
+        }
+
+        let text = label_text(isoline, &settings);
+        // Leave half the text height clear either side of the label.
+        let gap = text::width(&text, size) + size;
+        let total = draw::length(&isoline.points);
+
+        let mut center = (settings.label_spacing / 2.0).min(total / 2.0).max(gap / 2.0);
+        while center + gap / 2.0 <= total {
+            let start = draw::point_along(&isoline.points, center - gap / 2.0).unwrap();
+            let end = draw::point_along(&isoline.points, center + gap / 2.0).unwrap();
+
+            // Labels are only written where the line is nearly straight.
+            if (end - start).magnitude() < 0.9 * gap {
+                center += size;
+                continue;
+            }
+
+            // Keep the text upright, reading from left to right.
+            let direction = if end[0] >= start[0] { end - start } else { start - end };
+            let angle = direction[1].atan2(direction[0]);
+            let middle = draw::point_along(&isoline.points, center).unwrap();
+
+            let (sin, cos) = angle.sin_cos();
+            let extent = Vector::from([
+                (gap / 2.0 * cos).abs() + (size * sin).abs(),
+                (gap / 2.0 * sin).abs() + (size * cos).abs(),
+            ]);
+            let (min, max) = (middle - extent, middle + extent);
+
+            let inside = min[0] >= 0.0 && min[1] >= 0.0 && max[0] <= width as f64 - 1.0 && max[1] <= height as f64 - 1.0;
+            let clear = taken.iter().all(|(a, b)| max[0] < a[0] || min[0] > b[0] || max[1] < a[1] || min[1] > b[1]);
+            if !inside || !clear {
+                center += size;
+                continue;
+            }
+
+            taken.push((min, max));
+            labels.push(Label {
+                text: text.clone(),
+                center: middle,
+                angle,
+                line,
+                gap: (center - gap / 2.0, center + gap / 2.0),
+            });
+            // The gap is never empty, as it is at least the text height, so the next label is always further along.
+            center += settings.label_spacing.max(gap);
+        }
+    }
+
+    return labels;
+}
+
+/// Draws contour lines as anti-aliased polylines, with index contours emphasised and labelled.
+pub fn render(isolines: &[Isoline], width: usize, height: usize, settings: Settings) -> Image {
+    let mut result = Image::new(width, height);
+    let labels = match settings.labels {
+        true => place_labels(isolines, width, height, settings),
+        false => Vec::new(),
+    };
+
+    let mut lines = Mask::new(width, height);
+    let mut index_lines = Mask::new(width, height);
+    for (i, isoline) in isolines.iter().enumerate() {
+        if !is_index(isoline, &settings) {
+            lines.polyline(&isoline.points, settings.line_width, LineCap::Round, LineJoin::Round);
+            continue;
+        }
+
+        let gaps: Vec<(f64, f64)> = labels.iter().filter(|l| l.line == i).map(|l| l.gap).collect();
+        for piece in draw::cut(&isoline.points, &gaps) {
+            index_lines.polyline(&piece, settings.index_width, LineCap::Round, LineJoin::Round);
+        }
+    }
+
+    let mut writing = Mask::new(width, height);
+    for label in &labels {
+        for stroke in text::strokes(&label.text, settings.label_size, label.center, label.angle) {
+            writing.polyline(&stroke, settings.label_size * 0.15, LineCap::Round, LineJoin::Round);
+        }
+    }
+
+    lines.paint(&mut result, settings.line_color).unwrap();
+    index_lines.paint(&mut result, settings.index_color).unwrap();
+    writing.paint(&mut result, settings.label_color).unwrap();
+
+    return result;
+}
+
+/// Formats a color and its opacity as svg attributes.
+fn svg_color(attribute: &str, color: Color) -> String {
+    format!(
+        "{}=\"#{:02x}{:02x}{:02x}\" {}-opacity=\"{:.3}\"",
+        attribute, color[0], color[1], color[2], attribute, color[3] as f64 / u8::MAX as f64
+    )
+}
+
+/// Formats a line as the data of an svg path.
+fn svg_path(points: &[Vector<2>], closed: bool) -> String {
+    let mut path = String::new();
+    for (i, p) in points.iter().enumerate() {
+        path += &format!("{}{:.2} {:.2} ", if i == 0 { "M" } else { "L" }, p[0], p[1]);
+    }
+    if closed {
+        path += "Z";
+    }
+    return path.trim_end().to_string();
+}
+
+/// Writes contour lines as the paths of an svg image, each tagged with its elevation,
+/// with index contours emphasised and labelled as with `render`.
+pub fn to_svg(isolines: &[Isoline], width: usize, height: usize, settings: Settings) -> String {
+    let labels = match settings.labels {
+        true => place_labels(isolines, width, height, settings),
+        false => Vec::new(),
+    };
+
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" viewBox=\"-0.5 -0.5 {} {}\">\n",
+        width, height, width, height
+    );
+
+    let group = |color: Color, width: f64| {
+        format!("<g fill=\"none\" {} stroke-width=\"{}\" stroke-linecap=\"round\" stroke-linejoin=\"round\">\n", svg_color("stroke", color), width)
+    };
+
+    svg += &group(settings.line_color, settings.line_width);
+    for isoline in isolines.iter().filter(|i| !is_index(i, &settings)) {
+        svg += &format!("<path data-elevation=\"{}\" d=\"{}\"/>\n", isoline.elevation, svg_path(&isoline.points, isoline.closed));
+    }
+    svg += "</g>\n";
+
+    svg += &group(settings.index_color, settings.index_width);
+    for (i, isoline) in isolines.iter().enumerate().filter(|(_, i)| is_index(i, &settings)) {
+        let gaps: Vec<(f64, f64)> = labels.iter().filter(|l| l.line == i).map(|l| l.gap).collect();
+        let path = match gaps.is_empty() {
+            true => svg_path(&isoline.points, isoline.closed),
+            false => draw::cut(&isoline.points, &gaps).iter().map(|piece| svg_path(piece, false)).collect::<Vec<_>>().join(" "),
+        };
+        svg += &format!("<path data-elevation=\"{}\" d=\"{}\"/>\n", isoline.elevation, path);
+    }
+    svg += "</g>\n";
+
+    if !labels.is_empty() {
+        svg += &format!(
+            "<g {} font-size=\"{}\" font-family=\"sans-serif\" text-anchor=\"middle\" dominant-baseline=\"central\">\n",
+            svg_color("fill", settings.label_color),
+            settings.label_size * 1.4
+        );
+        for label in &labels {
+            let (x, y) = (label.center[0], label.center[1]);
+            svg += &format!(
+                "<text x=\"{:.2}\" y=\"{:.2}\" transform=\"rotate({:.2} {:.2} {:.2})\">{}</text>\n",
+                x, y, label.angle.to_degrees(), x, y, label.text
+            );
+        }
+        svg += "</g>\n";
+    }
+
+    svg += "</svg>\n";
+    return svg;
+}
+
+/// Traces, smooths and draws the contour lines of a heightmap.
+pub fn generate(heightmap: &HeightMap, settings: Settings) -> Image {
+    let isolines: Vec<Isoline> = trace(heightmap, &levels(settings.line_divisions))
+        .iter()
+        .map(|i| i.smoothed(settings.smoothing))
+        .collect();
+    return render(&isolines, heightmap.width(), heightmap.height(), settings);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A round hill in the middle of a 21x21 heightmap, with a second hill running off its right edge.
+    fn hills() -> HeightMap {
+        let mut heightmap = HeightMap::new(21, 21);
+        for x in 0..21 {
+            for y in 0..21 {
+                let peak = (x as f64 - 8.0).hypot(y as f64 - 10.0);
+                let edge = (x as f64 - 20.0).hypot(y as f64 - 10.0);
+                let height = (1000.0 - 100.0 * peak).max(800.0 - 100.0 * edge).max(0.0);
+                heightmap.set_height_at(x, y, height as u16).unwrap();
+            }
+        }
+        return heightmap;
+    }
+
+    #[test]
+    fn lines_follow_the_elevation() {
+        let heightmap = hills();
+        let isolines = trace(&heightmap, &[500]);
+        assert_eq!(isolines.len(), 2);
+
+        // The hill in the middle is circled by a closed line five pixels out.
+        let circle = isolines.iter().find(|i| i.closed).unwrap();
+        assert_eq!(circle.elevation, 500);
+        assert_eq!(circle.points.first(), circle.points.last());
+        for p in &circle.points {
+            let distance = (p[0] - 8.0).hypot(p[1] - 10.0);
+            assert!((distance - 5.0).abs() < 0.2, "{:?} is {} from the peak", p, distance);
+        }
+
+        // The hill on the edge is cut off by it.
+        let arc = isolines.iter().find(|i| !i.closed).unwrap();
+        assert_eq!(arc.points.first().unwrap()[0], 20.0);
+        assert_eq!(arc.points.last().unwrap()[0], 20.0);
+
+        // Higher ground lies to the left, so walking around the peak turns the same way throughout.
+        let turn: f64 = circle.points.windows(2).map(|w| w[0][0] * w[1][1] - w[1][0] * w[0][1]).sum();
+        assert!(turn < 0.0);
+
+        let smoothed = circle.smoothed(2);
+        assert!(smoothed.closed && smoothed.points.len() > circle.points.len());
+        assert_eq!(smoothed.points.first(), smoothed.points.last());
+
+        assert!(trace(&heightmap, &[2000]).is_empty());
+        assert_eq!(levels(4), vec![16383, 32766, 49149, 65532]);
+    }
+
+    #[test]
+    fn render_and_svg() {
+        let heightmap = hills();
+        // A hundred divisions put a single line around the middle hill, 655 high and three and a half pixels out.
+        let settings = Settings { line_divisions: 100, line_width: 2.0, ..Settings::default() };
+        let image = generate(&heightmap, settings);
+        assert_eq!(image.pixel_at(8, 10).unwrap()[3], 0);
+        assert!(image.pixel_at(8, 7).unwrap()[3] > 0);
+
+        let svg = to_svg(&trace(&heightmap, &[500, 900]), 21, 21, settings);
+        assert!(svg.starts_with("<svg"));
+        assert_eq!(svg.matches("<path").count(), 3);
+        assert!(svg.contains("data-elevation=\"900\""));
+        assert!(svg.contains("stroke=\"#ff0000\""));
+    }
+
+    #[test]
+    fn index_contours_are_labelled() {
+        // A plane rising to the east, so every contour runs straight down the image.
+        let mut heightmap = HeightMap::new(200, 60);
+        for x in 0..200 {
+            for y in 0..60 {
+                heightmap.set_height_at(x, y, (x * 300) as u16).unwrap();
+            }
+        }
+
+        let settings = Settings { smoothing: 0, ..Settings::default() };
+        let isolines = trace(&heightmap, &levels(settings.line_divisions));
+        let labels = place_labels(&isolines, 200, 60, settings);
+        let texts: Vec<&str> = labels.iter().map(|l| l.text.as_str()).collect();
+        assert_eq!(texts, vec!["500", "1000", "1500", "2000", "2500"]);
+        assert!(labels.iter().all(|l| l.gap.0 >= 0.0 && l.gap.1 <= 59.0));
+
+        // The line is cut where it is labelled.
+        let center = labels[0].center;
+        let (x, y) = (center[0].round() as usize, center[1].round() as usize);
+        let unlabelled = render(&isolines, 200, 60, Settings { labels: false, ..settings });
+        assert_eq!(unlabelled.pixel_at(x, y).unwrap()[0], 160);
+        assert_eq!(render(&isolines, 200, 60, settings).pixel_at(x, y).unwrap()[3], 0);
+
+        let svg = to_svg(&isolines, 200, 60, settings);
+        assert_eq!(svg.matches("<text").count(), 5);
+        assert!(svg.contains(">1500</text>"));
+
+        // Labelling every line packs them closer than the labels are wide, so they are staggered to avoid each other.
+        let crowded = place_labels(&isolines, 200, 60, Settings { index_interval: 1, ..settings });
+        assert!(crowded.len() > 5);
+        for (i, a) in crowded.iter().enumerate() {
+            for b in &crowded[i + 1..] {
+                let apart = b.center - a.center;
+                assert!(apart[0].abs() > 16.0 || apart[1].abs() > 23.0, "{} and {} overlap", a.text, b.text);
+            }
+        }
+
+        // Text without a height takes no room to place, and no spacing still moves along the line between labels.
+        assert!(place_labels(&isolines, 200, 60, Settings { label_size: 0.0, label_spacing: 0.0, ..settings }).is_empty());
+        assert!(place_labels(&isolines, 200, 60, Settings { label_size: -2.0, ..settings }).is_empty());
+        assert!(!place_labels(&isolines, 200, 60, Settings { label_spacing: 0.0, ..settings }).is_empty());
+    }
+}
@@ -0,0 +1,331 @@
+use super::hydrology::Drainage;
+use super::*;
+use crate::error::{Error, Result};
+use crate::region::{BiomeSettings, Configuration};
+
+#[derive(Clone, Copy)]
+pub struct Settings {
+    /// Heightmap values at or below this are sea, which is never filled.
+    pub sea_level: u16,
+    /// Lakes covering fewer pixels than this are left out.
+    pub min_area: usize,
+    /// Lakes whose deepest point is less than this far below their surface, in heightmap values, are left out.
+    pub min_depth: u16,
+    /// The color to paint lakes, by default the color of water in the sample biome maps.
+    pub color: Color,
+    /// Color of the shoreline drawn around lakes.
+    pub shore_color: Color,
+    /// Width of the shoreline in pixels, 0 for none.
+    pub shore_thickness: u64,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            sea_level: 0,
+            min_area: 10,
+            min_depth: 1,
+            color: Color::from([64, 255, 255]),
+            shore_color: Color::from([0, 128, 160]),
+            shore_thickness: 0,
+        }
+    }
+}
+
+/// A depression in a heightmap filled with water up to the height it spills over at.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Lake {
+    /// Heightmap value of the surface of the lake.
+    pub surface: u16,
+    /// The pixel just outside the lake that its water overflows through.
+    pub spill: (usize, usize),
+    /// Top left corner of the smallest box around the lake.
+    pub origin: (usize, usize),
+    /// Width and height of the box around the lake.
+    pub size: (usize, usize),
+    /// Whether each pixel of the box is under the lake, row by row.
+    pub mask: Vec<bool>,
+}
+
+impl Lake {
+    /// Returns whether a pixel of the heightmap is under the lake.
+    pub fn contains(&self, x: usize, y: usize) -> bool {
+        let (x, y) = (x.wrapping_sub(self.origin.0), y.wrapping_sub(self.origin.1));
+        return x < self.size.0 && y < self.size.1 && self.mask[x + y * self.size.0];
+    }
+
+    /// Returns the pixels of the heightmap under the lake.
+    pub fn pixels(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        let (width, (x0, y0)) = (self.size.0, self.origin);
+        self.mask.iter().enumerate().filter(|(_, m)| **m).map(move |(i, _)| (x0 + i % width, y0 + i / width))
+    }
+
+    /// Returns the number of pixels under the lake.
+    pub fn area(&self) -> usize {
+        self.mask.iter().filter(|m| **m).count()
+    }
+}
+
+/// Finds the lakes that form when every depression of a heightmap is filled to the height it spills over at.
+/// Depressions filled to the same height are one lake where they touch, or where a single pixel at exactly the height of
+/// the surface lies between them, but stay apart where they are only connected by dry ground at that height.
+pub fn find(heightmap: &HeightMap, settings: Settings) -> Vec<Lake> {
+    let drainage = Drainage::new(heightmap, HydrologySettings { sea_level: settings.sea_level, ..HydrologySettings::default() });
+    let (width, height) = (heightmap.width(), heightmap.height());
+    let (original, filled) = (heightmap.data(), drainage.filled().data());
+    let around = |i: usize| {
+        let (x, y) = (i % width, i / width);
+        let columns = x.saturating_sub(1)..(x + 2).min(width);
+        (y.saturating_sub(1)..(y + 2).min(height)).flat_map(move |ny| columns.clone().map(move |nx| nx + ny * width))
+    };
+
+    let mut flat_of = vec![usize::MAX; width * height];
+    let mut depression = vec![usize::MAX; width * height];
+    let mut lake_of = vec![usize::MAX; width * height];
+    let mut depressions = 0;
+    let mut lakes = Vec::new();
+    let mut found = 0;
+
+    for start in 0..width * height {
+        if filled[start] == original[start] || flat_of[start] != usize::MAX {
+            continue;
+        }
+
+        // Collect the flat of pixels at the same filled height connected to this raised one.
+        let surface = filled[start];
+        let mut flat = vec![start];
+        flat_of[start] = start;
+        let mut i = 0;
+        while i < flat.len() {
+            for n in around(flat[i]) {
+                if flat_of[n] == usize::MAX && filled[n] == surface {
+                    flat_of[n] = start;
+                    flat.push(n);
+                }
+            }
+            i += 1;
+        }
+
+        // The raised pixels are under water, split into the depressions they fill.
+        let first = depressions;
+        for &p in flat.iter().filter(|p| original[**p] < surface) {
+            if depression[p] != usize::MAX {
+                continue;
+            }
+            let mut stack = vec![p];
+            depression[p] = depressions;
+            while let Some(q) = stack.pop() {
+                for n in around(q) {
+                    if depression[n] == usize::MAX && flat_of[n] == start && original[n] < surface {
+                        depression[n] = depressions;
+                        stack.push(n);
+                    }
+                }
+            }
+            depressions += 1;
+        }
+
+        // A pixel at the surface touching two depressions is under water too, joining them into one lake.
+        let mut joined: Vec<usize> = (0..depressions - first).collect();
+        let mut bridges = Vec::new();
+        for &p in flat.iter().filter(|p| original[**p] >= surface) {
+            let mut touching: Vec<usize> =
+                around(p).map(|n| depression[n]).filter(|d| (first..depressions).contains(d)).collect();
+            touching.sort_unstable();
+            touching.dedup();
+            if touching.len() > 1 {
+                let into = root(&mut joined, touching[0] - first);
+                for d in &touching[1..] {
+                    let from = root(&mut joined, d - first);
+                    joined[from] = into;
+                }
+                bridges.push((p, touching[0]));
+            }
+        }
+
+        let mut members = vec![Vec::new(); depressions - first];
+        let raised = flat.iter().filter(|p| original[**p] < surface).map(|p| (*p, depression[*p]));
+        for (p, d) in raised.chain(bridges) {
+            members[root(&mut joined, d - first)].push(p);
+        }
+
+        for pixels in members.into_iter().filter(|m| !m.is_empty()) {
+            for p in &pixels {
+                lake_of[*p] = found;
+            }
+
+            // Water leaving the lake flows out at its spill point, the first pixel downstream that is not under it.
+            let mut spill = pixels[0];
+            while lake_of[spill] == found {
+                match drainage.downstream(spill % width, spill / width) {
+                    Some((x, y)) => spill = x + y * width,
+                    None => break,
+                }
+            }
+
+            let (xs, ys) = (pixels.iter().map(|p| p % width), pixels.iter().map(|p| p / width));
+            let (x0, y0) = (xs.clone().min().unwrap(), ys.clone().min().unwrap());
+            let (x1, y1) = (xs.max().unwrap(), ys.max().unwrap());
+            let size = (x1 - x0 + 1, y1 - y0 + 1);
+            let mut mask = vec![false; size.0 * size.1];
+            for p in &pixels {
+                mask[(p % width - x0) + (p / width - y0) * size.0] = true;
+            }
+
+            found += 1;
+            let depth = surface - pixels.iter().map(|p| original[*p]).min().unwrap();
+            if pixels.len() >= settings.min_area && depth >= settings.min_depth {
+                lakes.push(Lake { surface, spill: (spill % width, spill / width), origin: (x0, y0), size, mask });
+            }
+        }
+    }
+
+    return lakes;
+}
+
+/// The depression a set of joined depressions is merged into, shortening the path to it along the way.
+fn root(joined: &mut [usize], mut d: usize) -> usize {
+    while joined[d] != d {
+        joined[d] = joined[joined[d]];
+        d = joined[d];
+    }
+    return d;
+}
+
+/// Paints the pixels under each lake into an image, such as a biome map so that lakes are drawn like the biome of
+/// that color.
+pub fn paint(lakes: &[Lake], image: &mut Image, color: Color) -> Result<()> {
+    for lake in lakes {
+        let (x1, y1) = (lake.origin.0 + lake.size.0, lake.origin.1 + lake.size.1);
+        if x1 > image.width() || y1 > image.height() {
+            return Err(Error::DimensionMismatch { expected: (image.width(), image.height()), found: (x1, y1) });
+        }
+
+        for (x, y) in lake.pixels() {
+            image.set_pixel_at(x, y, color)?;
+        }
+    }
+
+    return Ok(());
+}
+
+pub fn generate(heightmap: &HeightMap, settings: Settings) -> Image {
+    let mut water = Image::new(heightmap.width(), heightmap.height());
+    paint(&find(heightmap, settings), &mut water, settings.color).unwrap();
+
+    let lake = BiomeSettings::new(settings.color).outline(settings.shore_color, settings.shore_thickness);
+    let configuration = Configuration::builder().biome(settings.color, lake).build().unwrap();
+    return configuration.generate_region_layer(&water).unwrap();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Ground sloping down to the left edge of a 21x11 heightmap, with a round hollow and a single pixel pit in it.
+    fn hollows() -> HeightMap {
+        let mut heightmap = HeightMap::new(21, 11);
+        for x in 0..21 {
+            for y in 0..11 {
+                let distance = (x as f64 - 12.0).hypot(y as f64 - 5.0);
+                let hollow = (500.0 - 100.0 * distance).max(0.0);
+                heightmap.set_height_at(x, y, (1000.0 + 50.0 * x as f64 - hollow) as u16).unwrap();
+            }
+        }
+        heightmap.set_height_at(4, 5, 1000).unwrap();
+        return heightmap;
+    }
+
+    #[test]
+    fn depressions_hold_lakes() {
+        let heightmap = hollows();
+        let lakes = find(&heightmap, Settings { min_area: 1, ..Settings::default() });
+        assert_eq!(lakes.len(), 2);
+
+        // The hollow fills until it overflows down hill, to the left.
+        let hollow = lakes.iter().find(|l| l.contains(12, 5)).unwrap();
+        assert!(hollow.area() > 5);
+        assert!(hollow.contains(11, 5) && !hollow.contains(16, 5));
+        assert!(hollow.spill.0 < 12);
+        assert!(heightmap.height_at(hollow.spill.0, hollow.spill.1).unwrap() <= hollow.surface);
+        assert!(hollow.pixels().all(|(x, y)| heightmap.height_at(x, y).unwrap() < hollow.surface));
+
+        let pit = lakes.iter().find(|l| l.contains(4, 5)).unwrap();
+        assert_eq!(pit.area(), 1);
+        assert_eq!(pit.surface, 1150);
+
+        // Small and shallow lakes are left out.
+        let lakes = find(&heightmap, Settings::default());
+        assert_eq!(lakes.len(), 1);
+        assert!(lakes[0].contains(12, 5));
+        assert_eq!(find(&heightmap, Settings { min_depth: 1000, ..Settings::default() }).len(), 0);
+    }
+
+    #[test]
+    fn lakes_are_drawn_as_water() {
+        let heightmap = hollows();
+        let lakes = find(&heightmap, Settings { min_area: 2, ..Settings::default() });
+
+        let mut biomes = Image::new(21, 11).fill(Color::from([0, 255, 0]));
+        paint(&lakes, &mut biomes, Color::from([64, 255, 255])).unwrap();
+        assert_eq!(biomes.pixel_at(12, 5), Some(Color::from([64, 255, 255])));
+        assert_eq!(biomes.pixel_at(4, 5), Some(Color::from([0, 255, 0])));
+        assert!(paint(&lakes, &mut Image::new(5, 5), Color::from([64, 255, 255])).is_err());
+
+        let settings = Settings { min_area: 2, shore_thickness: 1, ..Settings::default() };
+        let layer = generate(&heightmap, settings);
+        assert_eq!(layer.pixel_at(12, 5), Some(settings.color));
+        assert_eq!(layer.pixel_at(hollow_edge(&lakes[0]).0, hollow_edge(&lakes[0]).1), Some(settings.shore_color));
+        assert_eq!(layer.pixel_at(0, 0).unwrap()[3], 0);
+    }
+
+    #[test]
+    fn depressions_meeting_at_the_surface_are_one_lake() {
+        // Two square hollows walled off from each other by a ridge exactly as high as the channel draining them.
+        let mut heightmap = HeightMap::new(21, 11);
+        for x in 0..21 {
+            for y in 0..11 {
+                let height = match (x, y) {
+                    (5..=9, 3..=7) | (11..=15, 3..=7) => 1000,
+                    (10, 3..=7) | (4, 5) => 1500,
+                    (0..=3, 5) => 1100 + 100 * x as u16,
+                    _ => 2000,
+                };
+                heightmap.set_height_at(x, y, height).unwrap();
+            }
+        }
+
+        let lakes = find(&heightmap, Settings::default());
+        assert_eq!(lakes.len(), 1);
+        assert_eq!(lakes[0].surface, 1500);
+        assert!(lakes[0].contains(7, 5) && lakes[0].contains(10, 5) && lakes[0].contains(13, 5));
+        assert!(!lakes[0].contains(4, 5));
+        assert_eq!(lakes[0].area(), 55);
+        // Water overflows across the dry pixel at the surface at the head of the channel.
+        assert_eq!(lakes[0].spill, (4, 5));
+    }
+
+    #[test]
+    fn separate_pits_on_a_plain_are_separate_lakes() {
+        let mut heightmap = HeightMap::new(9, 9);
+        heightmap.set_data(vec![1000; 81]).unwrap();
+        heightmap.set_height_at(2, 4, 500).unwrap();
+        heightmap.set_height_at(6, 4, 500).unwrap();
+
+        let lakes = find(&heightmap, Settings { min_area: 1, ..Settings::default() });
+        assert_eq!(lakes.len(), 2);
+        for (lake, x) in lakes.iter().zip([2, 6]) {
+            assert_eq!(lake.area(), 1);
+            assert!(lake.contains(x, 4));
+            assert_eq!(lake.surface, 1000);
+            assert_ne!(lake.spill, (x, 4));
+            assert!(lake.spill.0.abs_diff(x) <= 1 && lake.spill.1.abs_diff(4) <= 1);
+        }
+        assert!(find(&heightmap, Settings { min_area: 2, ..Settings::default() }).is_empty());
+    }
+
+    /// The rightmost pixel of a lake on the row through its middle.
+    fn hollow_edge(lake: &Lake) -> (usize, usize) {
+        return lake.pixels().filter(|(_, y)| *y == 5).max().unwrap();
+    }
+}
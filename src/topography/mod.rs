@@ -1,16 +1,58 @@
 use crate::image::*;
 use crate::vector::*;
 
+mod analysis;
+pub mod basins;
 mod contour;
+pub mod erosion;
 mod heightmap;
+pub mod hydrology;
+mod hypsometric;
+pub mod isolines;
+pub mod lakes;
+mod raster;
+pub mod rivers;
 mod shaded;
+mod shadow;
+mod sky_view;
 mod tanaka;
+pub mod terrain;
 
+pub use analysis::Analysis;
+pub use analysis::Settings as AnalysisSettings;
+pub use basins::Basins;
+pub use basins::Settings as BasinSettings;
 pub use contour::Settings as ContourSettings;
+pub use erosion::HydraulicSettings;
+pub use erosion::ThermalSettings;
 pub use heightmap::HeightMap;
+pub use hydrology::Drainage;
+pub use hydrology::FlowMethod;
+pub use hydrology::Settings as HydrologySettings;
+pub use hypsometric::Settings as HypsometricSettings;
+pub use isolines::Isoline;
+pub use isolines::Settings as IsolineSettings;
+pub use lakes::Lake;
+pub use lakes::Settings as LakeSettings;
+pub use raster::Raster;
+pub use rivers::River;
+pub use rivers::Settings as RiverSettings;
 pub use shaded::Settings as ShadedSettings;
+pub use shaded::ShadingMode;
+pub use shadow::Settings as ShadowSettings;
+pub use sky_view::Settings as SkyViewSettings;
 pub use tanaka::Settings as TanakaSettings;
+pub use terrain::Settings as TerrainSettings;
 
+pub use analysis::generate as generate_analysis_layer;
+pub use basins::generate as generate_basin_layer;
 pub use contour::generate as generate_contour_layer;
+pub use hypsometric::generate as generate_hypsometric_layer;
+pub use isolines::generate as generate_isoline_layer;
+pub use lakes::generate as generate_lake_layer;
+pub use rivers::generate as generate_river_layer;
 pub use shaded::generate as generate_shaded_layer;
+pub use shadow::generate as generate_shadow_layer;
+pub use sky_view::generate as generate_sky_view_layer;
 pub use tanaka::generate as generate_tanaka_layer;
+pub use terrain::generate as generate_heightmap;
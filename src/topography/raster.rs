@@ -0,0 +1,79 @@
+use super::*;
+use crate::error::{Error, Result};
+
+/// Grid of floating point values over a heightmap, such as slopes or aspects.
+/// Cells without a value hold NaN.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Raster {
+    width: usize,
+    height: usize,
+    data: Vec<f64>,
+}
+
+impl Raster {
+    /// Creates a new raster filled with one value.
+    pub fn new(width: usize, height: usize, value: f64) -> Self {
+        Raster { width, height, data: vec![value; width * height] }
+    }
+
+    /// Getter for the rasters width.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+    /// Getter for the rasters height.
+    pub fn height(&self) -> usize {
+        self.height
+    }
+    /// Gets a reference to the internal values.
+    pub fn data(&self) -> &Vec<f64> {
+        &self.data
+    }
+
+    /// Gets the value of the cell at the given coordinate.
+    /// Returns None if coordinate is out of bounds.
+    pub fn value_at(&self, x: usize, y: usize) -> Option<f64> {
+        if x < self.width && y < self.height {
+            Some(self.data[x + y * self.width])
+        } else {
+            None
+        }
+    }
+
+    /// Sets the value of the cell at the given position.
+    /// Returns an Err if coordinate is out of bounds.
+    pub fn set_value_at(&mut self, x: usize, y: usize, v: f64) -> Result<()> {
+        if x < self.width && y < self.height {
+            self.data[x + y * self.width] = v;
+            Ok(())
+        } else {
+            Err(Error::OutOfBounds { x: x as isize, y: y as isize, width: self.width, height: self.height })
+        }
+    }
+
+    /// Returns the lowest and highest values, ignoring cells without a value.
+    /// Returns None if no cell has a value.
+    pub fn range(&self) -> Option<(f64, f64)> {
+        return self.data.iter().filter(|v| !v.is_nan()).fold(None, |range, v| match range {
+            Some((min, max)) => Some((v.min(min), v.max(max))),
+            None => Some((*v, *v)),
+        });
+    }
+
+    /// Colors every cell along a ramp, with `min` at its start and `max` at its end.
+    /// Cells without a value are left transparent.
+    pub fn render(&self, ramp: &ColorRamp, min: f64, max: f64) -> Image {
+        let mut result = Image::new(self.width, self.height);
+        let span = if max > min { max - min } else { 1.0 };
+
+        for x in 0..self.width {
+            for y in 0..self.height {
+                let value = self.data[x + y * self.width];
+                if !value.is_nan() {
+                    result.set_pixel_at(x, y, ramp.color_at((value - min) / span)).unwrap();
+                }
+            }
+        }
+
+        return result;
+    }
+}
@@ -0,0 +1,272 @@
+use super::hydrology::{Drainage, FlowMethod};
+use super::*;
+use crate::image::draw::{self, LineCap, LineJoin, Mask};
+use std::collections::VecDeque;
+
+#[derive(Clone, Copy)]
+pub struct Settings {
+    /// How water is shared between the pixels below each pixel.
+    pub method: FlowMethod,
+    /// Heightmap values at or below this are sea, where rivers end.
+    pub sea_level: u16,
+    /// Number of pixels that must drain through a pixel for a river to rise there.
+    pub threshold: f64,
+    /// Lowest Strahler order of the rivers drawn, 1 to draw every river.
+    pub min_order: u32,
+    /// Furthest in pixels a point may be moved from a river when simplifying it.
+    pub simplify_tolerance: f64,
+    /// Number of times the corners of each river are cut to smooth it, 0 to keep the simplified points.
+    pub smoothing: usize,
+    /// Width of rivers in pixels where they rise.
+    pub source_width: f64,
+    /// Width in pixels of the largest river on the map where it reaches the sea.
+    pub mouth_width: f64,
+    /// The color to paint rivers, by default the color of water in the sample biome maps.
+    pub color: Color,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            method: FlowMethod::D8,
+            sea_level: 0,
+            threshold: 100.0,
+            min_order: 1,
+            simplify_tolerance: 0.5,
+            smoothing: 2,
+            source_width: 0.5,
+            mouth_width: 4.0,
+            color: Color::from([64, 255, 255]),
+        }
+    }
+}
+
+/// A stretch of river between where it rises or two rivers join, and where it joins another river or leaves the map.
+#[derive(Clone, Debug, PartialEq)]
+pub struct River {
+    /// Points along the river from upstream to downstream, in pixel coordinates where pixel centers lie on whole
+    /// coordinates. Rivers that flow into another end on the first point of the river below.
+    pub points: Vec<Vector<2>>,
+    /// Number of pixels draining through each point.
+    pub flow: Vec<f64>,
+    /// Strahler order of the river: 1 for rivers with no tributaries, rising by one where two rivers of the same order join.
+    pub order: u32,
+}
+
+impl River {
+    /// Returns the river with the points within `tolerance` of the rest removed. See `draw::simplify`.
+    pub fn simplified(&self, tolerance: f64) -> River {
+        let points = draw::simplify(&self.points, tolerance);
+
+        // The simplified points are a subset of the original points, in the same order.
+        let mut flow = Vec::with_capacity(points.len());
+        let mut original = self.points.iter().zip(&self.flow);
+        for p in &points {
+            if let Some((_, f)) = original.find(|(q, _)| *q == p) {
+                flow.push(*f);
+            }
+        }
+
+        return River { points, flow, order: self.order };
+    }
+
+    /// Returns the river with its corners cut `iterations` times, blending the flow between the points.
+    pub fn smoothed(&self, iterations: usize) -> River {
+        let line: Vec<Vector<3>> = self.points.iter().zip(&self.flow).map(|(p, f)| Vector::from([p[0], p[1], *f])).collect();
+        let line = draw::smooth(&line, iterations);
+
+        return River {
+            points: line.iter().map(|p| Vector::from([p[0], p[1]])).collect(),
+            flow: line.iter().map(|p| p[2]).collect(),
+            order: self.order,
+        };
+    }
+}
+
+/// Traces rivers from every pixel that at least `threshold` pixels drain through, down to the sea or the edge of the map.
+/// Rivers follow the neighbour taking the most water from each pixel, and are split where they join.
+pub fn trace(drainage: &Drainage, threshold: f64) -> Vec<River> {
+    let flow = drainage.accumulation();
+    let (width, height) = (flow.width(), flow.height());
+    let cell = |i: usize| (i % width, i / width);
+
+    // Every pixel below a pixel with enough flow is part of a river, even if it loses water to its other neighbours.
+    let mut next = vec![None; width * height];
+    let mut in_river = vec![false; width * height];
+    for i in 0..width * height {
+        let (x, y) = cell(i);
+        if flow.value_at(x, y).unwrap() < threshold {
+            continue;
+        }
+
+        let mut current = i;
+        while !in_river[current] {
+            in_river[current] = true;
+            let (x, y) = cell(current);
+            next[current] = drainage.downstream(x, y).map(|(x, y)| x + y * width);
+            match next[current] {
+                Some(n) => current = n,
+                None => break,
+            }
+        }
+    }
+
+    let mut inflows = vec![0; width * height];
+    for n in next.iter().flatten() {
+        inflows[*n] += 1;
+    }
+
+    // Orders are worked out from the sources down, once every river flowing into a pixel is known.
+    let mut order = vec![0; width * height];
+    let mut highest_inflow = vec![(0, 0); width * height];
+    let mut waiting = inflows.clone();
+    let mut queue: VecDeque<usize> = (0..width * height).filter(|i| in_river[*i] && inflows[*i] == 0).collect();
+    for i in &queue {
+        order[*i] = 1;
+    }
+
+    while let Some(i) = queue.pop_front() {
+        let n = match next[i] {
+            Some(n) => n,
+            None => continue,
+        };
+
+        let (highest, count) = &mut highest_inflow[n];
+        if order[i] > *highest {
+            *highest = order[i];
+            *count = 1;
+        } else if order[i] == *highest {
+            *count += 1;
+        }
+
+        waiting[n] -= 1;
+        if waiting[n] == 0 {
+            let (highest, count) = highest_inflow[n];
+            order[n] = if count > 1 { highest + 1 } else { highest };
+            queue.push_back(n);
+        }
+    }
+
+    // A river starts at each source and each confluence and runs until the next confluence.
+    let mut rivers = Vec::new();
+    for start in (0..width * height).filter(|i| in_river[*i] && inflows[*i] != 1) {
+        let mut cells = vec![start];
+        let mut current = start;
+        while let Some(n) = next[current] {
+            cells.push(n);
+            if inflows[n] > 1 {
+                break;
+            }
+            current = n;
+        }
+
+        if cells.len() < 2 {
+            continue;
+        }
+
+        rivers.push(River {
+            points: cells.iter().map(|i| Vector::from([(i % width) as f64, (i / width) as f64])).collect(),
+            flow: cells.iter().map(|i| flow.value_at(i % width, i / width).unwrap()).collect(),
+            order: order[start],
+        });
+    }
+
+    return rivers;
+}
+
+/// Draws rivers in the color given in the settings, widening from `source_width` where they rise to `mouth_width`
+/// where the most water flows, growing with the logarithm of their flow.
+pub fn render(rivers: &[River], width: usize, height: usize, settings: Settings) -> Image {
+    let mut result = Image::new(width, height);
+    let most = rivers.iter().flat_map(|r| r.flow.iter().copied()).fold(settings.threshold, f64::max);
+    let range = (most / settings.threshold).ln();
+
+    let mut mask = Mask::new(width, height);
+    for river in rivers.iter().filter(|r| r.order >= settings.min_order) {
+        let widths: Vec<f64> = river
+            .flow
+            .iter()
+            .map(|f| {
+                let t = if range > 0.0 { ((f / settings.threshold).ln() / range).clamp(0.0, 1.0) } else { 0.0 };
+                settings.source_width + (settings.mouth_width - settings.source_width) * t
+            })
+            .collect();
+        mask.tapered_polyline(&river.points, &widths, LineCap::Round, LineJoin::Round);
+    }
+
+    mask.paint(&mut result, settings.color).unwrap();
+    return result;
+}
+
+pub fn generate(heightmap: &HeightMap, settings: Settings) -> Image {
+    let drainage = Drainage::new(heightmap, HydrologySettings { method: settings.method, sea_level: settings.sea_level });
+    let rivers: Vec<River> = trace(&drainage, settings.threshold)
+        .iter()
+        .map(|r| r.simplified(settings.simplify_tolerance).smoothed(settings.smoothing))
+        .collect();
+    return render(&rivers, heightmap.width(), heightmap.height(), settings);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Two valleys running diagonally in from the top corners of a 21x21 heightmap, joining in the middle and
+    /// running down to the bottom edge.
+    fn valleys() -> HeightMap {
+        let segments = [([0.0, 0.0], [10.0, 10.0]), ([20.0, 0.0], [10.0, 10.0]), ([10.0, 10.0], [10.0, 20.0])];
+        let mut heightmap = HeightMap::new(21, 21);
+        for x in 0..21 {
+            for y in 0..21 {
+                let p = Vector::from([x as f64, y as f64]);
+                let distance = segments
+                    .iter()
+                    .map(|(a, b)| {
+                        let (a, b) = (Vector::from(*a), Vector::from(*b));
+                        let t = (Vector::dot(p - a, b - a) / Vector::dot(b - a, b - a)).clamp(0.0, 1.0);
+                        (p - (a + (b - a) * t)).magnitude()
+                    })
+                    .fold(f64::MAX, f64::min);
+                heightmap.set_height_at(x, y, (1000.0 + 10.0 * (20.0 - y as f64) + 30.0 * distance) as u16).unwrap();
+            }
+        }
+        return heightmap;
+    }
+
+    #[test]
+    fn rivers_join_and_grow() {
+        let drainage = Drainage::new(&valleys(), HydrologySettings::default());
+        let rivers = trace(&drainage, 12.0);
+
+        let main: Vec<&River> = rivers.iter().filter(|r| r.order == 2).collect();
+        assert_eq!(main.len(), 1);
+        assert_eq!(main[0].points.first(), Some(&Vector::from([10.0, 10.0])));
+        assert_eq!(main[0].points.last(), Some(&Vector::from([10.0, 20.0])));
+        assert_eq!(rivers.iter().filter(|r| r.order == 1 && r.points.last() == Some(&Vector::from([10.0, 10.0]))).count(), 2);
+
+        for river in &rivers {
+            assert_eq!(river.points.len(), river.flow.len());
+            assert!(river.flow.windows(2).all(|f| f[1] > f[0]));
+        }
+
+        // The straight river below the confluence simplifies to its ends.
+        let simplified = main[0].simplified(0.5);
+        assert_eq!(simplified.points.len(), 2);
+        assert_eq!(simplified.flow, vec![main[0].flow[0], main[0].flow[main[0].flow.len() - 1]]);
+    }
+
+    #[test]
+    fn rivers_widen_downstream() {
+        let settings = Settings { threshold: 12.0, source_width: 1.0, mouth_width: 5.0, smoothing: 0, ..Settings::default() };
+        let layer = generate(&valleys(), settings);
+
+        assert_eq!(layer.pixel_at(10, 19), Some(settings.color));
+        assert!(layer.pixel_at(12, 19).unwrap()[3] > 200);
+        assert_eq!(layer.pixel_at(5, 3).unwrap()[3], 0);
+        assert_eq!(layer.pixel_at(3, 18).unwrap()[3], 0);
+
+        let streams = generate(&valleys(), Settings { min_order: 2, ..settings });
+        assert_eq!(streams.pixel_at(5, 5).unwrap()[3], 0);
+        assert_eq!(layer.pixel_at(5, 5), Some(settings.color));
+    }
+}
@@ -1,20 +1,69 @@
 use super::*;
+use crate::error::Error;
 
+/// How many lights a relief is shaded with.
+#[derive(Copy, Clone, Debug, PartialEq, Default)]
+pub enum ShadingMode {
+    /// A single sun shining from the azimuth.
+    #[default]
+    Single,
+    /// Four suns 45 degrees apart around the azimuth, each weighted by how directly slopes face towards or away
+    /// from it, so that slopes running along the azimuth are not washed out.
+    MultiDirectional,
+}
+
+impl ShadingMode {
+    /// The name used for this mode in configuration files.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ShadingMode::Single => "single",
+            ShadingMode::MultiDirectional => "multidirectional",
+        }
+    }
+}
+
+impl std::str::FromStr for ShadingMode {
+    type Err = Error;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "single" => Ok(ShadingMode::Single),
+            "multidirectional" => Ok(ShadingMode::MultiDirectional),
+            _ => Err(Error::InvalidValue { expected: "shading mode", value: s.to_string() }),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
 pub struct Settings {
-    /// Direction in image space from where light will shine
-    light_dir: Vector<2>,
-    /// Color to use for cells with no shading.
-    background_color: Color,
+    /// Whether the relief is lit by one sun or several.
+    pub mode: ShadingMode,
+    /// Compass bearing in degrees the sun shines from, clockwise from north (the top of the image).
+    pub azimuth: f64,
+    /// Angle in degrees of the sun above the horizon.
+    pub altitude: f64,
+    /// Vertical exaggeration applied to the heights.
+    pub z_factor: f64,
+    /// Width of a cell, in the same units as `vertical_scale`.
+    pub cell_size: f64,
+    /// Elevation of the highest possible heightmap value.
+    pub vertical_scale: f64,
+    /// Color to use for flat cells, which are lit as much as the light allows.
+    pub background_color: Color,
     /// Color to use on cells facing the light.
-    light_color: Color,
+    pub light_color: Color,
     /// Color to use for cells facing away from the light.
-    dark_color: Color,
+    pub dark_color: Color,
 }
 
 impl Default for Settings {
     fn default() -> Self {
         Settings {
-            light_dir: Vector::from([1.0, 1.0]).normalise(),
+            mode: ShadingMode::Single,
+            azimuth: 315.0,
+            altitude: 45.0,
+            z_factor: 1.0,
+            cell_size: 1.0,
+            vertical_scale: 100.0,
             background_color: Color::from([0, 0, 0, 0]),
             light_color: Color::from([255, 255, 255, 255]),
             dark_color: Color::from([0, 0, 0, 255]),
@@ -22,24 +71,57 @@ impl Default for Settings {
     }
 }
 
+/// Unit vector pointing towards the sun, where x is east, y is south and z is up.
+pub(crate) fn sun_direction(azimuth: f64, altitude: f64) -> Vector<3> {
+    let (azimuth, altitude) = (azimuth.to_radians(), altitude.to_radians());
+    return Vector::from([
+        azimuth.sin() * altitude.cos(),
+        -azimuth.cos() * altitude.cos(),
+        altitude.sin(),
+    ]);
+}
+
+/// Illumination of a cell from 0 (facing away from the sun) to 1 (facing straight at it).
+fn illumination(heightmap: &HeightMap, x: usize, y: usize, sun: Vector<3>, settings: &Settings) -> f64 {
+    let normal = heightmap
+        .normal(x, y, settings.cell_size, settings.vertical_scale * settings.z_factor)
+        .unwrap();
+    if settings.mode == ShadingMode::Single {
+        return Vector::dot(normal, sun).max(0.0);
+    }
+
+    // The weights of the suns add up to 2 whichever way the slope faces, flat ground is lit evenly by all of them.
+    let flat = normal[0] == 0.0 && normal[1] == 0.0;
+    let aspect = normal[0].atan2(-normal[1]);
+
+    let mut total = 0.0;
+    for offset in [-90.0, -45.0, 0.0, 45.0] {
+        let azimuth = settings.azimuth + offset;
+        let weight = if flat { 0.5 } else { (aspect - azimuth.to_radians()).cos().powi(2) };
+        let sun = sun_direction(azimuth, settings.altitude);
+        total += weight * Vector::dot(normal, sun).max(0.0);
+    }
+
+    return total / 2.0;
+}
+
 pub fn generate(heightmap: &HeightMap, settings: Settings) -> Image {
-    let mut result =
-        Image::new(heightmap.width(), heightmap.height()).fill(settings.background_color);
+    let mut result = Image::new(heightmap.width(), heightmap.height());
+
+    let sun = sun_direction(settings.azimuth, settings.altitude);
+    // How brightly flat ground is lit, which is drawn with the background color.
+    let flat = sun[2];
 
     for x in 0..heightmap.width() {
         for y in 0..heightmap.height() {
-            // Work out lighting value
-            let norm = heightmap.surface_normal(x, y);
-            let shading = match norm {
-                Some(norm) => Vector::dot(norm, settings.light_dir),
-                None => continue,
-            };
+            let shading = illumination(heightmap, x, y, sun, &settings);
 
             // Interpolate between colors
-            let color = if shading < 0.0 {
-                Color::interpolate(settings.background_color, settings.light_color, -shading)
+            let color = if shading >= flat {
+                let amount = if flat < 1.0 { (shading - flat) / (1.0 - flat) } else { 0.0 };
+                Color::interpolate(settings.background_color, settings.light_color, amount)
             } else {
-                Color::interpolate(settings.background_color, settings.dark_color, shading)
+                Color::interpolate(settings.background_color, settings.dark_color, (flat - shading) / flat)
             };
 
             result.set_pixel_at(x, y, color).unwrap();
@@ -62,4 +144,57 @@ mod test {
             .write_to_file("image/heightmap_shaded.out.png")
             .unwrap();
     }
+
+    #[test]
+    fn steeper_slopes_shade_darker() {
+        // Three slopes of increasing steepness all facing east, away from a sun in the west.
+        let mut heightmap = HeightMap::new(30, 10);
+        for x in 0..30 {
+            for y in 0..10 {
+                let rise = [100, 400, 7000][x / 10];
+                heightmap.set_height_at(x, y, (65000 - rise * (x % 10)) as u16).unwrap();
+            }
+        }
+
+        let settings = Settings { azimuth: 270.0, ..Settings::default() };
+        let shaded = generate(&heightmap, settings);
+        let alpha = |x: usize| shaded.pixel_at(x, 5).unwrap()[3];
+        assert!(alpha(5) < alpha(15) && alpha(15) < alpha(25));
+
+        // Lighting from the east instead lights them up, and exaggerating the heights lights them more.
+        let settings = Settings { azimuth: 90.0, ..Settings::default() };
+        let lit = generate(&heightmap, settings);
+        let exaggerated = generate(&heightmap, Settings { z_factor: 4.0, ..settings });
+        assert!(lit.pixel_at(5, 5).unwrap()[0] > 0);
+        assert!(lit.pixel_at(5, 5).unwrap()[3] < exaggerated.pixel_at(5, 5).unwrap()[3]);
+
+        // Flat ground is left as the background.
+        for mode in [ShadingMode::Single, ShadingMode::MultiDirectional] {
+            let flat = generate(&HeightMap::new(4, 4), Settings { mode, ..Settings::default() });
+            assert_eq!(flat.pixel_at(1, 1), Some(Color::from([0, 0, 0, 0])));
+        }
+    }
+
+    #[test]
+    fn multidirectional_lights_slopes_along_the_sun() {
+        // A ridge running north west to south east, along the direction of the default sun.
+        let mut heightmap = HeightMap::new(40, 40);
+        for x in 0..40 {
+            for y in 0..40 {
+                let distance = (x as f64 - y as f64).abs();
+                heightmap.set_height_at(x, y, (60000.0 - 2000.0 * distance) as u16).unwrap();
+            }
+        }
+
+        let single = generate(&heightmap, Settings::default());
+        let multi = generate(&heightmap, Settings { mode: ShadingMode::MultiDirectional, ..Settings::default() });
+
+        // Both flanks face across the light, so a single sun leaves them alike while several suns pick them apart.
+        let contrast = |image: &Image| (image.pixel_at(25, 15).unwrap()[3] as i32 - image.pixel_at(15, 25).unwrap()[3] as i32).abs();
+        assert!(contrast(&single) <= 1);
+        assert!(contrast(&multi) > 20);
+
+        assert_eq!("MultiDirectional".parse::<ShadingMode>().unwrap(), ShadingMode::MultiDirectional);
+        assert!("oblique".parse::<ShadingMode>().is_err());
+    }
 }
@@ -0,0 +1,102 @@
+use super::*;
+use super::heightmap::Elevations;
+
+#[derive(Clone, Copy)]
+pub struct Settings {
+    /// Compass bearing in degrees the sun shines from, clockwise from north (the top of the image).
+    pub azimuth: f64,
+    /// Angle in degrees of the sun above the horizon.
+    pub altitude: f64,
+    /// Width of a cell, in the same units as `vertical_scale`.
+    pub cell_size: f64,
+    /// Elevation of the highest possible heightmap value.
+    pub vertical_scale: f64,
+    /// Color drawn over cells hidden from the sun.
+    pub shadow_color: Color,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            azimuth: 315.0,
+            altitude: 30.0,
+            cell_size: 1.0,
+            vertical_scale: 100.0,
+            shadow_color: Color::from([0, 0, 32, 96]),
+        }
+    }
+}
+
+/// Marches from the centre of a cell towards the sun, returning whether any terrain on the way rises above the ray.
+fn in_shadow(elevations: &Elevations, x: usize, y: usize, direction: Vector<2>, rise: f64, highest: f64) -> bool {
+    let start = elevations.data[x + y * elevations.width];
+
+    let mut step = 1.0;
+    loop {
+        let ray = start + step * rise;
+        if ray >= highest {
+            return false;
+        }
+
+        let terrain = match elevations.sample(x as f64 + step * direction[0], y as f64 + step * direction[1]) {
+            Some(terrain) => terrain,
+            None => return false,
+        };
+
+        if terrain > ray {
+            return true;
+        }
+
+        step += 1.0;
+    }
+}
+
+/// Shadows cast by the terrain, drawn as a translucent layer to composite over relief shading.
+pub fn generate(heightmap: &HeightMap, settings: Settings) -> Image {
+    let mut result = Image::new(heightmap.width(), heightmap.height());
+    let elevations = Elevations::new(heightmap, settings.vertical_scale);
+    let highest = elevations.data.iter().copied().fold(0.0, f64::max);
+
+    let azimuth = settings.azimuth.to_radians();
+    let direction = Vector::from([azimuth.sin(), -azimuth.cos()]);
+    // How far the ray climbs towards the sun with every cell it crosses.
+    let rise = settings.altitude.to_radians().tan() * settings.cell_size;
+
+    for x in 0..heightmap.width() {
+        for y in 0..heightmap.height() {
+            if in_shadow(&elevations, x, y, direction, rise, highest) {
+                result.set_pixel_at(x, y, settings.shadow_color).unwrap();
+            }
+        }
+    }
+
+    return result;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn tall_peaks_cast_long_shadows() {
+        // A single tall column in the middle of flat ground.
+        let mut heightmap = HeightMap::new(60, 60);
+        for x in 28..32 {
+            for y in 28..32 {
+                heightmap.set_height_at(x, y, u16::MAX).unwrap();
+            }
+        }
+
+        // With the sun in the west at 45 degrees, the 10 high column shades about 10 cells to its east.
+        let settings = Settings { azimuth: 270.0, altitude: 45.0, vertical_scale: 10.0, ..Settings::default() };
+        let shadow = generate(&heightmap, settings);
+        let shaded = |x: usize, y: usize| shadow.pixel_at(x, y).unwrap() == settings.shadow_color;
+        assert!(shaded(33, 30) && shaded(40, 30));
+        assert!(!shaded(43, 30) && !shaded(26, 30) && !shaded(36, 20) && !shaded(30, 30));
+
+        // A lower sun stretches the shadow out.
+        let low = generate(&heightmap, Settings { altitude: 20.0, ..settings });
+        assert_eq!(low.pixel_at(50, 30).unwrap(), settings.shadow_color);
+        assert_eq!(low.pixel_at(20, 30).unwrap(), Color::from([0, 0, 0, 0]));
+    }
+}
@@ -0,0 +1,106 @@
+use super::*;
+use super::heightmap::Elevations;
+
+#[derive(Clone)]
+pub struct Settings {
+    /// Number of directions, evenly spread around each cell, in which the horizon is searched for.
+    pub directions: usize,
+    /// Distance in cells the horizon is searched for in each direction.
+    pub radius: f64,
+    /// Width of a cell, in the same units as `vertical_scale`.
+    pub cell_size: f64,
+    /// Elevation of the highest possible heightmap value.
+    pub vertical_scale: f64,
+    /// Colors given to cells from fully enclosed (0) to seeing the whole sky (1).
+    pub ramp: ColorRamp,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            directions: 16,
+            radius: 20.0,
+            cell_size: 1.0,
+            vertical_scale: 100.0,
+            ramp: ColorRamp::greyscale(),
+        }
+    }
+}
+
+/// Returns the fraction of the sky hemisphere seen from a cell, from 0 to 1.
+fn sky_view_factor(elevations: &Elevations, x: usize, y: usize, settings: &Settings) -> f64 {
+    let start = elevations.data[x + y * elevations.width];
+
+    let mut hidden = 0.0;
+    for i in 0..settings.directions {
+        let angle = i as f64 / settings.directions as f64 * std::f64::consts::TAU;
+        let (dx, dy) = (angle.sin(), -angle.cos());
+
+        // Steepest rise seen in this direction, or level if nothing rises above the cell.
+        let mut horizon: f64 = 0.0;
+        let mut step = 1.0;
+        while step <= settings.radius {
+            match elevations.sample(x as f64 + step * dx, y as f64 + step * dy) {
+                Some(z) => horizon = horizon.max((z - start) / (step * settings.cell_size)),
+                None => break,
+            }
+            step += 1.0;
+        }
+
+        hidden += horizon.atan().sin();
+    }
+
+    return 1.0 - hidden / settings.directions as f64;
+}
+
+/// Darkens cells by how little of the sky they can see, independently of any light direction.
+pub fn generate(heightmap: &HeightMap, settings: Settings) -> Image {
+    let mut result = Image::new(heightmap.width(), heightmap.height());
+    if settings.directions == 0 {
+        return result.fill(settings.ramp.color_at(1.0));
+    }
+
+    let elevations = Elevations::new(heightmap, settings.vertical_scale);
+
+    for x in 0..heightmap.width() {
+        for y in 0..heightmap.height() {
+            let factor = sky_view_factor(&elevations, x, y, &settings);
+            result.set_pixel_at(x, y, settings.ramp.color_at(factor)).unwrap();
+        }
+    }
+
+    return result;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn valleys_see_less_sky() {
+        // A narrow valley cut through a plateau, beside an open plain.
+        let mut heightmap = HeightMap::new(40, 30);
+        for x in 0..20 {
+            for y in 0..30 {
+                if !(8..12).contains(&x) {
+                    heightmap.set_height_at(x, y, 30000).unwrap();
+                }
+            }
+        }
+
+        let settings = Settings { radius: 10.0, ..Settings::default() };
+        let sky = generate(&heightmap, settings.clone());
+        let brightness = |x: usize| sky.pixel_at(x, 15).unwrap()[0];
+
+        // The plain and the top of the plateau see the whole sky, the valley floor only a slit of it.
+        assert_eq!(brightness(35), 255);
+        assert_eq!(brightness(4), 255);
+        assert!(brightness(10) < 64);
+        // Next to the cliff the plain loses part of the sky, less than the valley does.
+        assert!(brightness(21) > brightness(10) && brightness(21) < 255);
+
+        let ramp: ColorRamp = "#402000,#ffe0a0".parse().unwrap();
+        let tinted = generate(&heightmap, Settings { ramp: ramp.clone(), ..settings });
+        assert_eq!(tinted.pixel_at(35, 15), Some(ramp.color_at(1.0)));
+    }
+}
@@ -1,18 +1,19 @@
 use super::*;
 
+#[derive(Clone, Copy)]
 pub struct Settings {
     /// Number of contour lines between 0 and u16::MAX.
-    line_divisions: u16,
+    pub line_divisions: u16,
     /// Color of points facing the light dir.
-    light_color: Color,
+    pub light_color: Color,
     /// Color of points facing away from the light dir.
-    dark_color: Color,
+    pub dark_color: Color,
     /// Color to point cells with no contour line on them.
-    background_color: Color,
+    pub background_color: Color,
     /// Direction that light will shine from in image space.
-    light_dir: Vector<2>,
-
-    cleaning_factor: usize,
+    pub light_dir: Vector<2>,
+    /// Minimum number of neighbouring line pixels a contour pixel needs to survive cleaning.
+    pub cleaning_factor: usize,
 }
 
 impl Default for Settings {
@@ -0,0 +1,390 @@
+use super::*;
+use crate::error::Error;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+/// How the heights of a generated heightmap are made.
+#[derive(Copy, Clone, Debug, PartialEq, Default)]
+pub enum Algorithm {
+    /// Fractal Brownian motion: octaves of Perlin gradient noise added together.
+    #[default]
+    Perlin,
+    /// Fractal Brownian motion of simplex noise, which has fewer grid aligned artifacts than Perlin noise.
+    Simplex,
+    /// Ridged multifractal Perlin noise, folded into sharp ridges like mountain ranges.
+    Ridged,
+    /// Midpoint displacement on a square grid, halving the grid and the displacement for every step.
+    DiamondSquare,
+}
+
+impl Algorithm {
+    /// The name used for this algorithm in configuration files.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Algorithm::Perlin => "perlin",
+            Algorithm::Simplex => "simplex",
+            Algorithm::Ridged => "ridged",
+            Algorithm::DiamondSquare => "diamond_square",
+        }
+    }
+}
+
+impl std::str::FromStr for Algorithm {
+    type Err = Error;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "perlin" => Ok(Algorithm::Perlin),
+            "simplex" => Ok(Algorithm::Simplex),
+            "ridged" => Ok(Algorithm::Ridged),
+            "diamond_square" | "diamond-square" => Ok(Algorithm::DiamondSquare),
+            _ => Err(Error::InvalidValue { expected: "terrain algorithm", value: s.to_string() }),
+        }
+    }
+}
+
+/// How land is lowered towards the edges of a generated heightmap, to leave sea around it.
+#[derive(Copy, Clone, Debug, PartialEq, Default)]
+pub enum Falloff {
+    /// Heights are left as they are generated.
+    #[default]
+    None,
+    /// Land falls away in every direction from the middle of the map, leaving a single island.
+    Island,
+    /// Land falls away only close to the edges of the map, leaving a continent with a ragged coast.
+    Continent,
+}
+
+impl Falloff {
+    /// The name used for this falloff in configuration files.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Falloff::None => "none",
+            Falloff::Island => "island",
+            Falloff::Continent => "continent",
+        }
+    }
+}
+
+impl std::str::FromStr for Falloff {
+    type Err = Error;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "none" => Ok(Falloff::None),
+            "island" => Ok(Falloff::Island),
+            "continent" => Ok(Falloff::Continent),
+            _ => Err(Error::InvalidValue { expected: "falloff", value: s.to_string() }),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Settings {
+    /// How the heights are made.
+    pub algorithm: Algorithm,
+    /// The same seed always generates the same heightmap.
+    pub seed: u64,
+    /// Size in pixels of the largest features, the first octave of noise. Unused by diamond-square.
+    pub scale: f64,
+    /// Number of layers of noise added together, each with finer detail than the last. Unused by diamond-square.
+    pub octaves: usize,
+    /// How much finer each octave is than the last. Unused by diamond-square.
+    pub lacunarity: f64,
+    /// How much weaker each octave or diamond-square step is than the last, from 0 to 1.
+    /// Higher values give rougher terrain.
+    pub persistence: f64,
+    /// How land is lowered towards the edges of the map.
+    pub falloff: Falloff,
+    /// Fraction of the way from the middle of the map to its edge that the falloff starts, from 0 to 1.
+    pub falloff_start: f64,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            algorithm: Algorithm::Perlin,
+            seed: 0,
+            scale: 256.0,
+            octaves: 6,
+            lacunarity: 2.0,
+            persistence: 0.5,
+            falloff: Falloff::None,
+            falloff_start: 0.5,
+        }
+    }
+}
+
+/// Gradient noise over the plane, from a random permutation of the lattice points.
+struct Noise {
+    permutation: Vec<usize>,
+}
+
+impl Noise {
+    fn new(rng: &mut ChaCha8Rng) -> Self {
+        let mut permutation: Vec<usize> = (0..256).collect();
+        permutation.shuffle(rng);
+        permutation.extend_from_within(..);
+        Noise { permutation }
+    }
+
+    /// A pseudo random gradient for a lattice point, one of eight directions around the circle.
+    fn gradient(&self, x: isize, y: isize, dx: f64, dy: f64) -> f64 {
+        let hash = self.permutation[self.permutation[(x & 255) as usize] + (y & 255) as usize];
+        let d = std::f64::consts::FRAC_1_SQRT_2;
+        let (gx, gy) = match hash & 7 {
+            0 => (1.0, 0.0),
+            1 => (-1.0, 0.0),
+            2 => (0.0, 1.0),
+            3 => (0.0, -1.0),
+            4 => (d, d),
+            5 => (-d, d),
+            6 => (d, -d),
+            _ => (-d, -d),
+        };
+        return gx * dx + gy * dy;
+    }
+
+    /// Perlin noise at a point, roughly between -1 and 1.
+    fn perlin(&self, x: f64, y: f64) -> f64 {
+        let (x0, y0) = (x.floor(), y.floor());
+        let (dx, dy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as isize, y0 as isize);
+        let fade = |t: f64| t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+        let (u, v) = (fade(dx), fade(dy));
+
+        let top = lerp(self.gradient(x0, y0, dx, dy), self.gradient(x0 + 1, y0, dx - 1.0, dy), u);
+        let bottom = lerp(self.gradient(x0, y0 + 1, dx, dy - 1.0), self.gradient(x0 + 1, y0 + 1, dx - 1.0, dy - 1.0), u);
+        return lerp(top, bottom, v) * std::f64::consts::SQRT_2;
+    }
+
+    /// Simplex noise at a point, roughly between -1 and 1.
+    fn simplex(&self, x: f64, y: f64) -> f64 {
+        let skew = (3.0f64.sqrt() - 1.0) / 2.0;
+        let unskew = (3.0 - 3.0f64.sqrt()) / 6.0;
+
+        // The corner of the skewed square grid the point lies in, and which of its two triangles.
+        let s = (x + y) * skew;
+        let (i, j) = ((x + s).floor(), (y + s).floor());
+        let t = (i + j) * unskew;
+        let (x0, y0) = (x - (i - t), y - (j - t));
+        let (i1, j1) = if x0 > y0 { (1, 0) } else { (0, 1) };
+        let (i, j) = (i as isize, j as isize);
+
+        let corners = [
+            (i, j, x0, y0),
+            (i + i1, j + j1, x0 - i1 as f64 + unskew, y0 - j1 as f64 + unskew),
+            (i + 1, j + 1, x0 - 1.0 + 2.0 * unskew, y0 - 1.0 + 2.0 * unskew),
+        ];
+
+        let total: f64 = corners
+            .iter()
+            .map(|(ci, cj, dx, dy)| {
+                let falloff = 0.5 - dx * dx - dy * dy;
+                if falloff <= 0.0 {
+                    0.0
+                } else {
+                    falloff.powi(4) * self.gradient(*ci, *cj, *dx, *dy)
+                }
+            })
+            .sum();
+        return total * 70.0;
+    }
+}
+
+fn lerp(a: f64, b: f64, t: f64) -> f64 {
+    a + (b - a) * t
+}
+
+/// Heights made by adding octaves of noise together, each finer and weaker than the last.
+fn fractal(width: usize, height: usize, settings: &Settings, noise: impl Fn(f64, f64) -> f64) -> Vec<f64> {
+    let mut heights = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            let (mut frequency, mut amplitude, mut total) = (1.0 / settings.scale, 1.0, 0.0);
+            for octave in 0..settings.octaves {
+                // Each octave is shifted so the lattice points of the octaves do not line up.
+                let offset = octave as f64 * 17.31;
+                total += amplitude * noise(x as f64 * frequency + offset, y as f64 * frequency + offset);
+                frequency *= settings.lacunarity;
+                amplitude *= settings.persistence;
+            }
+            heights.push(total);
+        }
+    }
+    return heights;
+}
+
+/// Heights made by Musgrave's ridged multifractal, where each octave is folded into ridges and is strongest on the
+/// ridges of the octaves before it.
+fn ridged(width: usize, height: usize, settings: &Settings, noise: &Noise) -> Vec<f64> {
+    let mut heights = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            let (mut frequency, mut amplitude, mut weight, mut total) = (1.0 / settings.scale, 1.0, 1.0, 0.0);
+            for octave in 0..settings.octaves {
+                let offset = octave as f64 * 17.31;
+                let ridge = 1.0 - noise.perlin(x as f64 * frequency + offset, y as f64 * frequency + offset).abs();
+                let signal = ridge * ridge * weight;
+                weight = (signal * 2.0).clamp(0.0, 1.0);
+                total += signal * amplitude;
+                frequency *= settings.lacunarity;
+                amplitude *= settings.persistence;
+            }
+            heights.push(total);
+        }
+    }
+    return heights;
+}
+
+/// Heights made by the diamond-square algorithm on the smallest square grid of 2^n + 1 points covering the map.
+fn diamond_square(width: usize, height: usize, settings: &Settings, rng: &mut ChaCha8Rng) -> Vec<f64> {
+    let size = width.max(height).max(2).next_power_of_two() + 1;
+    let mut grid = vec![0.0; size * size];
+    for (x, y) in [(0, 0), (size - 1, 0), (0, size - 1), (size - 1, size - 1)] {
+        grid[x + y * size] = rng.gen_range(-1.0..1.0);
+    }
+
+    let mut step = size - 1;
+    let mut displacement = 1.0;
+    while step > 1 {
+        let half = step / 2;
+
+        // Diamond step: the middle of every square is the average of its corners.
+        for y in (half..size).step_by(step) {
+            for x in (half..size).step_by(step) {
+                let corners = [(x - half, y - half), (x + half, y - half), (x - half, y + half), (x + half, y + half)];
+                let average = corners.iter().map(|(cx, cy)| grid[cx + cy * size]).sum::<f64>() / 4.0;
+                grid[x + y * size] = average + rng.gen_range(-displacement..displacement);
+            }
+        }
+
+        // Square step: the middle of every edge is the average of the points around it.
+        for y in (0..size).step_by(half) {
+            let start = if (y / half).is_multiple_of(2) { half } else { 0 };
+            for x in (start..size).step_by(step) {
+                let around = [(x.checked_sub(half), Some(y)), (Some(x + half), Some(y)), (Some(x), y.checked_sub(half)), (Some(x), Some(y + half))];
+                let inside: Vec<f64> = around
+                    .iter()
+                    .filter_map(|p| match *p {
+                        (Some(ax), Some(ay)) if ax < size && ay < size => Some(grid[ax + ay * size]),
+                        _ => None,
+                    })
+                    .collect();
+                let average = inside.iter().sum::<f64>() / inside.len() as f64;
+                grid[x + y * size] = average + rng.gen_range(-displacement..displacement);
+            }
+        }
+
+        step = half;
+        displacement *= settings.persistence.max(1e-9);
+    }
+
+    return (0..width * height).map(|i| grid[i % width + (i / width) * size]).collect();
+}
+
+/// How much of the height at a point is kept by the falloff, from 1 inside `falloff_start` to 0 at the edge of the map.
+fn falloff(x: usize, y: usize, width: usize, height: usize, settings: &Settings) -> f64 {
+    // Distance from the middle of the map, scaled so the edges are 1 away.
+    let dx = (2.0 * x as f64 / (width.max(2) - 1) as f64 - 1.0).abs();
+    let dy = (2.0 * y as f64 / (height.max(2) - 1) as f64 - 1.0).abs();
+    let distance = match settings.falloff {
+        Falloff::None => return 1.0,
+        Falloff::Island => dx.hypot(dy),
+        Falloff::Continent => dx.max(dy),
+    };
+
+    let start = settings.falloff_start.clamp(0.0, 0.999);
+    let t = ((distance - start) / (1.0 - start)).clamp(0.0, 1.0);
+    return 1.0 - t * t * (3.0 - 2.0 * t);
+}
+
+/// Generates a heightmap spanning the full range of heights, the same every time for the same settings.
+/// With a falloff, the land is lowered away from the middle of the map until the edges are at height 0.
+pub fn generate(width: usize, height: usize, settings: Settings) -> HeightMap {
+    let mut rng = ChaCha8Rng::seed_from_u64(settings.seed);
+    let heights = match settings.algorithm {
+        Algorithm::Perlin => {
+            let noise = Noise::new(&mut rng);
+            fractal(width, height, &settings, |x, y| noise.perlin(x, y))
+        }
+        Algorithm::Simplex => {
+            let noise = Noise::new(&mut rng);
+            fractal(width, height, &settings, |x, y| noise.simplex(x, y))
+        }
+        Algorithm::Ridged => ridged(width, height, &settings, &Noise::new(&mut rng)),
+        Algorithm::DiamondSquare => diamond_square(width, height, &settings, &mut rng),
+    };
+
+    let (min, max) = heights.iter().fold((f64::MAX, f64::MIN), |(min, max), h| (min.min(*h), max.max(*h)));
+    let range = if max > min { max - min } else { 1.0 };
+
+    // The falloff is subtracted rather than multiplied, so the coast follows the terrain instead of a circle.
+    let heights: Vec<f64> = heights
+        .iter()
+        .enumerate()
+        .map(|(i, h)| ((h - min) / range - (1.0 - falloff(i % width, i / width, width, height, &settings))).max(0.0))
+        .collect();
+    let highest = heights.iter().cloned().fold(0.0, f64::max);
+    let highest = if highest > 0.0 { highest } else { 1.0 };
+
+    let mut heightmap = HeightMap::new(width, height);
+    heightmap.set_data(heights.iter().map(|h| (h / highest * u16::MAX as f64).round() as u16).collect()).unwrap();
+    return heightmap;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn terrain_is_repeatable() {
+        for algorithm in [Algorithm::Perlin, Algorithm::Simplex, Algorithm::Ridged, Algorithm::DiamondSquare] {
+            let settings = Settings { algorithm, seed: 7, scale: 32.0, ..Settings::default() };
+            let terrain = generate(60, 40, settings);
+
+            assert_eq!(terrain.data(), generate(60, 40, settings).data(), "{} is not repeatable", algorithm.name());
+            assert_ne!(terrain.data(), generate(60, 40, Settings { seed: 8, ..settings }).data());
+            assert_eq!(terrain.data().iter().min(), Some(&0));
+            assert_eq!(terrain.data().iter().max(), Some(&u16::MAX));
+
+            // Neighbouring pixels are close in height, rather than random.
+            let steps: f64 = terrain.data().windows(2).map(|w| (w[0] as f64 - w[1] as f64).abs()).sum();
+            assert!(steps / (60.0 * 40.0) < 0.1 * u16::MAX as f64, "{} is too rough", algorithm.name());
+        }
+
+        assert_eq!("Diamond-Square".parse::<Algorithm>().unwrap(), Algorithm::DiamondSquare);
+        assert!("voronoi".parse::<Algorithm>().is_err());
+    }
+
+    #[test]
+    fn seeds_give_the_same_terrain_everywhere() {
+        // Pinned so that a change of generator, or of how it is used, is noticed rather than quietly changing worlds.
+        let pinned = [
+            (Algorithm::Perlin, [48867, 20427, 30483, 26758]),
+            (Algorithm::Simplex, [24608, 43676, 55348, 49841]),
+            (Algorithm::Ridged, [49424, 49282, 41952, 45784]),
+            (Algorithm::DiamondSquare, [14382, 19836, 60249, 34225]),
+        ];
+
+        for (algorithm, heights) in pinned {
+            let terrain = generate(32, 32, Settings { algorithm, seed: 42, scale: 16.0, ..Settings::default() });
+            let found = [(3, 5), (16, 16), (29, 2), (10, 27)].map(|(x, y)| terrain.height_at(x, y).unwrap());
+            assert_eq!(found, heights, "{}", algorithm.name());
+        }
+    }
+
+    #[test]
+    fn falloff_leaves_sea_at_the_edges() {
+        let settings = Settings { scale: 32.0, falloff: Falloff::Island, ..Settings::default() };
+        let island = generate(50, 50, settings);
+
+        assert!((0..50).all(|i| island.height_at(i, 0) == Some(0) && island.height_at(0, i) == Some(0)));
+        assert!(island.height_at(25, 25).unwrap() > 0);
+
+        // Continents reach further into the corners than islands.
+        let continent = generate(50, 50, Settings { falloff: Falloff::Continent, ..settings });
+        let land = |h: &HeightMap| h.data().iter().filter(|v| **v > 0).count();
+        assert!(land(&continent) > land(&island));
+        assert_eq!(continent.height_at(49, 25), Some(0));
+    }
+}
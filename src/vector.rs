@@ -4,6 +4,12 @@ pub struct Vector<const S: usize> {
     data: [f64; S],
 }
 
+impl<const S: usize> Default for Vector<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<const S: usize> Vector<S> {
     /// New vector with all 0's.
     pub fn new() -> Self {
@@ -17,10 +23,10 @@ impl<const S: usize> Vector<S> {
 
     /// Returns the vector with the same direction as self, and a magnitude of 1.
     pub fn normalise(&self) -> Self {
-        let mut normalised = self.clone();
+        let mut normalised = *self;
         let length = self.magnitude();
         for v in &mut normalised.data {
-            *v = *v / length;
+            *v /= length;
         }
         normalised
     }
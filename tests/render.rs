@@ -0,0 +1,20 @@
+use std::path::Path;
+use std::process::Command;
+
+#[test]
+fn projects_render_from_another_directory() {
+    // Every path in the sample project, including the glyphs of its region configuration, is relative to its own file.
+    let project = Path::new(env!("CARGO_MANIFEST_DIR")).join("samples/project.json");
+    let output = std::env::temp_dir().join("eldorado_project.out.png");
+    let status = Command::new(env!("CARGO_BIN_EXE_eldorado"))
+        .args(["render", "--project"])
+        .arg(&project)
+        .arg("--output")
+        .arg(&output)
+        .current_dir(std::env::temp_dir())
+        .status()
+        .unwrap();
+
+    assert!(status.success());
+    assert!(output.exists());
+}